@@ -0,0 +1,46 @@
+//! The language server of the Lisaa lang, for the editors.
+//!
+//! It speaks the Language Server Protocol on the standard input and output.
+extern crate clap;
+extern crate lisaa_lang;
+
+use clap::{App, Arg};
+use lisaa_lang::lsp;
+use std::env;
+use std::io;
+use std::process::exit;
+
+fn main() {
+    let matches = App::new("lisaa-lsp")
+        .version("0.0.0")
+        .author("Pierre Bertin-Johannet")
+        .about("Language server for the Lisaa lang")
+        .arg(
+            Arg::with_name("PATH")
+                .long("path")
+                .short("I")
+                .value_name("DIR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("search the imports in the given directory, before the ones of LISAA_PATH"),
+        )
+        .get_matches();
+    let mut search_path = matches
+        .values_of("PATH")
+        .into_iter()
+        .flat_map(|dirs| dirs)
+        .map(|dir| dir.to_string())
+        .collect::<Vec<String>>();
+    if let Some(dirs) = env::var_os("LISAA_PATH") {
+        for dir in env::split_paths(&dirs) {
+            search_path.push(dir.to_string_lossy().into_owned());
+        }
+    }
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(e) = lsp::serve(&mut stdin.lock(), &mut stdout.lock(), search_path) {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
@@ -1,7 +1,9 @@
 //! Module for emiting bytecode readable by the vm.
 
+use diagnostic::{Diagnostic, Span};
 use expression::{
-    BinaryExpr, Deref, Expr, ExprEnum, FunctionCall, LiteralExpr, Operator, UnaryExpr,
+    BinaryExpr, Callee, Closure, Deref, Expr, ExprEnum, FunctionCall, LiteralExpr, Operator,
+    UnaryExpr,
 };
 use native::{get_native_funcs, get_native_types};
 use statement::{
@@ -9,8 +11,8 @@ use statement::{
     WhileStatement,
 };
 use std::collections::HashMap;
-use types::LisaaType;
-use vm::{IS_PTR_SLICE_BIT, IS_SLICE_BIT, OP, STRING_TYPE};
+use types::{LisaaType, TypedVar};
+use vm::{DebugInfo, LineInfo, Symbol, IS_PTR_SLICE_BIT, IS_SLICE_BIT, OP, STRING_TYPE};
 
 /// These are unlinked instructions.
 /// the goto (symbol) will be replaced by goto(usize) when the program is completed.
@@ -88,6 +90,14 @@ pub struct Compiler {
     scopes: Vec<Scope>,
     /// associates the labels with the positions in the code.
     labels: HashMap<String, Option<usize>>,
+    /// The functions compiled, in the order of the code.
+    symbols: Vec<Symbol>,
+    /// The source lines of the code, in the order of the code.
+    lines: Vec<LineInfo>,
+    /// Checks that the slice indexes are in bounds, on by default.
+    bounds_checks: bool,
+    /// The closures found in the code with their labels, compiled after the functions.
+    closures: Vec<(String, Closure)>,
 }
 
 impl Compiler {
@@ -99,8 +109,59 @@ impl Compiler {
             functions: HashMap::new(),
             classes: HashMap::new(),
             labels: HashMap::new(),
+            symbols: vec![],
+            lines: vec![],
+            bounds_checks: true,
+            closures: vec![],
         }
     }
+
+    /// Enables or disables the bounds checks of slice indexing.
+    /// Without them an index outside of a slice reads or writes the heap around it.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
+
+    /// Returns the symbol table and the source lines, filled by compile.
+    pub fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            symbols: self.symbols.clone(),
+            lines: self.lines.clone(),
+        }
+    }
+
+    /// Returns the positions of the instructions pushing a label, filled by compile.
+    /// Once linked they push numbers like the others, the optimizations must move them too.
+    pub fn adresses(&self) -> Vec<usize> {
+        self.code
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| match e {
+                &UnlinkedInstruction::Push(_) => Some(i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Tells that the next instructions come from the given place in the code.
+    /// Spans without position (created by the compiler) are ignored.
+    pub fn mark_line(&mut self, span: &Span) {
+        if span.line == 0 {
+            return;
+        }
+        // the functions compiled before the current one end where it starts.
+        let function_start = self.symbols.last().map_or(0, |s| s.end);
+        if let Some(last) = self.lines.last() {
+            if last.line == span.line && last.file == span.file && last.start >= function_start {
+                return;
+            }
+        }
+        self.lines.push(LineInfo {
+            start: self.code.len(),
+            file: span.file.clone(),
+            line: span.line,
+        });
+    }
     /// Add a lib to the program.
     pub fn add_lib(&mut self, lib: &str) {
         for f in get_native_funcs(lib) {
@@ -165,31 +226,82 @@ impl Compiler {
     }
 
     /// Compiles the functions and emit a "goto main" instruction at the beggining.
-    pub fn compile(&mut self, program: &Program) -> Result<Vec<OP>, String> {
+    /// Programs embedded in a host may have no main, their code then starts with End.
+    pub fn compile(&mut self, program: &Program) -> Result<Vec<OP>, Diagnostic> {
+        self.program(program);
+        self.link()
+    }
+
+    /// Compiles the program followed by a line of the repl.
+    /// The compiler is kept for the whole session : the code of the previous lines stays
+    /// and the functions already compiled are not compiled again,
+    /// so the function values created by the previous lines still point to their code.
+    /// Returns the code and the position of the line.
+    pub fn compile_line(
+        &mut self,
+        program: &Program,
+        line: &FunctionDecl,
+    ) -> Result<(Vec<OP>, usize), Diagnostic> {
+        self.program(program);
+        let start = self.code.len();
+        self.line_body(line);
+        self.link().map(|code| (code, start))
+    }
+
+    /// Compiles the call to main and the functions of the program.
+    fn program(&mut self, program: &Program) {
         self.functions = program.functions().clone();
         self.classes = program.classes().clone();
         self.add_lib("base");
 
-        self.function_call(&FunctionCall::function("main".to_string(), vec![]));
-        self.emit(OP::End);
-        for f in program.functions().iter() {
-            if !f.1.inline {
-                self.function(f.1);
+        if self.code.is_empty() {
+            let main_sig =
+                FunctionSig::new(vec![], vec![], LisaaType::Void, "main".to_string(), None);
+            if program.functions().contains_key(&main_sig) {
+                let mut main = FunctionCall::function("main".to_string(), vec![]);
+                main.set_signature(main_sig);
+                self.function_call(&main, &Span::default());
             }
+            self.emit(OP::End);
+        }
+        // sorted, so the same program always gives the same code
+        let mut functions = program
+            .functions()
+            .values()
+            .filter(|f| !f.inline && !self.labels.contains_key(&f.signature().to_string()))
+            .collect::<Vec<&FunctionDecl>>();
+        functions.sort_by_key(|f| f.signature().to_string());
+        for f in functions {
+            self.function(f);
         }
-        Ok(self
-            .code
+    }
+
+    /// Compiles the closures found so far and replaces the labels by their positions.
+    fn link(&mut self) -> Result<Vec<OP>, Diagnostic> {
+        while let Some((label, closure)) = self.closures.pop() {
+            self.closure_body(label, &closure);
+        }
+        self.code
             .iter()
             .map(|e| match e {
-                &UnlinkedInstruction::Op(ref o) => o.clone(),
-                &UnlinkedInstruction::Goto(ref label) => {
-                    OP::Goto(self.labels.get(label).unwrap().unwrap())
-                }
+                &UnlinkedInstruction::Op(ref o) => Ok(o.clone()),
+                &UnlinkedInstruction::Goto(ref label) => Ok(OP::Goto(self.label_position(label)?)),
                 &UnlinkedInstruction::Push(ref label) => {
-                    OP::PushNum(self.labels.get(label).unwrap().unwrap() as f64)
+                    Ok(OP::PushNum(self.label_position(label)? as f64))
                 }
             })
-            .collect())
+            .collect()
+    }
+
+    /// Returns the position in the code of the given label.
+    fn label_position(&self, label: &String) -> Result<usize, Diagnostic> {
+        match self.labels.get(label) {
+            Some(&Some(position)) => Ok(position),
+            _ => Err(Diagnostic::new(format!(
+                "could not link the code, unknown function or label : {}",
+                label
+            ))),
+        }
     }
 
     /// Compiles a function.
@@ -198,9 +310,51 @@ impl Compiler {
     /// Where Ret is the return value, Off is the stack offset of the preceding function,
     /// Ins is the index of the instruction to execute next and Args the arguments in order.
     /// The calling convention is emited in the function_calls.
-    /// Puts a label for the begining of the function with the function name.
+    /// Puts a label for the begining of the function with the function signature,
+    /// so each overload and each instance of a generic function has its own.
     pub fn function(&mut self, func: &FunctionDecl) {
-        self.new_label_here(func.name().to_string());
+        self.new_label_here(func.signature().to_string());
+        self.function_body(func);
+    }
+
+    /// Compiles a closure as a function taking the captured values after its arguments.
+    pub fn closure_body(&mut self, label: String, closure: &Closure) {
+        let mut func = closure.decl().clone();
+        for capture in closure.captures() {
+            func.arguments.push(capture.clone());
+            func.signature.args.push(capture.type_var().clone().unwrap());
+        }
+        self.label_here(label);
+        self.function_body(&func);
+    }
+
+    /// Compiles a line of the repl, its arguments are the variables of the previous lines.
+    /// It does not return : the program ends after it and its variables stay on the stack.
+    pub fn line_body(&mut self, line: &FunctionDecl) {
+        let start = self.code.len();
+        self.scopes.push(Scope::new(1, 0));
+        self.create_var("0".to_string());
+        self.create_var("1".to_string());
+        self.create_var("2".to_string());
+        for var in line.args() {
+            self.create_var(var.name().to_string());
+        }
+        for st in line.scope() {
+            self.statement(st);
+        }
+        self.emit(OP::End);
+        self.scopes.pop();
+        self.symbols.push(Symbol {
+            name: line.name().clone(),
+            start: start,
+            end: self.code.len(),
+        });
+    }
+
+    /// Compiles the arguments and the statements of a function.
+    /// The instructions of the function are added to the symbol table.
+    pub fn function_body(&mut self, func: &FunctionDecl) {
+        let start = self.code.len();
         self.scopes.push(Scope::new(1, 0));
         self.create_var("0".to_string()); // return value.
         self.create_var("1".to_string()); // next instruction.
@@ -214,7 +368,12 @@ impl Compiler {
         for st in func.scope() {
             self.statement(st);
         }
-        self.return_statement(&Expr::number(0.0, 1));
+        self.return_statement(&Expr::number(0.0, Span::default()));
+        self.symbols.push(Symbol {
+            name: func.signature().to_string(),
+            start: start,
+            end: self.code.len(),
+        });
     }
 
     /// Compiles a statement. depends on the statement.
@@ -231,7 +390,15 @@ impl Compiler {
             &Statement::WhileStatement(ref i) => self.while_statement(i),
             &Statement::BreakStatement => self.break_scope(),
             &Statement::ReturnStatement(ref e) => self.return_statement(e),
-            &Statement::Native(ref ops) => self.emit_chunks(ops.clone()),
+            &Statement::Native(ref ops) => {
+                let bounds_checks = self.bounds_checks;
+                self.emit_chunks(
+                    ops.iter()
+                        .filter(|&op| bounds_checks || op != &OP::CheckIndex)
+                        .cloned()
+                        .collect(),
+                )
+            }
         }
     }
 
@@ -304,13 +471,41 @@ impl Compiler {
         self.exit_scope();
     }
 
+    /// Compiles an if statement :
+    /// if the condition is false it jumps to the else label, after the statement.
+    /// The statement then jumps over the else statement to the end label.
+    /// Without else statement the two labels are at the same place.
     pub fn if_statement(&mut self, if_statement: &IfStatement) {
         self.expression(if_statement.condition());
-        let end_label = self.new_empty_label();
+        let else_label = self.new_empty_label();
         self.emit(OP::JMPIf);
-        self.emit_goto(end_label.clone());
-        self.statement(if_statement.statement());
-        self.label_here(end_label);
+        self.emit_goto(else_label.clone());
+        self.branch(if_statement.statement());
+        match if_statement.else_statement() {
+            Some(else_statement) => {
+                let end_label = self.new_empty_label();
+                self.emit_goto(end_label.clone());
+                self.label_here(else_label);
+                self.branch(else_statement);
+                self.label_here(end_label);
+            }
+            None => self.label_here(else_label),
+        }
+    }
+
+    /// Compiles a statement executed conditionally in its own scope,
+    /// so the variables it declares are popped at its end.
+    pub fn branch(&mut self, statement: &Statement) {
+        match statement {
+            &Statement::Scope(_) => self.statement(statement),
+            _ => {
+                let depth = self.scopes.len();
+                let starting_size = self.scopes.last().unwrap().current_size;
+                self.scopes.push(Scope::new(depth, starting_size));
+                self.statement(statement);
+                self.exit_scope();
+            }
+        }
     }
 
     /// compiles a scope.
@@ -365,13 +560,50 @@ impl Compiler {
     /// Compiles an expression.
     /// The result of the expression will be at the top of the stack at the end.
     pub fn expression(&mut self, expr: &Expr) {
+        self.mark_line(expr.span());
         match expr.expr() {
             &ExprEnum::Literal(ref l) => self.literal(l),
             &ExprEnum::Unary(ref u) => self.unary(u),
             &ExprEnum::GetAttr(ref a) => self.get_attr(a),
             &ExprEnum::Identifier(ref i) => self.identifier(i),
-            &ExprEnum::FunctionCall(ref f) => self.generic_call(f),
+            &ExprEnum::FunctionCall(ref f) => self.generic_call(f, expr.span()),
             &ExprEnum::Deref(ref d) => self.deref(d),
+            &ExprEnum::FunctionRef(ref f) => self.function_value(f.signature().to_string(), &[]),
+            &ExprEnum::Closure(ref c) => {
+                let label = self.new_empty_label();
+                self.closures.push((label.clone(), c.clone()));
+                self.function_value(label, c.captures());
+            }
+        }
+    }
+
+    /// Creates a function value, an object holding the position of the code then the captured values :
+    /// | adress | captures ...
+    /// The captures are copied, changing the variable after does not change the closure.
+    pub fn function_value(&mut self, label: String, captures: &[TypedVar]) {
+        let size = captures.len() + 1;
+        let pointers = captures
+            .iter()
+            .enumerate()
+            .filter(|&(_, c)| c.type_var().as_ref().unwrap().is_pointer_type())
+            .map(|(id, _)| 2u64.pow(id as u32 + 1))
+            .sum::<u64>();
+        self.emit_chunks(vec![
+            OP::PushNum(size as f64),
+            OP::AllocObj(pointers + 2u64.pow(size as u32)),
+            OP::PushCopy,
+        ]);
+        self.emit_push(label);
+        self.emit_chunks(vec![OP::Swap2, OP::SetHeap]);
+        for (id, capture) in captures.iter().enumerate() {
+            self.emit(OP::PushCopy);
+            self.identifier(&capture.name().to_string());
+            self.emit_chunks(vec![
+                OP::Swap2,
+                OP::PushNum(id as f64 + 1.0),
+                OP::Add,
+                OP::SetHeap,
+            ]);
         }
     }
 
@@ -398,12 +630,15 @@ impl Compiler {
     /// A generic call, can be a method a function or anything callable.
     /// checks for method first.
     /// If it is a method, brings the callee then call the method.
-    pub fn generic_call(&mut self, call: &FunctionCall) {
+    pub fn generic_call(&mut self, call: &FunctionCall, span: &Span) {
+        if let &Callee::Value(ref value) = call.callee() {
+            return self.value_call(call, value, span);
+        }
         let func = self.functions.get(&call.signature()).unwrap().clone();
         if func.is_inline() {
             self.inline_call(call);
         } else {
-            self.function_call(call);
+            self.function_call(call, span);
         }
     }
 
@@ -425,8 +660,8 @@ impl Compiler {
     /// As specified, when a function is called we the stack must be in the following format :
     /// Ret | Off | Args ...
     /// So we push the ret, push the offset and go to the function.
-    ///
-    pub fn function_call(&mut self, call: &FunctionCall) {
+    /// The line of the call is marked before the goto, it is the line shown in backtraces.
+    pub fn function_call(&mut self, call: &FunctionCall, span: &Span) {
         let after_call = self.new_empty_label();
         self.emit(OP::PushOffset); // stack :  | Offset |
         self.emit(OP::PushNum(0.0)); // pushes the return value.
@@ -437,7 +672,25 @@ impl Compiler {
         self.emit(OP::OffsetToTop(
             call.args().len() + 3 + call.callee().get_method().is_some() as usize,
         )); // down the current offset to (num args + 3)
-        self.emit_goto(call.name().to_string());
+        self.mark_line(span);
+        self.emit_goto(call.signature().to_string());
+        self.label_here(after_call);
+    }
+
+    /// Calls a function value with the same frame as a function call :
+    /// Ret | Ins | Off | Args ...
+    /// The function value is pushed last, the vm replaces it by the captured values then jumps.
+    pub fn value_call(&mut self, call: &FunctionCall, value: &Expr, span: &Span) {
+        let after_call = self.new_empty_label();
+        self.emit_chunks(vec![OP::PushOffset, OP::PushNum(0.0), OP::Swap2]);
+        self.emit_push(after_call.to_string());
+        self.emit(OP::Swap2);
+        for arg in call.args() {
+            self.expression(arg);
+        }
+        self.expression(value);
+        self.mark_line(span);
+        self.emit(OP::CallIndirect(call.args().len() + 3));
         self.label_here(after_call);
     }
 
@@ -0,0 +1,169 @@
+//! The diagnostics reported to the user.
+//! They are produced by the scanner, the parser, the typechecker and the compiler.
+use std::fmt;
+
+/// A position in a source file.
+/// The span starts at the given line and column (both starting at 1) and is len chars long.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    /// The name of the file.
+    pub file: String,
+    /// The line of the start of the span.
+    pub line: usize,
+    /// The column of the start of the span.
+    pub column: usize,
+    /// The number of chars covered by the span.
+    pub len: usize,
+}
+
+impl Span {
+    /// Creates a new span in the given file.
+    pub fn new(file: String, line: usize, column: usize, len: usize) -> Self {
+        Span {
+            file: file,
+            line: line,
+            column: column,
+            len: len,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// An error message, with the place in the code where it happened if it is known.
+/// Warnings are shown the same way but do not stop the compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    message: String,
+    span: Option<Span>,
+    warning: bool,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic without location.
+    pub fn new(message: String) -> Self {
+        Diagnostic {
+            message: message,
+            span: None,
+            warning: false,
+        }
+    }
+    /// Creates a diagnostic at the given location.
+    pub fn at(message: String, span: Span) -> Self {
+        Diagnostic {
+            message: message,
+            span: Some(span),
+            warning: false,
+        }
+    }
+    /// Creates a warning at the given location.
+    pub fn warning(message: String, span: Span) -> Self {
+        Diagnostic {
+            message: message,
+            span: Some(span),
+            warning: true,
+        }
+    }
+    /// Sets the location if it is not already known.
+    /// Used to give the location of the enclosing expression to errors that have none.
+    pub fn or_at(mut self, span: &Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+    /// Returns the message.
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+    /// Returns the location of the error.
+    pub fn span(&self) -> &Option<Span> {
+        &self.span
+    }
+    /// Checks if the diagnostic is a warning, that does not stop the compilation.
+    pub fn is_warning(&self) -> bool {
+        self.warning
+    }
+    /// Renders the error, showing the offending line of the source and underlining the span :
+    ///
+    /// error: Unknown variable : b
+    ///  --> test.lisaa:4:13
+    ///   |
+    /// 4 |     num a = b;
+    ///   |             ^
+    pub fn render(&self, source: Option<&str>) -> String {
+        let level = if self.warning { "warning" } else { "error" };
+        let mut res = format!("{}: {}", level, self.message);
+        if let Some(ref span) = self.span {
+            res.push_str(&format!("\n --> {}", span));
+            if let Some(line) = source.and_then(|s| s.lines().nth(span.line.wrapping_sub(1))) {
+                let number = span.line.to_string();
+                let margin = " ".repeat(number.len());
+                let padding = line
+                    .chars()
+                    .take(span.column.saturating_sub(1))
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect::<String>();
+                res.push_str(&format!(
+                    "\n{} |\n{} | {}\n{} | {}{}",
+                    margin,
+                    number,
+                    line,
+                    margin,
+                    padding,
+                    "^".repeat(span.len.max(1))
+                ));
+            }
+        }
+        res
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic::new(message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(ref span) => write!(f, "{} : {}", span, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_diagnostic {
+    use super::*;
+    #[test]
+    fn test_render_underlines_span() {
+        let diag = Diagnostic::at(
+            "Unknown variable : b".to_string(),
+            Span::new("test.lisaa".to_string(), 2, 13, 1),
+        );
+        let source = "fn main(){\n    num a = b;\n}";
+        assert_eq!(
+            diag.render(Some(source)),
+            "error: Unknown variable : b\n --> test.lisaa:2:13\n  |\n2 |     num a = b;\n  |             ^"
+        );
+    }
+    #[test]
+    fn test_render_warning() {
+        let diag = Diagnostic::warning(
+            "unused variable : a".to_string(),
+            Span::new("test.lisaa".to_string(), 1, 5, 1),
+        );
+        assert!(diag.render(Some("num a = 2;")).starts_with("warning: unused variable : a\n"));
+    }
+    #[test]
+    fn test_render_without_location() {
+        let diag = Diagnostic::new("No main function found".to_string());
+        assert_eq!(diag.render(None), "error: No main function found");
+    }
+}
@@ -0,0 +1,213 @@
+//! The disassembler, shows the compiled code in a readable way.
+//!
+//! The functions start with a header giving their signature,
+//! the jump targets and return addresses are replaced by labels
+//! and the string literals are shown instead of the code building them.
+use std::char;
+use std::collections::HashMap;
+use vm::{Symbol, IS_SLICE_BIT, OP, STRING_TYPE};
+
+/// Returns the listing of the code, one instruction per line.
+/// Without symbols the functions are not named but the listing still has labels.
+pub fn disassemble(code: &[OP], symbols: &[Symbol]) -> String {
+    let labels = labels(code, symbols);
+    let mut listing = String::new();
+    let mut i = 0;
+    while i < code.len() {
+        match symbols.iter().find(|s| s.start == i) {
+            Some(symbol) => listing.push_str(&format!("\n{}:\n", symbol.name)),
+            None => {
+                if let Some(label) = labels.get(&i) {
+                    listing.push_str(&format!("{}:\n", label));
+                }
+            }
+        }
+        if let Some((string, size)) = string_literal(&code[i..]) {
+            listing.push_str(&format!("{:>6}  String {:?}\n", i, string));
+            i += size;
+            continue;
+        }
+        let instruction = match code[i] {
+            OP::Goto(target) => format!("Goto {}", labels[&target]),
+            OP::PushFrame(target) => format!("PushFrame {}", labels[&target]),
+            OP::PushNum(target) if is_return_address(code, i) => {
+                format!("PushNum {}", labels[&(target as usize)])
+            }
+            ref op => format!("{:?}", op),
+        };
+        listing.push_str(&format!("{:>6}  {}\n", i, instruction));
+        i += 1;
+    }
+    listing
+}
+
+/// Names all the positions that are jumped to.
+/// The start of functions are named after the function, the others are named after their position.
+fn labels(code: &[OP], symbols: &[Symbol]) -> HashMap<usize, String> {
+    let mut labels = HashMap::new();
+    for (i, op) in code.iter().enumerate() {
+        let target = match op {
+            &OP::Goto(target) | &OP::PushFrame(target) => target,
+            &OP::PushNum(target) if is_return_address(code, i) => target as usize,
+            _ => continue,
+        };
+        let name = match symbols.iter().find(|s| s.start == target) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("L{}", target),
+        };
+        labels.insert(target, name);
+    }
+    labels
+}
+
+/// Checks if the instruction pushes the position to go back to after a call.
+/// The calling convention is : PushOffset PushNum(0) Swap2 PushNum(return address) Swap2
+fn is_return_address(code: &[OP], i: usize) -> bool {
+    i >= 3
+        && code[i - 3] == OP::PushOffset
+        && code[i - 2] == OP::PushNum(0.0)
+        && code[i - 1] == OP::Swap2
+        && code.get(i + 1) == Some(&OP::Swap2)
+}
+
+/// If the code starts with a string literal, returns the string and the number of instructions.
+/// The code must match what the compiler emits for literals, or what the peephole makes of it.
+fn string_literal(code: &[OP]) -> Option<(String, usize)> {
+    let len = match code.get(3) {
+        Some(&OP::PushNum(n)) if n >= 0.0 => n as usize,
+        _ => return None,
+    };
+    let mut i = 0;
+    expect(
+        code,
+        &mut i,
+        &[
+            OP::PushNum(2.0),
+            OP::AllocObj(STRING_TYPE),
+            OP::PushCopy,
+            OP::PushNum(len as f64),
+            OP::Swap2,
+            OP::SetHeap,
+            OP::PushCopy,
+            OP::PushNum(len as f64),
+            OP::AllocObj(IS_SLICE_BIT + len as u64),
+        ],
+    )?;
+    let mut string = String::new();
+    for id in 0..len {
+        let ch = match code.get(i + 1) {
+            Some(&OP::PushNum(ch)) => ch,
+            _ => return None,
+        };
+        string.push(char::from_u32(ch as u32)?);
+        expect(code, &mut i, &[OP::PushCopy, OP::PushNum(ch), OP::Swap2])?;
+        add(code, &mut i, id as f64)?;
+        expect(code, &mut i, &[OP::SetHeap])?;
+    }
+    expect(code, &mut i, &[OP::Swap2])?;
+    add(code, &mut i, 1.0)?;
+    expect(code, &mut i, &[OP::SetHeap])?;
+    Some((string, i))
+}
+
+/// Moves the position after the instructions if the code has them there.
+fn expect(code: &[OP], i: &mut usize, ops: &[OP]) -> Option<()> {
+    match code.get(*i..*i + ops.len()) {
+        Some(found) if found == ops => {
+            *i += ops.len();
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+/// Moves the position after the addition of the number : PushNum(n) Add, or PushAdd(n).
+fn add(code: &[OP], i: &mut usize, n: f64) -> Option<()> {
+    expect(code, i, &[OP::PushAdd(n)]).or_else(|| expect(code, i, &[OP::PushNum(n), OP::Add]))
+}
+
+#[cfg(test)]
+mod tests_disassemble {
+    use super::*;
+
+    #[test]
+    fn test_labels_and_headers() {
+        let code = vec![
+            OP::PushOffset,
+            OP::PushNum(0.0),
+            OP::Swap2,
+            OP::PushNum(7.0),
+            OP::Swap2,
+            OP::OffsetToTop(3),
+            OP::Goto(8),
+            OP::End,
+            OP::JMPIf,
+            OP::Goto(8),
+            OP::GotoTop,
+        ];
+        let symbols = vec![Symbol {
+            name: "main()".to_string(),
+            start: 8,
+            end: 11,
+        }];
+        assert_eq!(
+            disassemble(&code, &symbols),
+            "     0  PushOffset
+     1  PushNum(0.0)
+     2  Swap2
+     3  PushNum L7
+     4  Swap2
+     5  OffsetToTop(3)
+     6  Goto main()
+L7:
+     7  End
+
+main():
+     8  JMPIf
+     9  Goto main()
+    10  GotoTop
+"
+        );
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let mut code = vec![
+            OP::PushNum(2.0),
+            OP::AllocObj(STRING_TYPE),
+            OP::PushCopy,
+            OP::PushNum(2.0),
+            OP::Swap2,
+            OP::SetHeap,
+            OP::PushCopy,
+            OP::PushNum(2.0),
+            OP::AllocObj(IS_SLICE_BIT + 2),
+        ];
+        for (id, ch) in "hi".chars().enumerate() {
+            code.extend(vec![
+                OP::PushCopy,
+                OP::PushNum(ch as u32 as f64),
+                OP::Swap2,
+                OP::PushNum(id as f64),
+                OP::Add,
+                OP::SetHeap,
+            ]);
+        }
+        code.extend(vec![OP::Swap2, OP::PushNum(1.0), OP::Add, OP::SetHeap, OP::End]);
+        assert_eq!(
+            disassemble(&code, &[]),
+            "     0  String \"hi\"\n    25  End\n"
+        );
+        let mut optimized = vec![];
+        for op in code {
+            match (optimized.last().cloned(), op) {
+                (Some(OP::PushNum(n)), OP::Add) => *optimized.last_mut().unwrap() = OP::PushAdd(n),
+                (_, op) => optimized.push(op),
+            }
+        }
+        assert_eq!(
+            disassemble(&optimized, &[]),
+            "     0  String \"hi\"\n    22  End\n"
+        );
+    }
+}
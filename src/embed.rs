@@ -0,0 +1,129 @@
+//! The programs loaded by a host, see Lisaa::load.
+//!
+//! The program is compiled once and its functions can then be called many times,
+//! the arguments and results are converted like the ones of the native functions.
+use std::fmt;
+use vm::ffi::{NativeType, NativeValue};
+use vm::{Vm, OP};
+
+/// A function of the program that the host can call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    name: String,
+    args: Vec<NativeType>,
+    ret: NativeType,
+    /// The first instruction of the function.
+    start: usize,
+}
+
+impl Export {
+    /// Creates an export for the function starting at the given instruction.
+    pub fn new(name: String, args: Vec<NativeType>, ret: NativeType, start: usize) -> Self {
+        Export {
+            name: name,
+            args: args,
+            ret: ret,
+            start: start,
+        }
+    }
+
+    /// Checks if the function can be called with these values.
+    fn accepts(&self, args: &[NativeValue]) -> bool {
+        self.args.len() == args.len()
+            && self
+                .args
+                .iter()
+                .zip(args.iter())
+                .all(|(arg_type, arg)| arg.is_of_type(arg_type))
+    }
+}
+
+/// Shows the export like a lisaa signature : add(num, num) -> num
+impl fmt::Display for Export {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        match self.ret {
+            NativeType::Void => write!(f, "{}({})", self.name, args),
+            ref ret => write!(f, "{}({}) -> {}", self.name, args, ret),
+        }
+    }
+}
+
+/// A compiled program with its vm, the heap and the natives are kept between the calls.
+pub struct Instance<'a> {
+    code: Vec<OP>,
+    exports: Vec<Export>,
+    vm: Vm<'a>,
+}
+
+impl<'a> Instance<'a> {
+    /// Creates an instance running the code in the given vm.
+    pub fn new(code: Vec<OP>, exports: Vec<Export>, vm: Vm<'a>) -> Self {
+        Instance {
+            code: code,
+            exports: exports,
+            vm: vm,
+        }
+    }
+
+    /// Returns the signatures of the functions that can be called, sorted.
+    /// Only the functions whose arguments and result can be converted are there.
+    pub fn functions(&self) -> Vec<String> {
+        let mut functions = self
+            .exports
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>();
+        functions.sort();
+        functions
+    }
+
+    /// Calls the function with the given name, the overload is chosen by the type of the arguments.
+    /// A runtime error is returned with the backtrace of the lisaa functions.
+    pub fn call(&mut self, name: &str, args: &[NativeValue]) -> Result<NativeValue, String> {
+        let export = {
+            let candidates = self
+                .exports
+                .iter()
+                .filter(|e| e.name == name && e.accepts(args))
+                .collect::<Vec<&Export>>();
+            match candidates.as_slice() {
+                [export] => (*export).clone(),
+                [] if self.exports.iter().any(|e| e.name == name) => {
+                    return Err(format!("no function {} taking the arguments {:?}", name, args))
+                }
+                [] => return Err(format!("unknown function : {}", name)),
+                _ => {
+                    return Err(format!(
+                        "ambiguous call of {}, it could be any of : {}",
+                        name,
+                        candidates
+                            .iter()
+                            .map(|e| e.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ))
+                }
+            }
+        };
+        let args = args
+            .iter()
+            .cloned()
+            .zip(export.args.iter().cloned())
+            .collect::<Vec<(NativeValue, NativeType)>>();
+        self.vm
+            .call(&self.code, export.start, &args, &export.ret)
+            .map_err(|e| {
+                let mut message = e.to_string();
+                for frame in e.backtrace.iter() {
+                    message.push_str(&format!("\n    {}", frame));
+                }
+                message
+            })
+    }
+}
@@ -1,10 +1,11 @@
 //! The module containing the code for the different expressions.
 //!
 
-use statement::FunctionSig;
+use diagnostic::Span;
+use statement::{FunctionDecl, FunctionSig};
 use std::fmt;
 use token::{Token, TokenType};
-use types::LisaaType;
+use types::{LisaaType, TypedVar};
 
 #[derive(Debug, Clone)]
 /// The base for an expression.
@@ -24,6 +25,10 @@ pub enum ExprEnum {
     FunctionCall(FunctionCall),
     /// A dereferenced value contains a
     Deref(Deref),
+    /// A named function used as a value : add or newslice::<num>
+    FunctionRef(FunctionRef),
+    /// An anonymous function : fn(num a) -> num { return a + b; }
+    Closure(Closure),
 }
 /// Expressions that are lvalues :
 /// Identifier
@@ -34,7 +39,7 @@ pub enum ExprEnum {
 pub struct Expr {
     expr: ExprEnum,
     return_type: Option<LisaaType>,
-    line: usize,
+    span: Span,
 }
 
 impl Expr {
@@ -46,25 +51,29 @@ impl Expr {
         new
     }
     pub fn deref(inner: Expr) -> Self {
-        let line = inner.get_line();
+        let span = inner.span().clone();
         Expr {
             expr: ExprEnum::Deref(Deref::new(inner)),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
-    pub fn getattr(lhs: Expr, rhs: Expr, line: usize) -> Self {
+    pub fn getattr(lhs: Expr, rhs: Expr, span: Span) -> Self {
         Expr {
             expr: ExprEnum::GetAttr(BinaryExpr::new(lhs, Operator::Get, rhs)),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
     /// Now the binary expression turn into a call of a getattr.
     ///
-    pub fn binary(lhs: Expr, operator: Operator, rhs: Expr, line: usize) -> Self {
-        let inner = Expr::getattr(lhs, Expr::identifier(format!("{}", operator), line), line);
-        Expr::method_call(inner, vec![rhs], line)
+    pub fn binary(lhs: Expr, operator: Operator, rhs: Expr, span: Span) -> Self {
+        let inner = Expr::getattr(
+            lhs,
+            Expr::identifier(format!("{}", operator), span.clone()),
+            span.clone(),
+        );
+        Expr::method_call(inner, vec![rhs], span)
     }
     pub fn is_identifier(&self) -> bool {
         match self.expr {
@@ -93,60 +102,82 @@ impl Expr {
     pub fn return_type_uncheck(&self) -> &Option<LisaaType> {
         &self.return_type
     }
-    pub fn method_call(expr: Expr, args: Vec<Expr>, line: usize) -> Self {
+    pub fn method_call(expr: Expr, args: Vec<Expr>, span: Span) -> Self {
         Expr {
             expr: ExprEnum::FunctionCall(FunctionCall::method(expr, args)),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
-    pub fn function_call(name: String, args: Vec<Expr>, line: usize) -> Self {
+    pub fn function_call(name: String, args: Vec<Expr>, span: Span) -> Self {
         Expr {
             expr: ExprEnum::FunctionCall(FunctionCall::function(name, args)),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
-    pub fn constructor_call(func: FunctionCall, line: usize) -> Self {
+    pub fn constructor_call(func: FunctionCall, span: Span) -> Self {
         Expr {
             expr: ExprEnum::FunctionCall(func),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
-    pub fn unary(operator: Operator, expr: Expr, line: usize) -> Self {
+    pub fn unary(operator: Operator, expr: Expr, span: Span) -> Self {
         Expr {
             expr: ExprEnum::Unary(UnaryExpr::new(operator, expr)),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
-    pub fn char(ch: char, line: usize) -> Self {
+    pub fn char(ch: char, span: Span) -> Self {
         Expr {
             expr: ExprEnum::Literal(LiteralExpr::CHAR(ch)),
             return_type: Some(LisaaType::Char),
-            line: line,
+            span: span,
         }
     }
-    pub fn number(num: f64, line: usize) -> Self {
+    /// A true or false literal, stored as a number.
+    pub fn boolean(value: bool, span: Span) -> Self {
+        Expr {
+            expr: ExprEnum::Literal(LiteralExpr::NUMBER(if value { 1.0 } else { 0.0 })),
+            return_type: Some(LisaaType::Bool),
+            span: span,
+        }
+    }
+    pub fn number(num: f64, span: Span) -> Self {
         Expr {
             expr: ExprEnum::Literal(LiteralExpr::NUMBER(num)),
             return_type: Some(LisaaType::Num),
-            line: line,
+            span: span,
         }
     }
-    pub fn string(string: String, line: usize) -> Self {
+    pub fn string(string: String, span: Span) -> Self {
         Expr {
             expr: ExprEnum::Literal(LiteralExpr::STRING(string)),
             return_type: Some(LisaaType::Class("String".to_owned(), vec![])),
-            line: line,
+            span: span,
+        }
+    }
+    pub fn function_ref(name: String, type_args: Vec<LisaaType>, span: Span) -> Self {
+        Expr {
+            expr: ExprEnum::FunctionRef(FunctionRef::new(name, type_args)),
+            return_type: None,
+            span: span,
         }
     }
-    pub fn identifier(string: String, line: usize) -> Self {
+    pub fn closure(decl: FunctionDecl, span: Span) -> Self {
+        Expr {
+            expr: ExprEnum::Closure(Closure::new(decl)),
+            return_type: None,
+            span: span,
+        }
+    }
+    pub fn identifier(string: String, span: Span) -> Self {
         Expr {
             expr: ExprEnum::Identifier(string),
             return_type: None,
-            line: line,
+            span: span,
         }
     }
     pub fn get_identifier(&self) -> Result<&String, String> {
@@ -156,7 +187,11 @@ impl Expr {
         }
     }
     pub fn get_line(&self) -> usize {
-        self.line
+        self.span.line
+    }
+    /// Returns the position of the expression in the source.
+    pub fn span(&self) -> &Span {
+        &self.span
     }
 }
 
@@ -188,17 +223,101 @@ impl Deref {
     }
 }
 
+/// A reference to a named function, the signature is set by the typechecker.
+#[derive(Debug, Clone)]
+pub struct FunctionRef {
+    name: String,
+    type_args: Vec<LisaaType>,
+    signature: Option<FunctionSig>,
+    /// The file of the module qualifying the name, only its overloads are referenced.
+    module: Option<String>,
+}
+impl FunctionRef {
+    pub fn new(name: String, type_args: Vec<LisaaType>) -> Self {
+        FunctionRef {
+            name: name,
+            type_args: type_args,
+            signature: None,
+            module: None,
+        }
+    }
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn type_args(&self) -> &Vec<LisaaType> {
+        &self.type_args
+    }
+    pub fn type_args_mut(&mut self) -> &mut Vec<LisaaType> {
+        &mut self.type_args
+    }
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+    pub fn signature(&self) -> FunctionSig {
+        self.signature
+            .clone()
+            .expect("cant get signature of non typechecked function!")
+    }
+    pub fn set_signature(&mut self, sig: FunctionSig) {
+        self.signature = Some(sig);
+    }
+    /// Returns the signature if the reference was typechecked.
+    pub fn checked_signature(&self) -> Option<&FunctionSig> {
+        self.signature.as_ref()
+    }
+    /// Returns the file of the module qualifying the name.
+    pub fn module(&self) -> Option<&String> {
+        self.module.as_ref()
+    }
+    /// Sets the file of the module qualifying the name.
+    pub fn set_module(&mut self, file: String) {
+        self.module = Some(file);
+    }
+}
+
+/// An anonymous function.
+/// The variables it uses from the enclosing functions are captured by value when it is created,
+/// the typechecker finds them.
+#[derive(Debug, Clone)]
+pub struct Closure {
+    decl: Box<FunctionDecl>,
+    captures: Vec<TypedVar>,
+}
+impl Closure {
+    pub fn new(decl: FunctionDecl) -> Self {
+        Closure {
+            decl: Box::new(decl),
+            captures: vec![],
+        }
+    }
+    pub fn decl(&self) -> &FunctionDecl {
+        &self.decl
+    }
+    pub fn decl_mut(&mut self) -> &mut FunctionDecl {
+        &mut self.decl
+    }
+    /// The captured variables, in the order they are stored in the function value.
+    pub fn captures(&self) -> &Vec<TypedVar> {
+        &self.captures
+    }
+    pub fn set_captures(&mut self, captures: Vec<TypedVar>) {
+        self.captures = captures;
+    }
+}
+
 /// Represents a function call in the code.
 #[derive(Debug, Clone)]
 pub enum Callee {
     StaticFunc(String),
     Method(Box<Expr>),
+    /// A call of a function value, set by the typechecker.
+    Value(Box<Expr>),
 }
 
 impl Callee {
     pub fn get_caller_type(&self) -> Option<LisaaType> {
         match self {
-            &Callee::StaticFunc(_) => None,
+            &Callee::StaticFunc(_) | &Callee::Value(_) => None,
             &Callee::Method(ref e) => {
                 if let (LisaaType::Function(box t, _, _), _) = e.return_type().max_deref() {
                     Some(t)
@@ -211,7 +330,7 @@ impl Callee {
 
     pub fn get_method(&self) -> Option<&Expr> {
         match self {
-            &Callee::StaticFunc(_) => None,
+            &Callee::StaticFunc(_) | &Callee::Value(_) => None,
             &Callee::Method(ref e) => match e.expr() {
                 ExprEnum::GetAttr(b) => Some(b.lhs()),
                 ExprEnum::Deref(deref) => match deref.expr.expr() {
@@ -233,6 +352,8 @@ pub struct FunctionCall {
     signature: Option<FunctionSig>,
     /// Only if it is a constructor.
     type_args: Vec<LisaaType>,
+    /// The file of the module qualifying the name, only its overloads are called.
+    module: Option<String>,
 }
 
 impl FunctionCall {
@@ -243,6 +364,7 @@ impl FunctionCall {
             name: "method".to_owned(),
             signature: None,
             type_args: vec![],
+            module: None,
         }
     }
     /// Creates a new function call expression
@@ -253,6 +375,7 @@ impl FunctionCall {
             name: name,
             signature: None,
             type_args: vec![],
+            module: None,
         }
     }
     /// Creates a new function call expression
@@ -263,6 +386,7 @@ impl FunctionCall {
             name: name,
             signature: None,
             type_args: type_args,
+            module: None,
         }
     }
     pub fn signature(&self) -> FunctionSig {
@@ -280,6 +404,18 @@ impl FunctionCall {
     pub fn set_signature(&mut self, sig: FunctionSig) {
         self.signature = Some(sig);
     }
+    /// Returns the signature if the call was typechecked, a call of a function value has none.
+    pub fn checked_signature(&self) -> Option<&FunctionSig> {
+        self.signature.as_ref()
+    }
+    /// Returns the file of the module qualifying the name.
+    pub fn module(&self) -> Option<&String> {
+        self.module.as_ref()
+    }
+    /// Sets the file of the module qualifying the name.
+    pub fn set_module(&mut self, file: String) {
+        self.module = Some(file);
+    }
     pub fn callee(&self) -> &Callee {
         &self.callee
     }
@@ -289,6 +425,9 @@ impl FunctionCall {
     pub fn type_args(&self) -> &Vec<LisaaType> {
         &self.type_args
     }
+    pub fn type_args_mut(&mut self) -> &mut Vec<LisaaType> {
+        &mut self.type_args
+    }
     /// Returns the name.
     pub fn set_name(&mut self, name: &String) {
         self.name = name.to_owned();
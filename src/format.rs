@@ -0,0 +1,749 @@
+//! The formatter, used by `lisaa fmt` to rewrite the sources in the canonical style :
+//! four spaces of indentation, the opening braces at the end of the line of their statement,
+//! one blank line between the elements and at most one between the statements.
+//!
+//! The source is parsed and its tree is printed back, so the parentheses are only the ones needed
+//! by the precedence and the bodies of the ifs and the loops are always blocks.
+//! The comments are not in the tree, the scanner keeps them aside with their line :
+//! a comment is written before the statement following it, or at the end of the line it ends.
+//! The comments inside a statement written on several lines are written before it,
+//! the ones before an else at the end of the block preceding it.
+use diagnostic::Diagnostic;
+use expression::{Callee, Expr, ExprEnum, LiteralExpr, Operator};
+use module::Import;
+use parser::Parser;
+use scanner::Scanner;
+use statement::{ClassDecl, Element, FunctionDecl, Statement, TraitDecl, TypeParam, WhileStatement};
+use std::collections::VecDeque;
+use token::{Token, TokenType};
+use types::LisaaType;
+
+const INDENT: &str = "    ";
+/// The priority of the unary expressions, the binary ones have a lower one.
+const UNARY: u8 = 6;
+/// The priority of the expressions never needing parentheses : the literals, the calls, the attributes...
+const POSTFIX: u8 = 7;
+
+/// Returns the source formatted, or the errors found while parsing it.
+pub fn format(source: &str, file: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.to_string(), file.to_string());
+    let tokens = scanner.tokens().map_err(|e| vec![e])?;
+    let braces = braces(&tokens);
+    let elements = Parser::new(tokens).elements()?;
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        line_start: true,
+        block_start: true,
+        last_line: 0,
+        comments: scanner.comments().iter().cloned().collect(),
+        braces: braces,
+    };
+    formatter.elements(&elements);
+    Ok(formatter.out)
+}
+
+/// Returns the lines of the opening and closing braces of the blocks, in the order of the source,
+/// with the line of the else following the closing brace (the line of the brace if there is none).
+/// The tree does not know where its blocks end, the formatter takes them in the same order.
+fn braces(tokens: &[Token]) -> VecDeque<(usize, usize, usize)> {
+    let mut braces = VecDeque::new();
+    let mut open = vec![];
+    for (position, token) in tokens.iter().enumerate() {
+        match token.get_type() {
+            &TokenType::LeftCurlyBrace => {
+                open.push(braces.len());
+                braces.push_back((token.get_line(), token.get_line(), token.get_line()));
+            }
+            &TokenType::RightCurlyBrace => {
+                let else_line = match tokens.get(position + 1) {
+                    Some(next) if next.is_type(&TokenType::ELSE) => next.get_line(),
+                    _ => token.get_line(),
+                };
+                if let Some(i) = open.pop() {
+                    braces[i].1 = token.get_line();
+                    braces[i].2 = else_line;
+                }
+            }
+            _ => (),
+        }
+    }
+    braces
+}
+
+/// Writes the tree, the lines of the source are only used to place the comments and the blank lines.
+struct Formatter {
+    out: String,
+    indent: usize,
+    /// Nothing was written on the current line yet, not even the indentation.
+    line_start: bool,
+    /// Nothing was written in the current block yet, a blank line there is not kept.
+    block_start: bool,
+    /// The last line of the source that was written.
+    last_line: usize,
+    /// The comments not written yet.
+    comments: VecDeque<Token>,
+    /// The blocks not written yet.
+    braces: VecDeque<(usize, usize, usize)>,
+}
+
+impl Formatter {
+    /// Writes some text, indented if it starts a line.
+    fn write(&mut self, text: &str) {
+        if self.line_start {
+            for _ in 0..self.indent {
+                self.out.push_str(INDENT);
+            }
+            self.line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    /// Ends the line written, that ends the given line of the source.
+    /// The comments of that line or of the previous ones are written at its end.
+    fn end_line(&mut self, line: usize) {
+        while self.comments.front().map_or(false, |c| c.get_line() <= line) {
+            let comment = self.comments.pop_front().unwrap();
+            self.write(" ");
+            self.write(comment.get_lexeme());
+        }
+        self.out.push('\n');
+        self.line_start = true;
+        self.last_line = self.last_line.max(line);
+    }
+
+    /// Writes a blank line if there was one in the source before the given line.
+    fn separate(&mut self, line: usize) {
+        if !self.block_start && self.last_line > 0 && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+    }
+
+    /// Writes the comments found before the given line of the source, each one on its line.
+    fn comments_before(&mut self, line: usize) {
+        while self.comments.front().map_or(false, |c| c.get_line() < line) {
+            let comment = self.comments.pop_front().unwrap();
+            self.separate(comment.get_line());
+            self.write(comment.get_lexeme());
+            self.end_line(comment.get_line());
+        }
+    }
+
+    /// Writes the elements separated by a blank line, the imports are kept together, the traits too.
+    fn elements(&mut self, elements: &[(bool, Element)]) {
+        let mut previous = None;
+        for &(public, ref element) in elements {
+            let (line, kind) = match element {
+                &Element::Function(ref f) => (f.span.as_ref().map_or(0, |s| s.line), "fn"),
+                &Element::Class(ref c) => (c.span().map_or(0, |s| s.line), "class"),
+                &Element::Trait(ref t) => (t.span().map_or(0, |s| s.line), "trait"),
+                &Element::Import(ref i) => (i.span.line, "import"),
+            };
+            match previous {
+                Some(previous) if previous == kind && (kind == "import" || kind == "trait") => (),
+                Some(_) => self.out.push('\n'),
+                None => (),
+            }
+            self.block_start = true;
+            self.comments_before(line);
+            self.separate(line);
+            if public {
+                self.write("pub ");
+            }
+            match element {
+                &Element::Function(ref f) => self.function(f),
+                &Element::Class(ref c) => self.class(c),
+                &Element::Trait(ref t) => self.trait_decl(t, line),
+                &Element::Import(ref i) => self.import(i),
+            }
+            previous = Some(kind);
+        }
+        self.comments_before(usize::max_value());
+    }
+
+    /// import module, import module::{a, b}
+    fn import(&mut self, import: &Import) {
+        self.write("import ");
+        self.write(&import.path);
+        let mut line = import.span.line;
+        if let Some(ref names) = import.names {
+            line = self.braces.pop_front().map_or(line, |b| b.1);
+            self.write("::{");
+            self.write(&names.join(", "));
+            self.write("}");
+        }
+        self.end_line(line);
+    }
+
+    /// fn name<T : Trait>(num a) -> num { ... }, or method name() -> num of Class { ... }
+    fn function(&mut self, function: &FunctionDecl) {
+        let arguments = function
+            .args()
+            .iter()
+            .map(|a| format!("{} {}", type_name(a.type_var().as_ref().unwrap()), a.name()))
+            .collect::<Vec<String>>();
+        let header = match function.self_type() {
+            &Some(ref self_type) => format!(
+                "method {}{} of {} ",
+                function.name().splitn(2, "::").last().unwrap(),
+                signature(function.type_args(), &arguments, function.ret_type()),
+                type_name(self_type)
+            ),
+            &None => format!(
+                "fn {}{} ",
+                function.name(),
+                signature(function.type_args(), &arguments, function.ret_type())
+            ),
+        };
+        self.write(&header);
+        self.block(function.scope());
+        let line = self.last_line;
+        self.end_line(line);
+    }
+
+    /// Class Name<T : Trait> { num attribute = 0; }
+    fn class(&mut self, class: &ClassDecl) {
+        let header = format!("Class {}{} ", class.name(), type_params(class.type_params()));
+        self.write(&header);
+        let close = self.open_block();
+        for attribute in class.attributes() {
+            self.statement(&Statement::Declaration(attribute.clone()));
+        }
+        self.close_block(close);
+        self.end_line(close.0);
+    }
+
+    /// trait Name = Other + method name(num) -> Self;
+    /// The methods are not kept in their order, they are sorted by name after the traits.
+    fn trait_decl(&mut self, decl: &TraitDecl, line: usize) {
+        let mut parts = decl.sub_traits().clone();
+        // the Any added by the parser.
+        if decl.name() != "Any" {
+            parts.pop();
+        }
+        let mut methods = decl.methods().values().collect::<Vec<_>>();
+        methods.sort_by_key(|m| m.name());
+        for method in methods {
+            let arguments = method.args.iter().map(type_name).collect::<Vec<String>>();
+            // the Self added by the parser.
+            let params = &method.type_args[..method.type_args.len().saturating_sub(1)];
+            parts.push(format!(
+                "method {}{}",
+                method.name(),
+                signature(params, &arguments, &method.ret_type)
+            ));
+        }
+        let text = format!("trait {} = {};", decl.name(), parts.join(" + "));
+        self.write(&text);
+        self.end_line(line);
+    }
+
+    /// Writes the opening brace of the next block of the source,
+    /// returns the lines of its closing brace and of the else following it.
+    fn open_block(&mut self) -> (usize, usize) {
+        let last_line = self.last_line;
+        let (open, close, else_line) = self
+            .braces
+            .pop_front()
+            .unwrap_or((last_line, last_line, last_line));
+        self.write("{");
+        self.end_line(open);
+        self.indent += 1;
+        self.block_start = true;
+        (close, else_line)
+    }
+
+    /// Writes the comments left in the block and its closing brace, the line is not ended.
+    /// The comments before the else following the block are written in it, the else is written after the brace.
+    fn close_block(&mut self, (close, else_line): (usize, usize)) {
+        self.comments_before(close);
+        self.last_line = self.last_line.max(close);
+        self.comments_before(else_line);
+        self.indent -= 1;
+        self.write("}");
+        self.last_line = self.last_line.max(else_line);
+    }
+
+    /// Writes a block of the source.
+    fn block(&mut self, statements: &[Statement]) {
+        let close = self.open_block();
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close_block(close);
+    }
+
+    /// Writes the body of an if or a loop, in a block even if the source has none.
+    fn body(&mut self, statement: &Statement) {
+        match statement {
+            &Statement::Scope(ref statements) if for_loop(statements).is_none() => {
+                self.block(statements)
+            }
+            statement => {
+                self.write("{");
+                let line = self.last_line;
+                self.end_line(line);
+                self.indent += 1;
+                self.block_start = true;
+                self.statement(statement);
+                self.indent -= 1;
+                self.write("}");
+            }
+        }
+    }
+
+    /// Writes a statement on its own lines, after the comments preceding it.
+    /// A simple statement is written on one line, the comments inside it are written before it.
+    fn statement(&mut self, statement: &Statement) {
+        let mut bounds = (usize::max_value(), 0);
+        statement_lines(statement, &mut bounds);
+        match self.start_line(statement) {
+            Some(line) => {
+                self.comments_before(line.max(bounds.1));
+                self.separate(line);
+            }
+            None => self.block_start = false,
+        }
+        match statement {
+            &Statement::Scope(ref statements) => {
+                match for_loop(statements) {
+                    Some((init, inner)) => self.for_loop(init, inner),
+                    None => self.block(statements),
+                }
+                let line = self.last_line;
+                self.end_line(line);
+            }
+            &Statement::IfStatement(ref i) => {
+                self.if_statement(i.condition(), i.statement(), i.else_statement());
+                let line = self.last_line;
+                self.end_line(line);
+            }
+            &Statement::WhileStatement(ref w) => {
+                self.write("while ");
+                self.expr(w.condition(), 0);
+                self.write(" ");
+                self.body(w.statement());
+                let line = self.last_line;
+                self.end_line(line);
+            }
+            &Statement::Native(_) => (),
+            statement => {
+                self.simple(statement);
+                let mut bounds = (usize::max_value(), self.last_line);
+                statement_lines(statement, &mut bounds);
+                self.end_line(bounds.1);
+            }
+        }
+    }
+
+    /// Writes an if and its else, an else if is written on the line of the closing brace.
+    fn if_statement(&mut self, condition: &Expr, statement: &Statement, other: Option<&Statement>) {
+        self.write("if ");
+        self.expr(condition, 0);
+        self.write(" ");
+        self.body(statement);
+        match other {
+            Some(&Statement::IfStatement(ref i)) => {
+                self.write(" else ");
+                self.if_statement(i.condition(), i.statement(), i.else_statement());
+            }
+            Some(other) => {
+                self.write(" else ");
+                self.body(other);
+            }
+            None => (),
+        }
+    }
+
+    /// for (num i = 0; i < 3; i = i + 1;) { ... }
+    fn for_loop(&mut self, init: &Statement, inner: &WhileStatement) {
+        let (statement, repeat) = match inner.statement() {
+            &Statement::Scope(ref s) => (&s[0], &s[1]),
+            _ => unreachable!("a for always has a scope with its statement and the repeated one"),
+        };
+        self.write("for (");
+        self.simple(init);
+        self.write(" ");
+        self.expr(inner.condition(), 0);
+        self.write("; ");
+        self.simple(repeat);
+        self.write(") ");
+        self.body(statement);
+    }
+
+    /// Writes a statement ending with a semicolon, without ending the line.
+    fn simple(&mut self, statement: &Statement) {
+        match statement {
+            &Statement::ExprStatement(ref e) => self.expr(e, 0),
+            &Statement::Assignment(ref a) => {
+                self.expr(a.assignee(), 0);
+                self.write(" = ");
+                self.expr(a.expr(), 0);
+            }
+            &Statement::Declaration(ref d) => {
+                let text = format!("{} {} = ", type_name(d.val_type()), d.name());
+                self.write(&text);
+                self.expr(d.expr(), 0);
+            }
+            &Statement::ReturnStatement(ref e) => {
+                self.write("return ");
+                self.expr(e, 0);
+            }
+            &Statement::BreakStatement => self.write("break"),
+            _ => unreachable!("not a simple statement : {:?}", statement),
+        }
+        self.write(";");
+    }
+
+    /// Returns the line where the statement starts, None if it is not known.
+    fn start_line(&self, statement: &Statement) -> Option<usize> {
+        match statement {
+            &Statement::Scope(ref statements) => match for_loop(statements) {
+                Some((init, _)) => self.start_line(init),
+                None => self.braces.front().map(|b| b.0),
+            },
+            &Statement::IfStatement(ref i) => Some(lines(i.condition()).0),
+            &Statement::WhileStatement(ref w) => Some(lines(w.condition()).0),
+            &Statement::BreakStatement | &Statement::Native(_) => None,
+            statement => {
+                let mut bounds = (usize::max_value(), 0);
+                statement_lines(statement, &mut bounds);
+                Some(bounds.0)
+            }
+        }
+    }
+
+    /// Writes an expression, between parentheses if its priority is lower than the one given.
+    fn expr(&mut self, expr: &Expr, priority: u8) {
+        if self::priority(expr) < priority {
+            self.write("(");
+            self.expr_inner(expr);
+            self.write(")");
+        } else {
+            self.expr_inner(expr);
+        }
+    }
+
+    fn expr_inner(&mut self, expr: &Expr) {
+        if let Some((lhs, name, rhs)) = operator_call(expr) {
+            if let Some((symbol, priority)) = binary_operator(name) {
+                self.expr(lhs, priority);
+                self.write(&format!(" {} ", symbol));
+                self.expr(rhs, priority + 1);
+                return;
+            }
+        }
+        match expr.expr() {
+            &ExprEnum::Literal(ref literal) => {
+                let text = literal_text(literal, expr.return_type_uncheck());
+                self.write(&text);
+            }
+            &ExprEnum::Identifier(ref name) => self.write(name),
+            &ExprEnum::FunctionRef(ref reference) => {
+                let text = format!("{}{}", reference.name(), type_args(reference.type_args()));
+                self.write(&text);
+            }
+            &ExprEnum::Unary(ref unary) => {
+                self.write(match unary.operator() {
+                    Operator::Not => "!",
+                    _ => "-",
+                });
+                self.expr(unary.expression(), UNARY);
+            }
+            &ExprEnum::GetAttr(ref attr) => {
+                self.expr(attr.lhs(), POSTFIX);
+                self.write(".");
+                self.expr(attr.rhs(), POSTFIX);
+            }
+            &ExprEnum::Deref(ref deref) => match operator_call(deref.inner()) {
+                Some((lhs, "index", index)) => {
+                    self.expr(lhs, POSTFIX);
+                    self.write("[");
+                    self.expr(index, 0);
+                    self.write("]");
+                }
+                _ => self.expr_inner(deref.inner()),
+            },
+            &ExprEnum::FunctionCall(ref call) => {
+                match call.callee() {
+                    &Callee::StaticFunc(ref name) => {
+                        let text = format!("{}{}", name, type_args(call.type_args()));
+                        self.write(&text);
+                    }
+                    &Callee::Method(ref e) | &Callee::Value(ref e) => self.expr(e, POSTFIX),
+                }
+                self.write("(");
+                for (i, arg) in call.args().iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expr(arg, 0);
+                }
+                self.write(")");
+            }
+            &ExprEnum::Closure(ref closure) => {
+                let decl = closure.decl();
+                let arguments = decl
+                    .args()
+                    .iter()
+                    .map(|a| format!("{} {}", type_name(a.type_var().as_ref().unwrap()), a.name()))
+                    .collect::<Vec<String>>();
+                let header = format!("fn{} ", signature(&[], &arguments, decl.ret_type()));
+                self.write(&header);
+                self.block(decl.scope());
+            }
+        }
+    }
+}
+
+/// Returns the init and the loop of a scope written as a for, see `Statement::for_statement`.
+fn for_loop(statements: &[Statement]) -> Option<(&Statement, &WhileStatement)> {
+    match statements {
+        &[ref init, Statement::WhileStatement(ref inner)] if inner.is_for() => {
+            match inner.statement() {
+                &Statement::Scope(ref s) if s.len() == 2 && is_simple(init) && is_simple(&s[1]) => {
+                    Some((init, inner))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Checks if the statement is written on one line ending with a semicolon.
+fn is_simple(statement: &Statement) -> bool {
+    match statement {
+        &Statement::ExprStatement(_)
+        | &Statement::Assignment(_)
+        | &Statement::Declaration(_)
+        | &Statement::ReturnStatement(_)
+        | &Statement::BreakStatement => true,
+        _ => false,
+    }
+}
+
+/// Returns the receiver, the name and the argument of a call written with an operator : a + b, a[b].
+/// The parser turns them into method calls, without the dereference of the method written with a dot.
+fn operator_call(expr: &Expr) -> Option<(&Expr, &str, &Expr)> {
+    if let &ExprEnum::FunctionCall(ref call) = expr.expr() {
+        if let (&Callee::Method(ref method), 1) = (call.callee(), call.args().len()) {
+            if let &ExprEnum::GetAttr(ref attr) = method.expr() {
+                if let &ExprEnum::Identifier(ref name) = attr.rhs().expr() {
+                    return Some((attr.lhs(), name, &call.args()[0]));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Returns the symbol and the priority of the binary operator calling the given method.
+fn binary_operator(method: &str) -> Option<(&'static str, u8)> {
+    match method {
+        "oror" => Some(("||", 1)),
+        "andand" => Some(("&&", 1)),
+        "equals" => Some(("==", 2)),
+        "ne" => Some(("!=", 2)),
+        "ge" => Some((">=", 3)),
+        "greater" => Some((">", 3)),
+        "le" => Some(("<=", 3)),
+        "less" => Some(("<", 3)),
+        "add" => Some(("+", 4)),
+        "minus" => Some(("-", 4)),
+        "times" => Some(("*", 5)),
+        "divide" => Some(("/", 5)),
+        "modulo" => Some(("%", 5)),
+        _ => None,
+    }
+}
+
+/// Returns the priority of the expression, the parser binds the highest ones first.
+fn priority(expr: &Expr) -> u8 {
+    match operator_call(expr).and_then(|(_, name, _)| binary_operator(name)) {
+        Some((_, priority)) => priority,
+        None => match expr.expr() {
+            &ExprEnum::Unary(_) => UNARY,
+            _ => POSTFIX,
+        },
+    }
+}
+
+/// Writes a literal like the scanner reads it, the bools are numbers typed as bools.
+fn literal_text(literal: &LiteralExpr, literal_type: &Option<LisaaType>) -> String {
+    match literal {
+        &LiteralExpr::NUMBER(n) if literal_type == &Some(LisaaType::Bool) => {
+            (if n == 0.0 { "false" } else { "true" }).to_string()
+        }
+        &LiteralExpr::NUMBER(n) => n.to_string(),
+        &LiteralExpr::STRING(ref s) => format!("\"{}\"", s.replace('\n', "\\n").replace('\t', "\\t")),
+        &LiteralExpr::CHAR(c) => match c {
+            '\n' => "'\\n'".to_string(),
+            '\'' => "'\\''".to_string(),
+            '\\' => "'\\\\'".to_string(),
+            c => format!("'{}'", c),
+        },
+    }
+}
+
+/// Returns the first and the last line of the expression, without the bodies of its closures.
+fn lines(expr: &Expr) -> (usize, usize) {
+    let mut bounds = (usize::max_value(), 0);
+    expr_lines(expr, &mut bounds);
+    bounds
+}
+
+fn statement_lines(statement: &Statement, bounds: &mut (usize, usize)) {
+    match statement {
+        &Statement::ExprStatement(ref e) | &Statement::ReturnStatement(ref e) => {
+            expr_lines(e, bounds)
+        }
+        &Statement::Assignment(ref a) => {
+            expr_lines(a.assignee(), bounds);
+            expr_lines(a.expr(), bounds);
+        }
+        &Statement::Declaration(ref d) => {
+            expr_lines(d.assignment().assignee(), bounds);
+            expr_lines(d.expr(), bounds);
+        }
+        _ => (),
+    }
+}
+
+fn expr_lines(expr: &Expr, bounds: &mut (usize, usize)) {
+    let line = expr.span().line;
+    *bounds = (bounds.0.min(line), bounds.1.max(line));
+    match expr.expr() {
+        &ExprEnum::Unary(ref u) => expr_lines(u.expression(), bounds),
+        &ExprEnum::GetAttr(ref attr) => {
+            expr_lines(attr.lhs(), bounds);
+            expr_lines(attr.rhs(), bounds);
+        }
+        &ExprEnum::Deref(ref d) => expr_lines(d.inner(), bounds),
+        &ExprEnum::FunctionCall(ref call) => {
+            match call.callee() {
+                &Callee::Method(ref e) | &Callee::Value(ref e) => expr_lines(e, bounds),
+                &Callee::StaticFunc(_) => (),
+            }
+            for arg in call.args() {
+                expr_lines(arg, bounds);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Writes a type like it is written in the code : num, Point<num>, fn(num) -> num.
+fn type_name(t: &LisaaType) -> String {
+    match t {
+        &LisaaType::Class(ref name, ref args) => format!("{}{}", name, type_list(args)),
+        &LisaaType::FunctionPointer(ref args, ref ret) => {
+            let args = args.iter().map(type_name).collect::<Vec<String>>();
+            format!("fn{}", signature(&[], &args, ret))
+        }
+        t => t.to_string(),
+    }
+}
+
+/// <num, Point>, nothing if there are no types.
+fn type_list(types: &[LisaaType]) -> String {
+    match types.is_empty() {
+        true => String::new(),
+        false => format!("<{}>", types.iter().map(type_name).collect::<Vec<String>>().join(", ")),
+    }
+}
+
+/// The type arguments of a call : ::<num>
+fn type_args(types: &[LisaaType]) -> String {
+    match types.is_empty() {
+        true => String::new(),
+        false => format!("::{}", type_list(types)),
+    }
+}
+
+/// <T : Add, E : Mul>
+fn type_params(params: &[TypeParam]) -> String {
+    match params.is_empty() {
+        true => String::new(),
+        false => format!(
+            "<{}>",
+            params
+                .iter()
+                .map(|p| format!("{} : {}", p.name(), p.trait_name()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// <T : Add>(T a, num b) -> T
+fn signature(params: &[TypeParam], arguments: &[String], ret: &LisaaType) -> String {
+    let ret = match ret {
+        &LisaaType::Void => String::new(),
+        ret => format!(" -> {}", type_name(ret)),
+    };
+    format!("{}({}){}", type_params(params), arguments.join(", "), ret)
+}
+
+#[cfg(test)]
+mod tests_format {
+    use super::*;
+
+    fn formatted(source: &str) -> String {
+        format(source, "test.lisaa").unwrap()
+    }
+
+    #[test]
+    fn test_canonical_style() {
+        let source = "import string\nimport geometry::{Point,\n   Box}\ntrait A = method a(Self) -> Self;\n\
+                      trait B = A+method b<T : A>(T, num);\npub Class Pair<T : A>{\n\tT first = empty::<T>();\n\
+                      }\nmethod sum<T : A>(num a)-> fn(num) -> num of Pair<T>{\nif a<2 return x;else if !a \
+                      {b();}else {c();}\nfor (num i=0;i<3;i=i+1;) d[i] = (a+b)*c-(d-e)-f;\n\
+                      while a&&(b||c) {break;}\nreturn fn (num x) -> num {return -(x+1);};\n}";
+        let expected = "import string\nimport geometry::{Point, Box}\n\n\
+                        trait A = method a(Self) -> Self;\ntrait B = A + method b<T : A>(T, num);\n\n\
+                        pub Class Pair<T : A> {\n    T first = empty::<T>();\n}\n\n\
+                        method sum<T : A>(num a) -> fn(num) -> num of Pair<T> {\n    if a < 2 {\n        \
+                        return x;\n    } else if !a {\n        b();\n    } else {\n        c();\n    }\n    \
+                        for (num i = 0; i < 3; i = i + 1;) {\n        d[i] = (a + b) * c - (d - e) - f;\n    }\n    \
+                        while a && (b || c) {\n        break;\n    }\n    return fn(num x) -> num {\n        \
+                        return -(x + 1);\n    };\n}\n";
+        assert_eq!(formatted(source), expected);
+        assert_eq!(formatted(expected), expected);
+    }
+
+    #[test]
+    fn test_keeps_the_methods_written_with_a_dot() {
+        let source = "fn main() {\n    (a.add(b) + c[1]).toString();\n    x = 'a' == '\\n' || true;\n}\n";
+        assert_eq!(formatted(source), source);
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let source = "// the module\n\nimport string // strings\n// the entry point\nfn main() {\n\n\
+                      \tnum a = 1; // one\n\n\n\t// two\n\tnum b = 2;\n\tif a {\n\t\tb = 3;\n\t\t// end of if\n\
+                      \t} // after if\n\t// end of main\n}\n// the end\n";
+        let expected = "// the module\n\nimport string // strings\n\n// the entry point\nfn main() {\n\
+                        \x20   num a = 1; // one\n\n    // two\n    num b = 2;\n    if a {\n        b = 3;\n\
+                        \x20       // end of if\n    } // after if\n    // end of main\n}\n// the end\n";
+        assert_eq!(formatted(source), expected);
+        assert_eq!(formatted(expected), expected);
+    }
+
+    #[test]
+    fn test_comments_inside_statements() {
+        let source = "fn main() {\n    num a = f(1, // first\n        2); // end\n    if a {\n        a = 1;\n    } // after if\n\
+                      \x20   // before else\n    else {\n        a = 2;\n    }\n}\n";
+        let expected = "fn main() {\n    // first\n    num a = f(1, 2); // end\n    if a {\n        a = 1;\n\
+                        \x20       // after if\n        // before else\n    } else {\n        a = 2;\n    }\n}\n";
+        assert_eq!(formatted(source), expected);
+        assert_eq!(formatted(expected), expected);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = format("fn main() {\n    num a = ;\n}\n", "test.lisaa").unwrap_err();
+        assert_eq!(errors[0].span().as_ref().map(|s| s.line), Some(2));
+    }
+}
@@ -45,28 +45,114 @@ impl<'a> Inferer<'a> {
 
     /// Given enough information in the inferer find the correct function/mehtod to call.
     /// Returns the function alongside with the declaration, the original type parameters and the actual types given.
-    pub fn infer(&self, line : usize) -> Result<(FunctionSig, Option<(&'a FunctionDecl, Vec<TypeParam>, Vec<LisaaType>)>), String> {
+    /// When several overloads match, they are ranked : first the ones declaring exactly the types given,
+    /// then the ones with the fewest type parameters. Two overloads of the same rank are ambiguous.
+    pub fn infer(&self) -> Result<(FunctionSig, Option<(&'a FunctionDecl, Vec<TypeParam>, Vec<LisaaType>)>), String> {
+        let mut candidates = vec![];
         for (f, d) in self.functions {
             if f.name() == &self.func_name && f.args.len() == self.given_argument_types.len() {
-                match self.is_match(f) {
-                    Some((f, gen, act)) => return Ok((f, Some((d, gen, act)))),
-                    None => (),
-                };
+                if let Some((sig, gen, act)) = self.is_match(f) {
+                    candidates.push((self.rank(f, &act), f, (sig, Some((d, gen, act)))));
+                }
             }
         }
-        for f in self.local_functions {
-            if f.name() == &self.func_name && f.args.len() == self.given_argument_types.len(){
-                match self.is_match(f) {
-                    Some(f) => return Ok((f.0, None)),
-                    None => (),
-                };
+        if candidates.is_empty() {
+            for f in self.local_functions {
+                if f.name() == &self.func_name && f.args.len() == self.given_argument_types.len(){
+                    if let Some((sig, _, act)) = self.is_match(f) {
+                        candidates.push((self.rank(f, &act), f, (sig, None)));
+                    }
+                }
             }
         }
-        Err(format!(
-            "could not find a function satisfying type constraints for : {:?} line : {}",
-            self.func_name,
-            line,
-        ))
+        if candidates.is_empty() {
+            return Err(format!(
+                "could not find a function satisfying type constraints for : {:?}",
+                self.func_name,
+            ));
+        }
+        candidates.sort_by_key(|c| c.0);
+        let mut tied = candidates
+            .iter()
+            .take_while(|c| c.0 == candidates[0].0)
+            .map(|c| c.1.to_string())
+            .collect::<Vec<String>>();
+        if tied.len() > 1 {
+            tied.sort();
+            return Err(format!(
+                "ambiguous call to {:?}, the arguments match : {}",
+                self.func_name,
+                tied.join(" and ")
+            ));
+        }
+        Ok(candidates.swap_remove(0).2)
+    }
+
+    /// Ranks a matching overload, the lowest is chosen :
+    /// 0 if it declares exactly the types given, 1 otherwise, then its number of type parameters.
+    fn rank(&self, sig: &FunctionSig, actual: &Vec<LisaaType>) -> (usize, usize) {
+        let exact = sig
+            .args
+            .iter()
+            .zip(self.given_argument_types.iter())
+            .all(|(arg, given)| {
+                Self::replace_gen(arg, &sig.type_args, actual)
+                    .max_deref()
+                    .0
+                    .is_equivalent(&given.max_deref().0)
+            });
+        (!exact as usize, sig.type_args.len())
+    }
+
+    /// Finds the function referenced by name, to use it as a value.
+    /// There is no call to infer the type parameters from so they must all be given,
+    /// and the function must not be overloaded.
+    pub fn infer_reference(&self) -> Result<(FunctionSig, (&'a FunctionDecl, Vec<TypeParam>, Vec<LisaaType>)), String> {
+        let mut candidates = self
+            .functions
+            .iter()
+            .filter(|&(f, _)| f.name() == &self.func_name && f.self_type.is_none());
+        let (f, d) = candidates
+            .next()
+            .ok_or(format!("Unknown variable : {}", self.func_name))?;
+        if candidates.next().is_some() {
+            return Err(format!(
+                "can not take a reference to the overloaded function {}",
+                self.func_name
+            ));
+        }
+        if d.is_inline() {
+            return Err(format!(
+                "can not take a reference to the native function {}",
+                self.func_name
+            ));
+        }
+        if f.type_args.len() != self.given_generics.len() {
+            return Err(format!(
+                "the function {} takes {} type parameters, got {}",
+                self.func_name,
+                f.type_args.len(),
+                self.given_generics.len()
+            ));
+        }
+        if !self.check_type_constraints(&f.type_args, &self.given_generics) {
+            return Err(format!(
+                "could not find a function satisfying type constraints for : {:?}",
+                self.func_name,
+            ));
+        }
+        let generics = &f.type_args;
+        let sig = FunctionSig::new_simple_args(
+            vec![],
+            f.args
+                .iter()
+                .map(|arg| Self::replace_gen(arg, generics, &self.given_generics))
+                .collect(),
+            Self::replace_gen(&f.ret_type, generics, &self.given_generics),
+            f.name().clone(),
+            None,
+        );
+        Ok((sig, (d, generics.clone(), self.given_generics.clone())))
     }
 
     /// Try to match the two functions :
@@ -74,7 +160,7 @@ impl<'a> Inferer<'a> {
     /// Returns the morphised signature
     pub fn is_match(&self, sig: &FunctionSig) -> Option<(FunctionSig, Vec<TypeParam>, Vec<LisaaType>)> {
         if !self.given_generics.is_empty() && sig.type_args.len() == self.given_generics.len() {
-            if self.check_type_constraints(&sig.type_args, &self.given_argument_types) {
+            if self.check_type_constraints(&sig.type_args, &self.given_generics) {
                 Some(self.get_sig_from_generics(sig, self.given_generics.clone()))
             } else {
                 None
@@ -84,6 +170,19 @@ impl<'a> Inferer<'a> {
         }
     }
 
+    /// Checks if the arguments declared, with the generics replaced, are the types given.
+    fn takes_given_types(&self, sig: &FunctionSig, actual: &Vec<LisaaType>) -> bool {
+        sig.args
+            .iter()
+            .zip(self.given_argument_types.iter())
+            .all(|(arg, given)| {
+                Self::replace_gen(arg, &sig.type_args, actual)
+                    .max_deref()
+                    .0
+                    .is_equivalent(&given.max_deref().0)
+            })
+    }
+
     /// Returns a signature from a signature and the actual types given to the generics.
     pub fn get_sig_from_generics(&self, orig: &FunctionSig, actual: Vec<LisaaType>) -> (FunctionSig, Vec<TypeParam>, Vec<LisaaType>){
         let generics = &orig.type_args;
@@ -147,6 +246,12 @@ impl<'a> Inferer<'a> {
                         .collect(),
                 ),
             },
+            LisaaType::FunctionPointer(args, ret) => LisaaType::function_pointer(
+                args.iter()
+                    .map(|a| Self::replace_gen(a, gens, actual))
+                    .collect(),
+                Self::replace_gen(ret, gens, actual),
+            ),
             e => e.clone(),
         }
     }
@@ -208,6 +313,13 @@ impl<'a> Inferer<'a> {
             }
             return occurences;
         }
+        if let (&LisaaType::FunctionPointer(ref args, ref ret), &LisaaType::FunctionPointer(ref given_args, ref given_ret)) = (arg, given) {
+            let mut occurences = self.check_generic_in_class(gen_name, ret, given_ret);
+            for (sub_arg, given_arg) in args.iter().zip(given_args.iter()) {
+                occurences.append(&mut self.check_generic_in_class(gen_name, sub_arg, given_arg));
+            }
+            return occurences;
+        }
         vec![]
     }
 
@@ -224,23 +336,21 @@ impl<'a> Inferer<'a> {
     }
 
     /// Checks that a type respects the bound on a generic :
-    /// We need to find a method on the type that is ok
+    /// We need to find a method on the type for each method of the trait
     /// eg : method add(Self) -> Self
     /// for each candidate : Point::add
-    /// check that the function has first generic Self and return type self
+    /// The methods are found by name, their signatures are not compared yet.
     pub fn check_type_constraint(&self, type_param: &TypeParam, arg: &LisaaType) -> bool {
+        let type_name = arg.max_deref().0.name();
         self.traits
             .get(type_param.trait_name())
             .iter()
             .flat_map(|a| a.iter())
-            .map(|(name, sig)| (format!("{}::{}", arg.name(), name), sig))
-            .all(|(method_name, method_sig)| {
-                //"check for : {:?}", method_name);
-                for f in self.functions.iter().filter(|f| f.0.name() == &method_name) {
-
-                    //println!("check : {:?}{:?}", f.0, method_sig);
-                }
-                true
+            .filter(|&(name, _)| !name.is_empty())
+            .map(|(name, _)| format!("{}::{}", type_name, name))
+            .all(|method_name| {
+                self.functions.keys().any(|f| f.name() == &method_name)
+                    || self.local_functions.iter().any(|f| f.name() == &method_name)
             })
     }
 }
@@ -177,11 +177,16 @@ impl Interpreter {
     /// Interprets an if statement.
     pub fn if_statement(&self, statement: &IfStatement) -> Result<StatementResult, String> {
         let res = self.evaluate(statement.condition())?;
-        if self.is_true(&res) {
-            let res = self.run_statement(statement.statement())?;
-            if res.is_quit() {
-                return Ok(res);
-            }
+        let branch = match self.is_true(&res) {
+            true => statement.statement(),
+            false => match statement.else_statement() {
+                Some(else_statement) => else_statement,
+                None => return Ok(StatementResult::Empty),
+            },
+        };
+        let res = self.run_statement(branch)?;
+        if res.is_quit() {
+            return Ok(res);
         }
         Ok(StatementResult::Empty)
     }
@@ -21,6 +21,7 @@ lazy_static! {
         m.insert("if",     TokenType::IF);
         m.insert("nil",    TokenType::NIL);
         m.insert("or",     TokenType::OR);
+        m.insert("pub",    TokenType::PUB);
         m.insert("return", TokenType::RETURN);
         m.insert("super",  TokenType::SUPER);
         m.insert("this",   TokenType::THIS);
@@ -12,14 +12,22 @@ extern crate time;
 extern crate lazy_static;
 
 mod compile;
+mod diagnostic;
+mod disassemble;
+mod embed;
 //mod compile_req;
 mod expression;
 mod generic_inference;
 mod keywords;
+mod lint;
 pub mod lisaa;
+pub mod lsp;
+mod module;
 mod monomorphise;
 mod native;
+mod optimize;
 mod parser;
+mod repl;
 mod scanner;
 pub mod script;
 mod statement;
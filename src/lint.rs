@@ -0,0 +1,623 @@
+//! The lint pass : looks for the code that typechecks but is probably a mistake.
+//!
+//! It walks the typed declarations of the modules given, before their monomorphisation,
+//! so a generic function is linted once and the functions never called are linted too.
+//! Each lint can be allowed, shown as a warning (the default) or denied : its problems are errors.
+use diagnostic::{Diagnostic, Span};
+use expression::{Callee, Expr, ExprEnum, LiteralExpr};
+use module::{self, Module};
+use statement::{ClassDecl, FunctionDecl, FunctionSig, Program, Statement, TraitDecl, TypeParam};
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use types::LisaaType;
+
+/// A problem looked for by the lint pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A function returning a value whose end can be reached, it would return 0.
+    MissingReturn,
+    /// A local variable that is never read.
+    UnusedVariable,
+    /// A statement after a return, a break or a loop that never ends.
+    UnreachableCode,
+    /// A local variable named like a variable of an enclosing scope.
+    ShadowedVariable,
+    /// An import that brings nothing the module uses.
+    UnusedImport,
+    /// A type parameter of a function or a class that no type uses.
+    UnusedTypeParam,
+}
+
+/// All the lints, in the order they are listed to the user.
+pub const LINTS: [Lint; 6] = [
+    Lint::MissingReturn,
+    Lint::UnusedVariable,
+    Lint::UnreachableCode,
+    Lint::ShadowedVariable,
+    Lint::UnusedImport,
+    Lint::UnusedTypeParam,
+];
+
+impl Lint {
+    /// Returns the name of the lint, as given on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Lint::MissingReturn => "missing-return",
+            &Lint::UnusedVariable => "unused-variable",
+            &Lint::UnreachableCode => "unreachable-code",
+            &Lint::ShadowedVariable => "shadowed-variable",
+            &Lint::UnusedImport => "unused-import",
+            &Lint::UnusedTypeParam => "unused-type-param",
+        }
+    }
+
+    /// Finds the lint with the given name.
+    pub fn from_name(name: &str) -> Option<Lint> {
+        LINTS.iter().cloned().find(|l| l.name() == name)
+    }
+}
+
+/// What is done with the problems found by a lint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// The problems are not reported.
+    Allow,
+    /// The problems are shown as warnings, the program still runs.
+    Warn,
+    /// The problems are errors, the compilation is aborted.
+    Deny,
+}
+
+/// Lints the declarations of the given files in the typechecked program.
+/// The lints without a level are warnings, the problems found are sorted by position.
+pub fn lint(program: &Program, files: &[String], levels: &HashMap<Lint, Level>) -> Vec<Diagnostic> {
+    let mut linter = Linter::new(levels);
+    let linted = |span: Option<&Span>| span.filter(|s| files.contains(&s.file)).cloned();
+    for function in program.functions().values() {
+        match linted(function.span.as_ref()) {
+            Some(ref span) if !has_bytecode(&function.scope) => {
+                linter.function(function, &function.name(), span);
+                linter.type_params(function.type_args(), span);
+                linter.keep_names(&span.file);
+            }
+            _ => (),
+        }
+    }
+    for class in program.classes().values() {
+        if let Some(span) = linted(class.span()) {
+            linter.class(class, &span);
+            linter.keep_names(&span.file);
+        }
+    }
+    for tr in program.traits().values() {
+        if let Some(span) = linted(tr.span()) {
+            linter.trait_decl(tr);
+            linter.keep_names(&span.file);
+        }
+    }
+    for module in program.modules().iter().filter(|m| files.contains(m.file())) {
+        linter.imports(module, program.modules());
+    }
+    let mut found = linter.found;
+    found.sort_by_key(|d| {
+        d.span()
+            .as_ref()
+            .map(|s| (s.file.clone(), s.line, s.column))
+    });
+    found
+}
+
+/// A local variable of the function being linted.
+struct Local {
+    name: String,
+    /// Where it is declared, None for the arguments.
+    span: Option<Span>,
+    used: bool,
+}
+
+/// How a statement ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum End {
+    /// The next statement is executed, at least sometimes.
+    Falls,
+    /// It leaves the loop.
+    Breaks,
+    /// It leaves the function, or never ends.
+    Returns,
+}
+
+/// Walks the declarations, knowing the local variables in scope.
+struct Linter<'a> {
+    levels: &'a HashMap<Lint, Level>,
+    found: Vec<Diagnostic>,
+    /// The local variables of the function, the ones of the inner scopes are last.
+    scopes: Vec<Vec<Local>>,
+    /// The functions, classes, traits and type parameters used by the declaration linted.
+    names: HashSet<String>,
+    /// The names used by the declarations of each file, to find the unused imports.
+    used: HashMap<String, HashSet<String>>,
+    /// The position of the function linted, for the statements that have none.
+    context: Span,
+}
+
+impl<'a> Linter<'a> {
+    fn new(levels: &'a HashMap<Lint, Level>) -> Self {
+        Linter {
+            levels: levels,
+            found: vec![],
+            scopes: vec![],
+            names: HashSet::new(),
+            used: HashMap::new(),
+            context: Span::default(),
+        }
+    }
+
+    /// Reports a problem as its lint is configured, the name of the lint follows the message.
+    fn report(&mut self, lint: Lint, message: String, span: &Span) {
+        let message = format!("{} [{}]", message, lint.name());
+        match self.levels.get(&lint).cloned().unwrap_or(Level::Warn) {
+            Level::Allow => (),
+            Level::Warn => self.found.push(Diagnostic::warning(message, span.clone())),
+            Level::Deny => self.found.push(Diagnostic::at(message, span.clone())),
+        }
+    }
+
+    /// Adds the names used by the declaration linted to the ones of its file.
+    fn keep_names(&mut self, file: &str) {
+        let names = mem::replace(&mut self.names, HashSet::new());
+        self.used
+            .entry(file.to_string())
+            .or_insert_with(HashSet::new)
+            .extend(names);
+    }
+
+    /// Lints a function or a closure, the label names it in the messages.
+    fn function(&mut self, function: &FunctionDecl, label: &str, span: &Span) {
+        let context = mem::replace(&mut self.context, span.clone());
+        if function.ret_type() != &LisaaType::Void && end(&function.scope) != End::Returns {
+            self.report(
+                Lint::MissingReturn,
+                format!("{} can reach its end without returning a value", label),
+                span,
+            );
+        }
+        self.signature(function.signature());
+        self.scopes.push(
+            function
+                .args()
+                .iter()
+                .map(|a| Local {
+                    name: a.name().to_string(),
+                    span: None,
+                    used: false,
+                })
+                .collect(),
+        );
+        self.statement(&function.scope);
+        self.leave_scope();
+        self.context = context;
+    }
+
+    /// Reports the type parameters that the types of the declaration do not use.
+    fn type_params(&mut self, params: &[TypeParam], span: &Span) {
+        for param in params {
+            if !self.names.contains(param.name()) {
+                let message = format!("unused type parameter : {}", param.name());
+                self.report(Lint::UnusedTypeParam, message, span);
+            }
+        }
+    }
+
+    fn class(&mut self, class: &ClassDecl, span: &Span) {
+        let context = mem::replace(&mut self.context, span.clone());
+        for attr in class.attributes() {
+            self.type_names(attr.val_type());
+            self.scopes.push(vec![]);
+            self.expr(attr.expr());
+            self.scopes.pop();
+        }
+        self.type_params(class.type_params(), span);
+        for param in class.type_params() {
+            self.names.insert(param.trait_name().clone());
+        }
+        self.context = context;
+    }
+
+    fn trait_decl(&mut self, tr: &TraitDecl) {
+        self.names.extend(tr.sub_traits().iter().cloned());
+        for method in tr.methods().values() {
+            self.signature(method);
+        }
+    }
+
+    /// Reports the imports of the module that bring nothing its declarations use.
+    /// A public import is not reported, the modules importing this one may use it.
+    fn imports(&mut self, module: &Module, modules: &[Module]) {
+        let empty = HashSet::new();
+        let used = self.used.get(module.file()).unwrap_or(&empty).clone();
+        for import in module.imports().iter().filter(|i| !i.public) {
+            match import.names {
+                Some(ref names) => {
+                    for name in names.iter() {
+                        let method = format!("{}::", name);
+                        if !used.contains(name) && !used.iter().any(|u| u.starts_with(&method)) {
+                            let message = format!("unused import : {} from {}", name, import.path);
+                            self.report(Lint::UnusedImport, message, &import.span);
+                        }
+                    }
+                }
+                None => {
+                    let brought = module::imported(modules, import);
+                    if !used.iter().any(|u| brought.iter().any(|m| m.declares(u))) {
+                        let message = format!("unused import : {}", import.path);
+                        self.report(Lint::UnusedImport, message, &import.span);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reports the unused variables of the innermost scope and leaves it.
+    fn leave_scope(&mut self) {
+        for local in self.scopes.pop().unwrap_or(vec![]) {
+            if let (false, Some(span)) = (local.used, local.span) {
+                let message = format!("unused variable : {}", local.name);
+                self.report(Lint::UnusedVariable, message, &span);
+            }
+        }
+    }
+
+    /// Adds a local variable to the innermost scope, reporting the one it shadows.
+    fn declare(&mut self, name: &str, span: &Span) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.iter())
+            .rev()
+            .find(|l| l.name == name)
+            .map(|l| l.span.clone());
+        let message = match shadowed {
+            Some(Some(previous)) => Some(format!(
+                "{} shadows the variable declared at line {}",
+                name, previous.line
+            )),
+            Some(None) => Some(format!("{} shadows the argument {}", name, name)),
+            None => None,
+        };
+        if let Some(message) = message {
+            self.report(Lint::ShadowedVariable, message, span);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Local {
+                name: name.to_string(),
+                span: Some(span.clone()),
+                used: false,
+            });
+        }
+    }
+
+    /// Marks the variable with the given name as used, the names of functions are not variables.
+    fn use_var(&mut self, name: &str) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .flat_map(|scope| scope.iter_mut())
+            .rev()
+            .find(|l| l.name == name);
+        if let Some(local) = local {
+            local.used = true;
+        }
+    }
+
+    /// Walks the statements of a scope, reporting the first one that can not be reached.
+    fn statements(&mut self, statements: &[Statement]) {
+        let mut reachable = true;
+        for (i, statement) in statements.iter().enumerate() {
+            if reachable && i > 0 && end(&statements[i - 1]) != End::Falls {
+                let span = statement_span(statement).unwrap_or(self.context.clone());
+                self.report(Lint::UnreachableCode, "unreachable statement".to_string(), &span);
+                reachable = false;
+            }
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            &Statement::ExprStatement(ref e) | &Statement::ReturnStatement(ref e) => self.expr(e),
+            &Statement::Assignment(ref a) => {
+                self.expr(a.expr());
+                match a.assignee().expr() {
+                    // assigning a variable is not reading it.
+                    &ExprEnum::Identifier(_) => (),
+                    _ => self.expr(a.assignee()),
+                }
+            }
+            &Statement::Declaration(ref d) => {
+                self.type_names(d.val_type());
+                self.expr(d.expr());
+                self.declare(d.name(), d.assignment().assignee().span());
+            }
+            &Statement::Scope(ref statements) => {
+                self.scopes.push(vec![]);
+                self.statements(statements);
+                self.leave_scope();
+            }
+            &Statement::IfStatement(ref i) => {
+                self.expr(i.condition());
+                self.branch(i.statement());
+                if let Some(else_statement) = i.else_statement() {
+                    self.branch(else_statement);
+                }
+            }
+            &Statement::WhileStatement(ref w) => {
+                self.expr(w.condition());
+                match w.statement() {
+                    // the repeated statement of a for follows the body, even if it ends with a break.
+                    &Statement::Scope(ref body) if w.is_for() => {
+                        self.scopes.push(vec![]);
+                        for s in body {
+                            self.statement(s);
+                        }
+                        self.leave_scope();
+                    }
+                    body => self.branch(body),
+                }
+            }
+            &Statement::BreakStatement | &Statement::Native(_) => (),
+        }
+    }
+
+    /// Walks a statement executed or not, it has its own scope.
+    fn branch(&mut self, statement: &Statement) {
+        self.scopes.push(vec![]);
+        self.statement(statement);
+        self.leave_scope();
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        if let &Some(ref t) = expr.return_type_uncheck() {
+            self.type_names(t);
+        }
+        match expr.expr() {
+            &ExprEnum::Unary(ref u) => self.expr(u.expression()),
+            // the right hand side is the name of an attribute or a method.
+            &ExprEnum::GetAttr(ref attr) => self.expr(attr.lhs()),
+            &ExprEnum::Literal(_) => (),
+            &ExprEnum::Identifier(ref name) => self.use_var(name),
+            &ExprEnum::FunctionCall(ref call) => {
+                if let Some(sig) = call.checked_signature() {
+                    self.names.insert(sig.name.clone());
+                }
+                for t in call.type_args() {
+                    self.type_names(t);
+                }
+                match call.callee() {
+                    &Callee::Method(ref e) | &Callee::Value(ref e) => self.expr(e),
+                    &Callee::StaticFunc(ref name) => {
+                        self.names.insert(name.clone());
+                    }
+                }
+                for arg in call.args() {
+                    self.expr(arg);
+                }
+            }
+            &ExprEnum::Deref(ref d) => self.expr(d.inner()),
+            &ExprEnum::FunctionRef(ref reference) => {
+                let name = reference
+                    .checked_signature()
+                    .map_or(reference.name(), |sig| &sig.name)
+                    .clone();
+                self.names.insert(name);
+                for t in reference.type_args() {
+                    self.type_names(t);
+                }
+            }
+            &ExprEnum::Closure(ref c) => self.function(c.decl(), "the closure", expr.span()),
+        }
+    }
+
+    /// Adds the classes and traits a signature uses to the names used.
+    fn signature(&mut self, sig: &FunctionSig) {
+        for arg in sig.args.iter() {
+            self.type_names(arg);
+        }
+        self.type_names(&sig.ret_type);
+        if let Some(ref self_type) = sig.self_type {
+            self.type_names(self_type);
+        }
+        for param in sig.type_args.iter() {
+            self.names.insert(param.trait_name().clone());
+        }
+    }
+
+    /// Adds the classes and type parameters a type uses to the names used.
+    fn type_names(&mut self, t: &LisaaType) {
+        match t {
+            &LisaaType::Class(ref name, ref params) => {
+                self.names.insert(name.clone());
+                for param in params {
+                    self.type_names(param);
+                }
+            }
+            &LisaaType::Pointer(ref inner) => self.type_names(inner),
+            &LisaaType::Function(ref caller, _, ref args) => {
+                self.type_names(caller);
+                for arg in args {
+                    self.type_names(arg);
+                }
+            }
+            &LisaaType::FunctionPointer(ref args, ref ret) => {
+                for arg in args {
+                    self.type_names(arg);
+                }
+                self.type_names(ret);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Finds how the statement ends, a loop whose condition is true and that has no break never ends.
+fn end(statement: &Statement) -> End {
+    match statement {
+        &Statement::ReturnStatement(_) => End::Returns,
+        &Statement::BreakStatement => End::Breaks,
+        &Statement::Scope(ref statements) => statements
+            .iter()
+            .map(end)
+            .find(|e| e != &End::Falls)
+            .unwrap_or(End::Falls),
+        &Statement::IfStatement(ref i) => {
+            match (end(i.statement()), i.else_statement().map(end)) {
+                (End::Falls, _) | (_, None) | (_, Some(End::Falls)) => End::Falls,
+                (End::Returns, Some(End::Returns)) => End::Returns,
+                _ => End::Breaks,
+            }
+        }
+        &Statement::WhileStatement(ref w) if is_true(w.condition()) && !breaks(w.statement()) => {
+            End::Returns
+        }
+        _ => End::Falls,
+    }
+}
+
+/// Checks if the statement can break the loop it is in, the breaks of the inner loops do not.
+fn breaks(statement: &Statement) -> bool {
+    match statement {
+        &Statement::BreakStatement => true,
+        &Statement::Scope(ref statements) => statements.iter().any(breaks),
+        &Statement::IfStatement(ref i) => {
+            breaks(i.statement()) || i.else_statement().map_or(false, breaks)
+        }
+        _ => false,
+    }
+}
+
+/// Checks if the function is written in bytecode, like the natives and the class constructors.
+fn has_bytecode(scope: &Statement) -> bool {
+    match scope {
+        &Statement::Native(_) => true,
+        &Statement::Scope(ref statements) => statements.iter().any(has_bytecode),
+        _ => false,
+    }
+}
+
+/// Checks if the condition is the literal true.
+fn is_true(condition: &Expr) -> bool {
+    match (condition.expr(), condition.return_type_uncheck()) {
+        (&ExprEnum::Literal(LiteralExpr::NUMBER(n)), &Some(LisaaType::Bool)) => n != 0.0,
+        _ => false,
+    }
+}
+
+/// Returns the position of the statement : its first expression, or the variable declared.
+fn statement_span(statement: &Statement) -> Option<Span> {
+    match statement {
+        &Statement::ExprStatement(ref e) | &Statement::ReturnStatement(ref e) => {
+            Some(e.span().clone())
+        }
+        &Statement::Assignment(ref a) => Some(a.assignee().span().clone()),
+        &Statement::Declaration(ref d) => Some(d.assignment().assignee().span().clone()),
+        &Statement::Scope(ref statements) => statements.iter().filter_map(statement_span).next(),
+        &Statement::IfStatement(ref i) => Some(i.condition().span().clone()),
+        &Statement::WhileStatement(ref w) => Some(w.condition().span().clone()),
+        &Statement::BreakStatement | &Statement::Native(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests_lint {
+    use super::*;
+    use parser::Parser;
+    use scanner::Scanner;
+    use typecheck::TypeChecker;
+
+    fn program(source: &str) -> Program {
+        let tokens = Scanner::new(source.to_string(), "test.lisaa".to_string())
+            .tokens()
+            .unwrap();
+        let (mut program, _) = Parser::new(tokens).program().unwrap();
+        TypeChecker::new().check_program(&mut program).unwrap();
+        program
+    }
+
+    fn lines(found: &[Diagnostic]) -> Vec<(usize, String)> {
+        found
+            .iter()
+            .map(|d| (d.span().as_ref().unwrap().line, d.message().clone()))
+            .collect()
+    }
+
+    fn check(source: &str) -> Vec<(usize, String)> {
+        lines(&lint(&program(source), &["test.lisaa".to_string()], &HashMap::new()))
+    }
+
+    #[test]
+    fn test_missing_return() {
+        let source = "fn sign(num a) -> num {\n    if a < 0 {\n        return 0 - 1;\n    }\n}\n\
+                      fn abs(num a) -> num {\n    if a < 0 return 0 - a; else return a;\n}\n\
+                      fn forever() -> num {\n    while true {\n        print(1);\n    }\n}";
+        assert_eq!(
+            check(source),
+            vec![(1, "sign can reach its end without returning a value [missing-return]".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_unused_and_shadowed_variables() {
+        let source = "fn main() {\n    num a = 1;\n    num b = 2;\n    b = 3;\n    if a > 0 {\n\
+                      num a = 4;\n        print(a);\n    }\n    for (num i = 0; i < 2; i = i + 1;) {\n\
+                      print(i);\n    }\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (3, "unused variable : b [unused-variable]".to_string()),
+                (6, "a shadows the variable declared at line 2 [shadowed-variable]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let source = "fn f(num a) -> num {\n    while a > 0 {\n        break;\n        a = a - 1;\n    }\n\
+                      for (num i = 0; i < a; i = i + 1;) {\n        return i;\n    }\n\
+                      return a;\n    print(a);\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (4, "unreachable statement [unreachable-code]".to_string()),
+                (10, "unreachable statement [unreachable-code]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_type_params() {
+        let source = "fn id<T : Any, U : Any>(T a) -> T {\n    return a;\n}\n\
+                      fn keep<T : Any>(num n) -> num {\n    fn(T) -> T f = fn(T x) -> T { return x; };\n\
+                      return n;\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (1, "unused type parameter : U [unused-type-param]".to_string()),
+                (5, "unused variable : f [unused-variable]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_levels() {
+        let source = "fn main() {\n    num a = 1;\n    num b = 2;\n    print(b);\n    num b = 3;\n}";
+        let mut levels = HashMap::new();
+        levels.insert(Lint::UnusedVariable, Level::Allow);
+        levels.insert(Lint::ShadowedVariable, Level::Deny);
+        let found = lint(&program(source), &["test.lisaa".to_string()], &levels);
+        assert_eq!(
+            lines(&found),
+            vec![(5, "b shadows the variable declared at line 3 [shadowed-variable]".to_string())]
+        );
+        assert!(!found[0].is_warning());
+        assert!(lint(&program(source), &[], &levels).is_empty());
+    }
+}
@@ -1,21 +1,60 @@
 //! The ytp module, where the interpreter just calls the other modules.
 use compile::Compiler;
+use diagnostic::Diagnostic;
+use disassemble::disassemble;
+use embed::Export;
+pub use embed::Instance;
+use lint;
+pub use lint::{Level, Lint, LINTS};
+use module::{self, Module};
 //use interpreter::Interpreter;lk
+use native::{get_ffi_func, get_native_type};
+use optimize::optimize;
 use parser::Parser;
+use repl::{is_incomplete, Input, Session};
 use scanner::Scanner;
-use statement::Program;
-use std::collections::HashSet;
+use statement::{FunctionDecl, FunctionSig, Program};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Component, Path, PathBuf};
 use time::PreciseTime;
 use typecheck::TypeChecker;
-use vm::Vm;
+use types::LisaaType;
+use vm::bytecode;
+use vm::ffi::NativeFunction;
+pub use vm::ffi::{NativeType, NativeValue};
+use vm::peephole;
+use vm::trace::Trace;
+use vm::{DebugInfo, Symbol, Vm, OP};
+
+/// What to trace during the execution, see vm::trace.
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    /// The file to write the trace to, stderr if None.
+    pub output: Option<String>,
+    /// Only trace these functions, by name (add) or by signature (add(num, num) -> num).
+    pub functions: Vec<String>,
+    /// Only trace these instruction ranges, start included end excluded.
+    pub ranges: Vec<(usize, usize)>,
+}
 
 /// The interpreter, contains the code.
 pub struct Lisaa<'a> {
     source: String,
     output_stream: &'a mut Write,
+    /// Where the compilation and runtime errors are shown, stderr by default.
+    error_stream: Box<Write + 'a>,
     verbose: bool,
+    sources: Sources,
+    trace: Option<TraceOptions>,
+    bounds_checks: bool,
+    /// The functions registered by the host, called by their position.
+    natives: Vec<NativeFunction>,
+    /// The level of the lints, the ones not given are warnings.
+    lints: HashMap<Lint, Level>,
+    /// The optimizations done before the compilation, none at level 0.
+    opt_level: usize,
 }
 impl<'a> Lisaa<'a> {
     /// Creates a new instance of the interpreter with the given source
@@ -23,100 +62,770 @@ impl<'a> Lisaa<'a> {
         Lisaa {
             source: source,
             output_stream: output,
+            error_stream: Box::new(io::stderr()),
             verbose: verbose,
+            sources: Sources {
+                modules: HashMap::new(),
+                read: HashMap::new(),
+                search_path: vec![],
+            },
+            trace: None,
+            bounds_checks: true,
+            natives: vec![],
+            lints: HashMap::new(),
+            opt_level: 0,
         }
     }
 
-    fn find_source(name: String) -> Result<String, String> {
-        if File::open(name.to_owned()).is_ok() {
-            Ok(name)
-        } else if File::open(format!("{}.lisaa", name.to_owned())).is_ok() {
-            Ok(format!("{}.lisaa", name.to_owned()))
-        } else {
-            Err(format!("cannot find source for {}", name))
-        }
+    /// Shows the errors in the given stream instead of stderr.
+    pub fn error_output(&mut self, errors: &'a mut Write) -> &mut Self {
+        self.error_stream = Box::new(errors);
+        self
     }
 
-    /// Finds the source associated with an import.
-    fn open_source(source_name: String) -> Result<String, String> {
-        let mut file = File::open(Lisaa::find_source(source_name.clone())?).unwrap();
+    /// Adds a module from memory, it can be imported by its name or be the source to run.
+    pub fn module(&mut self, name: &str, source: &str) -> &mut Self {
+        self.sources
+            .modules
+            .insert(name.to_string(), source.to_string());
+        self
+    }
 
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).map_err({
-            |e| {
-                format!(
-                    "could not read file : {},\
-                     error : {} ",
-                    source_name, e
-                )
-            }
-        })?;
-        Ok(contents)
+    /// Adds a directory where the imports are searched, after the directory of the importing file.
+    /// The directories are searched in the order they are added, before the standard library.
+    pub fn search_path(&mut self, dir: &str) -> &mut Self {
+        self.sources.search_path.push(dir.to_string());
+        self
     }
 
-    fn parse(&self) -> Result<Program, String> {
-        let mut to_import = vec![self.source.clone()];
-        let mut imported = HashSet::new();
-        let mut program = Program::empty();
+    /// Registers a Rust function that the scripts can call with the given name and signature.
+    /// The arguments are converted from the lisaa values and the result is converted back,
+    /// an error returned by the function stops the program like a runtime error.
+    ///
+    /// The compiled code calls the natives by their position :
+    /// a file compiled with emit must be run with the same natives, registered in the same order.
+    pub fn register<F>(
+        &mut self,
+        name: &str,
+        args: Vec<NativeType>,
+        ret: NativeType,
+        function: F,
+    ) -> &mut Self
+    where
+        F: FnMut(&[NativeValue]) -> Result<NativeValue, String> + 'static,
+    {
+        self.natives.push(NativeFunction::new(
+            name.to_string(),
+            args,
+            ret,
+            Box::new(function),
+        ));
+        self
+    }
 
-        while let Some(file) = to_import.pop() {
-            let tokens = Scanner::new(Lisaa::open_source(file)?).tokens()?;
-            let (mut tree, imports) = Parser::new(tokens).program().map_err(|e| {
-                for p_err in e.iter() {
-                    println!("{}\n", p_err);
-                }
-                String::from("Compilation aborted because of preceding errors.")
-            })?;
-            for imp in imports {
-                if !imported.contains(&imp) {
-                    to_import.push(imp.clone());
-                    imported.insert(imp);
-                }
+    /// Enables or disables the bounds checks of slice indexing, on by default.
+    pub fn bounds_checks(&mut self, enabled: bool) -> &mut Self {
+        self.bounds_checks = enabled;
+        self
+    }
+
+    /// Sets what is done with the problems found by a lint : they are warnings by default.
+    pub fn lint(&mut self, lint: Lint, level: Level) -> &mut Self {
+        self.lints.insert(lint, level);
+        self
+    }
+
+    /// Sets the optimization level, 0 by default compiles the code as it is written.
+    /// Level 1 folds the constant expressions, removes the branches that never run
+    /// and the functions that are never called.
+    /// Level 2 also replaces the sequences of instructions that have a cheaper equivalent.
+    pub fn optimize(&mut self, level: usize) -> &mut Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Traces the execution of the program.
+    pub fn trace(&mut self, options: TraceOptions) -> &mut Self {
+        self.trace = Some(options);
+        self
+    }
+
+    /// Creates the trace asked for, finding the functions to trace in the symbols.
+    fn make_trace(options: &TraceOptions, symbols: &[Symbol]) -> Result<Trace, String> {
+        let sink: Box<Write> = match options.output {
+            Some(ref path) => Box::new(
+                File::create(path)
+                    .map_err(|e| format!("could not create trace file : {}, error : {}", path, e))?,
+            ),
+            None => Box::new(io::stderr()),
+        };
+        let mut trace = Trace::new(sink);
+        for &(start, end) in options.ranges.iter() {
+            trace = trace.only(start, end);
+        }
+        for function in options.functions.iter() {
+            let found = symbols
+                .iter()
+                .filter(|s| &s.name == function || s.name.starts_with(&format!("{}(", function)))
+                .collect::<Vec<&Symbol>>();
+            if found.is_empty() {
+                return Err(format!("can not trace unknown function : {}", function));
+            }
+            for symbol in found {
+                trace = trace.only_function(symbol.start, symbol.end);
             }
-            tree.initiate_methods();
-            program.merge(tree)?;
         }
+        Ok(trace)
+    }
+
+    /// Parses the source and the modules it imports, the declarations keep the module they come from.
+    fn parse(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        let file = self
+            .sources
+            .locate(&self.source, "")
+            .map_err(|e| vec![e.into()])?;
+        let contents = self
+            .sources
+            .read_source(&file)
+            .map_err(|e| vec![e.into()])?;
+        let main = self.sources.parse_source(file.clone(), contents)?;
+        let mut program = Program::empty();
+        let mut imported = HashSet::new();
+        imported.insert(file);
+        self.sources.load(&mut program, vec![main], &mut imported)?;
         Ok(program)
     }
+
+    /// Shows the errors with the source code they refer to.
+    fn report(&mut self, errors: Vec<Diagnostic>) -> String {
+        let _ = write!(self.error_stream, "{}", self.sources.render(&errors));
+        String::from("Compilation aborted because of preceding errors.")
+    }
     /// Runs this instance of the interpreter.
     /// Will parse then interpret.
     pub fn run(&mut self) -> Result<&mut Self, String> {
         //println!("source : {}", self.source);
         println!("\nlisaa : Running {}\n\n", self.source);
 
-        let mut tree = self.parse()?;
-        if let Err(e) = TypeChecker::new().resolve(&mut tree) {
-            println!("TypeError : {}", e);
-            return Err(String::from(
-                "Compilation aborted because of preceding errors.",
-            ));
-        }
+        let (code, debug_info) = self.compile()?;
+        self.do_vm(code, debug_info)?;
 
-        self.do_vm(tree.clone())?;
+        Ok(self)
+    }
 
+    /// Compiles the source and writes the bytecode to the given file instead of running it.
+    pub fn emit(&mut self, output: &str) -> Result<&mut Self, String> {
+        let (code, _) = self.compile()?;
+        File::create(output)
+            .and_then(|mut file| file.write_all(&bytecode::encode(&code)))
+            .map_err(|e| format!("could not write file : {}, error : {}", output, e))?;
         Ok(self)
     }
 
-    fn do_vm(&mut self, tree: Program) -> Result<(), String> {
-        let code = Compiler::new()
-            .compile(&tree)
-            .map_err(|e| format!("compilation error : {:?}", e))?;
-        if self.verbose {
-            for c in code.iter() {
-                println!("{:?}", c);
+    /// Runs a file compiled with emit, without going through the front end.
+    pub fn run_compiled(&mut self) -> Result<&mut Self, String> {
+        let code = self.read_compiled()?;
+        self.do_vm(code, DebugInfo::default())?;
+        Ok(self)
+    }
+
+    /// Writes the listing of the compiled code to the output instead of running it.
+    /// Compiled files have no symbols so their functions are not named.
+    pub fn disassemble(&mut self) -> Result<&mut Self, String> {
+        let (code, debug_info) = match self.source.ends_with(".lisaac") {
+            true => (self.read_compiled()?, DebugInfo::default()),
+            false => self.compile()?,
+        };
+        write!(self.output_stream, "{}", disassemble(&code, &debug_info.symbols))
+            .map_err(|e| format!("could not write the listing : {}", e))?;
+        Ok(self)
+    }
+
+    /// Starts an interactive session, reading the inputs until the end of the given stream.
+    /// The declarations (fn, Class, method, trait and import) are added to the program,
+    /// the statements are run with the variables declared by the previous ones
+    /// and the value of an expression is printed if its type has toString.
+    /// The heap is kept for the whole session, the errors do not stop it.
+    pub fn repl(&mut self, input: &mut BufRead) -> Result<&mut Self, String> {
+        let natives = self
+            .natives
+            .iter()
+            .enumerate()
+            .map(|(id, native)| get_ffi_func(id, native))
+            .collect::<Vec<FunctionDecl>>();
+        let mut session = Session::new();
+        let mut compiler = Compiler::new();
+        compiler.set_bounds_checks(self.bounds_checks);
+        let mut vm = Vm::new(&mut *self.output_stream);
+        vm.set_natives(self.natives.drain(..).collect());
+        let mut text = String::new();
+        loop {
+            let _ = write!(vm.output(), "{}", if text.is_empty() { "> " } else { ". " });
+            let _ = vm.output().flush();
+            match input.read_line(&mut text) {
+                Ok(0) => break,
+                Ok(_) if is_incomplete(&text) => continue,
+                Ok(_) => (),
+                Err(e) => return Err(format!("could not read the input : {}", e)),
+            }
+            if !text.trim().is_empty() {
+                let entered = Input::new(&text);
+                let result = Lisaa::eval(
+                    &mut session,
+                    &mut compiler,
+                    &mut vm,
+                    &entered,
+                    &mut self.sources,
+                    &natives,
+                );
+                if let Err(errors) = result {
+                    let _ = write!(self.error_stream, "{}", errors);
+                }
+            }
+            text.clear();
+        }
+        self.natives = vm.take_natives();
+        Ok(self)
+    }
+
+    /// Checks, compiles and runs an input of the repl.
+    /// Returns the errors, ready to be shown.
+    fn eval(
+        session: &mut Session,
+        compiler: &mut Compiler,
+        vm: &mut Vm,
+        input: &Input,
+        sources: &mut Sources,
+        natives: &[FunctionDecl],
+    ) -> Result<(), String> {
+        let (program, line) = match input {
+            &Input::Declarations(ref text) => {
+                return Lisaa::declare(session, text, sources, natives)
+                    .map_err(|e| sources.render(&e))
+            }
+            &Input::Expression(_) => {
+                Lisaa::check_line(session, input, true, sources, natives).or_else(|_| {
+                    Lisaa::check_line(session, input, false, sources, natives)
+                })
+            }
+            &Input::Statements(_) => Lisaa::check_line(session, input, false, sources, natives),
+        }
+        .map_err(|e| sources.render(&e))?;
+        let (code, start) = compiler
+            .compile_line(&program, &line)
+            .map_err(|e| sources.render(&[e]))?;
+        vm.set_debug_info(compiler.debug_info());
+        vm.run_line(&code, start).map_err(|e| {
+            let mut message = format!("{}\n", Diagnostic::new(e.to_string()).render(None));
+            for frame in e.backtrace.iter() {
+                message.push_str(&format!("    {}\n", frame));
+            }
+            message
+        })?;
+        session.declare(&line);
+        Ok(())
+    }
+
+    /// Adds declarations to the program of the session, if they typecheck with it.
+    fn declare(
+        session: &mut Session,
+        text: &str,
+        sources: &mut Sources,
+        natives: &[FunctionDecl],
+    ) -> Result<(), Vec<Diagnostic>> {
+        let tree = sources.parse_source("repl".to_string(), text.to_string())?;
+        let mut program = session.program().clone();
+        let mut imported = session.imported().clone();
+        sources.load(&mut program, vec![tree], &mut imported)?;
+        Lisaa::checker(natives).resolve_library(&mut program.clone())?;
+        session.set_program(program, imported);
+        Ok(())
+    }
+
+    /// Checks a line of the repl with the program of the session.
+    /// Returns the monomorphised program, without the line, and the line.
+    fn check_line(
+        session: &Session,
+        input: &Input,
+        print: bool,
+        sources: &mut Sources,
+        natives: &[FunctionDecl],
+    ) -> Result<(Program, FunctionDecl), Vec<Diagnostic>> {
+        let tree = sources.parse_line(input, print)?;
+        let parsed = match tree.functions().values().next() {
+            Some(parsed) if tree.functions().len() == 1 => parsed.clone(),
+            _ => return Err(vec![Diagnostic::new("expected statements".to_string())]),
+        };
+        let mut line = session.line_function(parsed).map_err(|e| vec![e.into()])?;
+        let modules = session.program().modules();
+        let scope = modules
+            .iter()
+            .find(|m| m.file() == "repl")
+            .cloned()
+            .unwrap_or_else(|| Module::new("repl".to_string()));
+        module::resolve_function(&mut line, &scope, modules)?;
+        let mut program = session.program().clone();
+        program
+            .functions_mut()
+            .insert(line.signature().clone(), line);
+        Lisaa::checker(natives).resolve_library(&mut program)?;
+        let line = program
+            .functions_mut()
+            .remove(&session.line_signature())
+            .unwrap();
+        Ok((program, line))
+    }
+
+    /// Creates a type checker knowing the native functions.
+    fn checker(natives: &[FunctionDecl]) -> TypeChecker {
+        let mut checker = TypeChecker::new();
+        for native in natives {
+            checker.add_function(native.clone());
+        }
+        checker
+    }
+
+    /// Reads the bytecode of a file compiled with emit.
+    fn read_compiled(&self) -> Result<Vec<OP>, String> {
+        let mut bytes = vec![];
+        File::open(&self.source)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("could not read file : {}, error : {}", self.source, e))?;
+        bytecode::decode(&bytes).map_err(|e| format!("{} : {}", self.source, e))
+    }
+
+    /// Compiles the program without running it, so the host can call its functions.
+    /// The program does not need a main function, every function that is not generic,
+    /// is not a method and only uses nums, chars, bools, Strings and slices can be called.
+    pub fn load(mut self) -> Result<Instance<'a>, String> {
+        let mut tree = self.check(true)?;
+        let roots = tree.1;
+        let (code, debug_info) = self.generate(&mut tree.0)?;
+        let mut exports = vec![];
+        for sig in roots {
+            let args = sig
+                .args
+                .iter()
+                .map(get_native_type)
+                .collect::<Option<Vec<NativeType>>>();
+            let (args, ret) = match (args, get_native_type(&sig.ret_type)) {
+                (Some(args), Some(ret)) => (args, ret),
+                _ => continue,
+            };
+            let label = sig.to_string();
+            if let Some(symbol) = debug_info.symbols.iter().find(|s| s.name == label) {
+                exports.push(Export::new(sig.name.clone(), args, ret, symbol.start));
+            }
+        }
+        let trace = match self.trace {
+            Some(ref options) => Some(Lisaa::make_trace(options, &debug_info.symbols)?),
+            None => None,
+        };
+        let mut vm = Vm::new(self.output_stream);
+        if let Some(trace) = trace {
+            vm.set_trace(trace);
+        }
+        vm.set_debug_info(debug_info);
+        vm.set_natives(self.natives);
+        Ok(Instance::new(code, exports, vm))
+    }
+
+    /// Parses, typechecks and compiles the source.
+    /// Returns the code with its symbol table and source lines.
+    fn compile(&mut self) -> Result<(Vec<OP>, DebugInfo), String> {
+        let (mut tree, _) = self.check(false)?;
+        self.generate(&mut tree)
+    }
+
+    /// Parses and typechecks the source, as a library if it does not need a main.
+    /// Returns the program with the functions kept for the host.
+    fn check(&mut self, library: bool) -> Result<(Program, Vec<FunctionSig>), String> {
+        let parsed = self.parse();
+        let mut tree = parsed.map_err(|e| self.report(e))?;
+        let natives = self
+            .natives
+            .iter()
+            .enumerate()
+            .map(|(id, native)| get_ffi_func(id, native))
+            .collect::<Vec<FunctionDecl>>();
+        let mut checker = Lisaa::checker(&natives);
+        if let Err(errors) = checker.check_program(&mut tree) {
+            return Err(self.report(errors));
+        }
+        let (warnings, errors): (Vec<Diagnostic>, Vec<Diagnostic>) = self
+            .lint_program(&tree)
+            .into_iter()
+            .partition(|d| d.is_warning());
+        let _ = write!(self.error_stream, "{}", self.sources.render(&warnings));
+        if !errors.is_empty() {
+            return Err(self.report(errors));
+        }
+        let resolved = match library {
+            true => checker.monomorphise_library(&mut tree),
+            false => checker.monomorphise_main(&mut tree).map(|_| vec![]),
+        };
+        let roots = resolved.map_err(|errors| self.report(errors))?;
+        if self.opt_level > 0 {
+            let main = FunctionSig::new(vec![], vec![], LisaaType::Void, "main".to_string(), None);
+            match library {
+                true => optimize(&mut tree, &roots),
+                false => optimize(&mut tree, &[main]),
             }
         }
+        Ok((tree, roots))
+    }
+
+    /// Parses, typechecks and lints the source without monomorphising it, for the tools reading the code.
+    /// The program is returned with the types of its expressions even if some of them are wrong,
+    /// it is None if the sources could not be parsed or their modules resolved.
+    pub fn analyse(&mut self) -> (Option<Program>, Vec<Diagnostic>) {
+        let mut tree = match self.parse() {
+            Ok(tree) => tree,
+            Err(errors) => return (None, errors),
+        };
+        let natives = self
+            .natives
+            .iter()
+            .enumerate()
+            .map(|(id, native)| get_ffi_func(id, native))
+            .collect::<Vec<FunctionDecl>>();
+        let errors = match Lisaa::checker(&natives).check_program(&mut tree) {
+            Ok(()) => self.lint_program(&tree),
+            Err(errors) => errors,
+        };
+        (Some(tree), errors)
+    }
+
+    /// Lints the checked program, the modules of the standard library are not linted.
+    fn lint_program(&self, tree: &Program) -> Vec<Diagnostic> {
+        let files = tree
+            .modules()
+            .iter()
+            .map(|m| m.file().clone())
+            .filter(|f| !f.starts_with(STDLIB_DIR))
+            .collect::<Vec<String>>();
+        lint::lint(tree, &files, &self.lints)
+    }
+
+    /// Compiles the checked program.
+    fn generate(&mut self, tree: &mut Program) -> Result<(Vec<OP>, DebugInfo), String> {
+        let mut compiler = Compiler::new();
+        compiler.set_bounds_checks(self.bounds_checks);
+        let code = compiler
+            .compile(tree)
+            .map_err(|e| self.report(vec![e]))?;
+        let mut debug_info = compiler.debug_info();
+        if self.opt_level > 1 {
+            let code = peephole::optimize(&code, &compiler.adresses(), &mut debug_info);
+            return Ok((code, debug_info));
+        }
+        Ok((code, debug_info))
+    }
 
+    fn do_vm(&mut self, code: Vec<OP>, debug_info: DebugInfo) -> Result<(), String> {
+        if self.verbose {
+            println!("{}", disassemble(&code, &debug_info.symbols));
+        }
+
+        let trace = match self.trace {
+            Some(ref options) => Some(Lisaa::make_trace(options, &debug_info.symbols)?),
+            None => None,
+        };
         let mut vm = Vm::new(&mut self.output_stream);
+        if let Some(trace) = trace {
+            vm.set_trace(trace);
+        }
+        vm.set_debug_info(debug_info);
+        vm.set_natives(self.natives.drain(..).collect());
         let start = PreciseTime::now();
-        vm.run(code);
+        let result = vm.run(code);
         let end = PreciseTime::now();
+        self.natives = vm.take_natives();
         let diff = start.to(end).num_milliseconds();
 
         if self.verbose {
             println!("heap state : {:?}", vm.heap());
         }
 
-        Ok(())
+        let errors = &mut self.error_stream;
+        result.map_err(|e| {
+            let _ = writeln!(errors, "{}", Diagnostic::new(e.to_string()).render(None));
+            for frame in e.backtrace.iter() {
+                let _ = writeln!(errors, "    {}", frame);
+            }
+            let _ = writeln!(errors);
+            String::from("Execution aborted because of a runtime error.")
+        })
+    }
+}
+
+/// The standard library : the files of the lib directory, bundled in the binary
+/// so that they are found whatever the directory the scripts are run from.
+const STDLIB: &[(&str, &str)] = &[
+    ("string", include_str!("../lib/string.lisaa")),
+    ("stdlib", include_str!("../lib/stdlib.lisaa")),
+];
+
+/// The directory the files of the standard library are shown in, in the errors.
+const STDLIB_DIR: &str = "<stdlib>";
+
+/// The sources of the program : the modules given by the host and the files read.
+struct Sources {
+    /// The modules given by the host, by name, they are found before the files.
+    modules: HashMap<String, String>,
+    /// The content of the files read, by file name, used to show the errors.
+    read: HashMap<String, String>,
+    /// The directories where the imports are searched, after the directory of the importing file.
+    search_path: Vec<String>,
+}
+
+impl Sources {
+    /// Finds the file of an import, the modules given by the host are found first.
+    /// Then the file is searched in the directory of the importing file, in the search path
+    /// and finally in the standard library, name or name.lisaa in each of them.
+    fn locate(&self, source_name: &str, importer: &str) -> Result<String, String> {
+        if self.modules.contains_key(source_name) {
+            return Ok(source_name.to_string());
+        }
+        let local = Path::new(importer)
+            .parent()
+            .map_or(PathBuf::new(), |dir| dir.to_path_buf());
+        let dirs = Some(local)
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::from))
+            .collect::<Vec<PathBuf>>();
+        for dir in dirs.iter() {
+            for name in [source_name.to_string(), format!("{}.lisaa", source_name)].iter() {
+                let path = dir.join(name);
+                if path.is_file() {
+                    return Ok(normalize(&path));
+                }
+            }
+        }
+        let std_name = source_name.trim_end_matches(".lisaa");
+        if STDLIB.iter().any(|&(name, _)| name == std_name) {
+            return Ok(format!("{}/{}.lisaa", STDLIB_DIR, std_name));
+        }
+        let searched = dirs
+            .iter()
+            .map(|dir| match normalize(dir).as_str() {
+                "" => ".".to_string(),
+                dir => dir.to_string(),
+            })
+            .collect::<Vec<String>>();
+        Err(format!(
+            "cannot find module {}, searched in : {} and the standard library",
+            source_name,
+            searched.join(", ")
+        ))
+    }
+
+    /// Reads the content of a file found by locate.
+    fn read_source(&self, path: &str) -> Result<String, String> {
+        if let Some(contents) = self.modules.get(path) {
+            return Ok(contents.clone());
+        }
+        if let Some(&(_, contents)) = STDLIB
+            .iter()
+            .find(|&&(name, _)| format!("{}/{}.lisaa", STDLIB_DIR, name) == path)
+        {
+            return Ok(contents.to_string());
+        }
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("could not read file : {}, error : {}", path, e))?;
+        Ok(contents)
+    }
+
+    /// Adds the parsed modules and the ones they import to the program, skipping the files already imported.
+    /// The imports are found relative to the file importing them, see locate.
+    /// The names they use are resolved once all the modules are known, see module::resolve.
+    fn load(
+        &mut self,
+        program: &mut Program,
+        mut parsed: Vec<Program>,
+        imported: &mut HashSet<String>,
+    ) -> Result<(), Vec<Diagnostic>> {
+        // The file that first imported each file read, to show the chain of imports in the errors.
+        let mut importers = HashMap::new();
+        let mut errors = vec![];
+        let mut next = 0;
+        while next < parsed.len() {
+            let mut module = parsed[next].modules()[0].clone();
+            let importer = module.file().clone();
+            let mut files = vec![];
+            for import in module.imports_mut() {
+                match self.locate(&import.path, &importer) {
+                    Ok(file) => {
+                        import.file = file.clone();
+                        if imported.insert(file.clone()) {
+                            importers.insert(file.clone(), importer.clone());
+                            files.push(file);
+                        }
+                    }
+                    Err(e) => {
+                        let chain = import_chain(&importers, &importer);
+                        let message = match chain.len() {
+                            1 => e,
+                            _ => format!("{}, imported by {}", e, chain.join(" -> ")),
+                        };
+                        errors.push(Diagnostic::at(message, import.span.clone()));
+                    }
+                }
+            }
+            parsed[next].set_module(module);
+            for file in files {
+                match self.read_source(&file) {
+                    Ok(contents) => parsed.push(self.parse_source(file, contents)?),
+                    Err(e) => errors.push(e.into()),
+                }
+            }
+            next += 1;
+        }
+        if let Some(cycle) = find_cycle(&parsed) {
+            errors.push(cycle);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // a name declared by two modules is shown at the import of the second one
+        let mut modules = program.modules().clone();
+        for tree in parsed.iter() {
+            for m in tree.modules() {
+                module::add_module(&mut modules, m.clone()).map_err(|e| {
+                    let import = parsed
+                        .iter()
+                        .flat_map(|t| t.modules()[0].imports())
+                        .find(|i| &i.file == m.file());
+                    vec![match import {
+                        Some(import) => Diagnostic::at(e, import.span.clone()),
+                        None => e.into(),
+                    }]
+                })?;
+            }
+        }
+        for mut tree in parsed {
+            let file = tree.modules()[0].file().clone();
+            let scope = modules.iter().find(|m| m.file() == &file).unwrap();
+            if let Err(e) = module::resolve(&mut tree, scope, &modules) {
+                errors.extend(e);
+            }
+            tree.initiate_methods();
+            program.merge(tree).map_err(|e| vec![e.into()])?;
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Parses a source, it is kept to show the errors.
+    /// Returns its declarations in its module, whose imports are found by load.
+    fn parse_source(&mut self, path: String, contents: String) -> Result<Program, Vec<Diagnostic>> {
+        self.read.insert(path.clone(), contents.clone());
+        let tokens = Scanner::new(contents, path)
+            .tokens()
+            .map_err(|e| vec![e])?;
+        let (mut tree, module) = Parser::new(tokens).program()?;
+        tree.set_module(module);
+        Ok(tree)
+    }
+
+    /// Parses a line of the repl in the function holding it, see Session::line_tokens.
+    /// The source kept for the errors is the line entered.
+    fn parse_line(&mut self, input: &Input, print: bool) -> Result<Program, Vec<Diagnostic>> {
+        self.read.insert("repl".to_string(), input.text().to_string());
+        let tokens = Scanner::new(input.text().to_string(), "repl".to_string())
+            .tokens()
+            .map_err(|e| vec![e])?;
+        let (tree, _) = Parser::new(Session::line_tokens(input, print, tokens)).program()?;
+        Ok(tree)
+    }
+
+    /// Shows the errors with the source code they refer to.
+    fn render(&self, errors: &[Diagnostic]) -> String {
+        let mut rendered = String::new();
+        for err in errors.iter() {
+            let source = err
+                .span()
+                .as_ref()
+                .and_then(|span| self.read.get(&span.file))
+                .map(|s| s.as_str());
+            rendered.push_str(&format!("{}\n\n", err.render(source)));
+        }
+        rendered
+    }
+}
+
+/// Removes the . and the .. that can be removed from a path, so that a file has one name.
+/// The path is not made absolute : the errors show the files like the user wrote them.
+fn normalize(path: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component.as_os_str()),
+        }
+    }
+    normalized.to_string_lossy().into_owned()
+}
+
+/// The files through which a file was first imported, starting by the file loaded.
+fn import_chain(importers: &HashMap<String, String>, file: &str) -> Vec<String> {
+    let mut chain = vec![file.to_string()];
+    while let Some(importer) = importers.get(chain.last().unwrap()) {
+        if chain.contains(importer) {
+            break;
+        }
+        chain.push(importer.clone());
+    }
+    chain.reverse();
+    chain
+}
+
+/// Finds a module importing itself, directly or through other modules.
+/// The error is shown at the import closing the cycle, with the imports leading to it.
+fn find_cycle(parsed: &[Program]) -> Option<Diagnostic> {
+    let modules = parsed
+        .iter()
+        .map(|tree| &tree.modules()[0])
+        .collect::<Vec<&Module>>();
+    // Walks the imports depth first, path holds the files being visited, done the ones without cycle.
+    fn visit(
+        modules: &[&Module],
+        path: &mut Vec<String>,
+        done: &mut HashSet<String>,
+    ) -> Option<Diagnostic> {
+        let file = path.last().unwrap().clone();
+        if let Some(module) = modules.iter().find(|m| m.file() == &file) {
+            for import in module.imports() {
+                if path.contains(&import.file) {
+                    let mut cycle = path.clone();
+                    cycle.push(import.file.clone());
+                    return Some(Diagnostic::at(
+                        format!("circular import : {}", cycle.join(" -> ")),
+                        import.span.clone(),
+                    ));
+                }
+                if !done.contains(&import.file) {
+                    path.push(import.file.clone());
+                    if let Some(cycle) = visit(modules, path, done) {
+                        return Some(cycle);
+                    }
+                    path.pop();
+                }
+            }
+        }
+        done.insert(file);
+        None
     }
+    let mut done = HashSet::new();
+    modules
+        .iter()
+        .filter_map(|m| visit(&modules, &mut vec![m.file().clone()], &mut done))
+        .next()
 }
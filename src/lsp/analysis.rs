@@ -0,0 +1,342 @@
+//! What the language server knows about a document : its errors, its declarations and the types.
+//!
+//! The document is checked like the interpreter does, the modules it imports are read from the disk.
+//! The positions are the ones of the spans : lines and columns starting at 1, counted in chars.
+use diagnostic::{Diagnostic, Span};
+use expression::{Callee, Expr, ExprEnum};
+use lisaa::Lisaa;
+use native::get_native_funcs;
+use statement::{FunctionSig, Program, Statement};
+use std::io;
+use types::LisaaType;
+
+/// The identifier written where a completion is asked, so that the source parses
+/// and the value before the dot gets its type.
+const COMPLETION_MARK: &str = "lisaacompletion";
+
+/// A document checked with the types of its expressions.
+pub struct Analysis {
+    file: String,
+    program: Program,
+}
+
+/// A method or an attribute that can follow the dot.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Completion {
+    /// The name to insert.
+    pub label: String,
+    /// The signature of the method or the type of the attribute.
+    pub detail: String,
+    /// A method, or an attribute if false.
+    pub is_method: bool,
+}
+
+impl Analysis {
+    /// Checks the source of the given file, the imports are searched from its directory.
+    /// Returns the analysis, None if the source could not be parsed, and the errors found.
+    pub fn new(file: &str, source: &str, search_path: &[String]) -> (Option<Analysis>, Vec<Diagnostic>) {
+        let mut output = io::sink();
+        let mut lisaa = Lisaa::new(file.to_string(), &mut output, false);
+        lisaa.module(file, source);
+        for dir in search_path {
+            lisaa.search_path(dir);
+        }
+        let (program, errors) = lisaa.analyse();
+        let analysis = program.map(|program| Analysis {
+            file: file.to_string(),
+            program: program,
+        });
+        (analysis, errors)
+    }
+
+    /// Returns the expressions of the functions declared in the document.
+    fn expressions(&self) -> Vec<&Expr> {
+        let mut found = vec![];
+        for function in self.program.functions().values() {
+            if function.span.as_ref().map_or(false, |s| s.file == self.file) {
+                statement_expressions(&function.scope, &mut found);
+            }
+        }
+        found
+    }
+
+    /// Returns the smallest expression at the position that is accepted,
+    /// the outermost one if several have the same span.
+    fn expression_at<F>(&self, line: usize, column: usize, accept: F) -> Option<&Expr>
+    where
+        F: Fn(&Expr) -> bool,
+    {
+        let mut best: Option<&Expr> = None;
+        for expr in self.expressions() {
+            let span = expr.span();
+            let inside = span.line == line && span.column <= column && column < span.column + span.len;
+            if inside && accept(expr) && best.map_or(true, |b| span.len < b.span().len) {
+                best = Some(expr);
+            }
+        }
+        best
+    }
+
+    /// Finds the declaration of the function, method, class or trait at the position.
+    /// A call goes to the overload it calls, else the name under the cursor is searched.
+    pub fn definition(&self, source: &str, line: usize, column: usize) -> Option<Span> {
+        let called = self
+            .expression_at(line, column, |e| called(e).is_some())
+            .and_then(called)
+            .and_then(|sig| self.function_span(sig));
+        called.or_else(|| self.declaration_of(&word_at(source, line, column)?))
+    }
+
+    /// Returns where the called function is declared : the function with the same signature,
+    /// else the first one with the same name and number of arguments, it is generic.
+    fn function_span(&self, sig: &FunctionSig) -> Option<Span> {
+        let functions = self.program.functions();
+        if let Some(span) = functions.get(sig).and_then(|f| f.span.clone()) {
+            return Some(span);
+        }
+        first(
+            functions
+                .iter()
+                .filter(|&(s, _)| s.name == sig.name && s.args.len() == sig.args.len())
+                .filter_map(|(_, f)| f.span.clone()),
+        )
+    }
+
+    /// Returns where the class, the trait or the function with the given name is declared.
+    fn declaration_of(&self, name: &str) -> Option<Span> {
+        if let Some(span) = self.program.classes().get(name).and_then(|c| c.span()) {
+            return Some(span.clone());
+        }
+        if let Some(span) = self.program.traits().get(name).and_then(|t| t.span()) {
+            return Some(span.clone());
+        }
+        first(
+            self.program
+                .functions()
+                .values()
+                .filter(|f| f.name() == name)
+                .filter_map(|f| f.span.clone()),
+        )
+    }
+
+    /// Describes the expression at the position : the signature of the function called
+    /// or the type of the value. Returns the description with the span of the expression.
+    pub fn hover(&self, line: usize, column: usize) -> Option<(String, Span)> {
+        let expr = self.expression_at(line, column, |e| describe(e).is_some())?;
+        Some((describe(expr)?, expr.span().clone()))
+    }
+
+    /// Returns the type of the value before the completion mark.
+    fn receiver(&self) -> Option<LisaaType> {
+        self.expressions()
+            .iter()
+            .filter_map(|e| match e.expr() {
+                &ExprEnum::GetAttr(ref attr) => match attr.rhs().expr() {
+                    &ExprEnum::Identifier(ref name) if name == COMPLETION_MARK => {
+                        attr.lhs().return_type_uncheck().clone()
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .map(|t| t.max_deref().0)
+            .find(|t| !t.is_error())
+    }
+
+    /// Returns the attributes and the methods of the type starting with the given prefix, sorted.
+    fn members(&self, receiver: &LisaaType, prefix: &str) -> Vec<Completion> {
+        let owner = match receiver {
+            &LisaaType::Num | &LisaaType::Char => "num".to_string(),
+            &LisaaType::Bool => "bool".to_string(),
+            &LisaaType::Class(ref name, _) => name.clone(),
+            _ => return vec![],
+        };
+        let mut found = vec![];
+        if let Some(class) = self.program.classes().get(&owner) {
+            for attribute in class.attributes() {
+                found.push(Completion {
+                    label: attribute.name().clone(),
+                    detail: type_name(attribute.val_type()),
+                    is_method: false,
+                });
+            }
+        }
+        let methods = self
+            .program
+            .functions()
+            .values()
+            .cloned()
+            .chain(get_native_funcs("base"));
+        let qualifier = format!("{}::", owner);
+        for method in methods.filter(|f| f.name().starts_with(&qualifier)) {
+            found.push(Completion {
+                label: method.name()[qualifier.len()..].to_string(),
+                detail: written(&method.signature().to_string()),
+                is_method: true,
+            });
+        }
+        found.retain(|c| c.label.starts_with(prefix));
+        found.sort();
+        found.dedup();
+        found
+    }
+}
+
+/// Returns the methods and attributes that can complete the name after the dot at the position.
+/// The document is checked again with the name replaced by a mark, the missing semicolon is added if needed.
+pub fn completions(
+    file: &str,
+    source: &str,
+    search_path: &[String],
+    line: usize,
+    column: usize,
+) -> Vec<Completion> {
+    let lines = source.split('\n').collect::<Vec<&str>>();
+    let current = match lines.get(line.wrapping_sub(1)) {
+        Some(text) => text.chars().collect::<Vec<char>>(),
+        None => return vec![],
+    };
+    let end = (column - 1).min(current.len());
+    let mut start = end;
+    while start > 0 && current[start - 1].is_alphanumeric() {
+        start -= 1;
+    }
+    if start == 0 || current[start - 1] != '.' {
+        return vec![];
+    }
+    let prefix = current[start..end].iter().collect::<String>();
+    for end_of_statement in ["", ";"].iter() {
+        let patched_line = format!(
+            "{}{}{}{}",
+            current[..start].iter().collect::<String>(),
+            COMPLETION_MARK,
+            end_of_statement,
+            current[end..].iter().collect::<String>()
+        );
+        let mut patched = lines.clone();
+        patched[line - 1] = &patched_line;
+        if let (Some(analysis), _) = Analysis::new(file, &patched.join("\n"), search_path) {
+            if let Some(receiver) = analysis.receiver() {
+                return analysis.members(&receiver, &prefix);
+            }
+        }
+    }
+    vec![]
+}
+
+/// Returns the first span in the order of the sources, the functions are not sorted.
+fn first<I: Iterator<Item = Span>>(spans: I) -> Option<Span> {
+    spans.min_by_key(|s| (s.file.clone(), s.line, s.column))
+}
+
+/// Returns the signature of the function called or referenced by the expression.
+fn called(expr: &Expr) -> Option<&FunctionSig> {
+    match expr.expr() {
+        &ExprEnum::FunctionCall(ref call) => call.checked_signature(),
+        &ExprEnum::FunctionRef(ref reference) => reference.checked_signature(),
+        _ => None,
+    }
+}
+
+/// Returns the signature of the function called by the expression or the type of its value.
+/// The expressions without a value or that failed to typecheck have no description.
+fn describe(expr: &Expr) -> Option<String> {
+    if let Some(sig) = called(expr) {
+        return Some(written(&sig.to_string()));
+    }
+    match expr.return_type_uncheck().as_ref().map(|t| t.max_deref().0) {
+        None
+        | Some(LisaaType::Error)
+        | Some(LisaaType::Void)
+        | Some(LisaaType::Function(..)) => None,
+        Some(t) => Some(type_name(&t)),
+    }
+}
+
+/// Shows a type like it is written in the code.
+fn type_name(t: &LisaaType) -> String {
+    written(&t.to_string())
+}
+
+/// Removes what the types show and the code does not write : the class keyword and the empty type arguments.
+fn written(text: &str) -> String {
+    text.replace("class ", "").replace("<>", "")
+}
+
+/// Returns the identifier at the position, or just before it.
+fn word_at(source: &str, line: usize, column: usize) -> Option<String> {
+    let chars = source
+        .split('\n')
+        .nth(line.wrapping_sub(1))?
+        .chars()
+        .collect::<Vec<char>>();
+    let is_word = |i: usize| chars.get(i).map_or(false, |c| c.is_alphanumeric());
+    let mut start = match (is_word(column - 1), column > 1 && is_word(column - 2)) {
+        (true, _) => column - 1,
+        (false, true) => column - 2,
+        (false, false) => return None,
+    };
+    let mut end = start;
+    while start > 0 && is_word(start - 1) {
+        start -= 1;
+    }
+    while is_word(end) {
+        end += 1;
+    }
+    Some(chars[start..end].iter().collect())
+}
+
+/// Adds the expressions of the statement and of the statements it contains.
+fn statement_expressions<'a>(statement: &'a Statement, found: &mut Vec<&'a Expr>) {
+    match statement {
+        &Statement::Assignment(ref a) => {
+            expressions(a.assignee(), found);
+            expressions(a.expr(), found);
+        }
+        &Statement::Declaration(ref d) => expressions(d.expr(), found),
+        &Statement::ExprStatement(ref e) | &Statement::ReturnStatement(ref e) => {
+            expressions(e, found)
+        }
+        &Statement::Scope(ref statements) => {
+            for s in statements {
+                statement_expressions(s, found);
+            }
+        }
+        &Statement::IfStatement(ref i) => {
+            expressions(i.condition(), found);
+            statement_expressions(i.statement(), found);
+            if let Some(else_statement) = i.else_statement() {
+                statement_expressions(else_statement, found);
+            }
+        }
+        &Statement::WhileStatement(ref w) => {
+            expressions(w.condition(), found);
+            statement_expressions(w.statement(), found);
+        }
+        &Statement::BreakStatement | &Statement::Native(_) => (),
+    }
+}
+
+/// Adds the expression and the ones it contains, the outer ones first.
+fn expressions<'a>(expr: &'a Expr, found: &mut Vec<&'a Expr>) {
+    found.push(expr);
+    match expr.expr() {
+        &ExprEnum::Unary(ref u) => expressions(u.expression(), found),
+        &ExprEnum::GetAttr(ref attr) => {
+            expressions(attr.lhs(), found);
+            expressions(attr.rhs(), found);
+        }
+        &ExprEnum::FunctionCall(ref call) => {
+            match call.callee() {
+                &Callee::Method(ref e) | &Callee::Value(ref e) => expressions(e, found),
+                &Callee::StaticFunc(_) => (),
+            }
+            for arg in call.args() {
+                expressions(arg, found);
+            }
+        }
+        &ExprEnum::Deref(ref d) => expressions(d.inner(), found),
+        &ExprEnum::Closure(ref c) => statement_expressions(&c.decl().scope, found),
+        &ExprEnum::Literal(_) | &ExprEnum::Identifier(_) | &ExprEnum::FunctionRef(_) => (),
+    }
+}
@@ -0,0 +1,358 @@
+//! A small JSON reader and writer, enough for the messages of the language server.
+use std::fmt;
+
+/// A JSON value, the fields of an object keep their order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    /// null, also returned for the missing fields.
+    Null,
+    /// true or false.
+    Bool(bool),
+    /// Any number.
+    Number(f64),
+    /// A string, unescaped.
+    String(String),
+    /// [1, "a"]
+    Array(Vec<Json>),
+    /// {"a": 1}
+    Object(Vec<(String, Json)>),
+}
+
+/// The value of the missing fields.
+static NULL: Json = Json::Null;
+
+impl Json {
+    /// Parses a JSON text, there can only be spaces after the value.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = Reader {
+            chars: text.chars().collect(),
+            current: 0,
+        };
+        let value = reader.value()?;
+        reader.skip_spaces();
+        match reader.current < reader.chars.len() {
+            true => Err(reader.error("expected the end of the text")),
+            false => Ok(value),
+        }
+    }
+
+    /// Creates an object with the given fields.
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Creates a string.
+    pub fn string(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+
+    /// Returns the field with the given name, null if it is not an object or has no such field.
+    pub fn get(&self, name: &str) -> &Json {
+        match self {
+            &Json::Object(ref fields) => fields
+                .iter()
+                .find(|&&(ref field, _)| field == name)
+                .map_or(&NULL, |&(_, ref value)| value),
+            _ => &NULL,
+        }
+    }
+
+    /// Returns the string if the value is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            &Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if the value is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            &Json::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements if the value is an array.
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            &Json::Array(ref elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    /// Checks if the value is null.
+    pub fn is_null(&self) -> bool {
+        self == &Json::Null
+    }
+}
+
+/// Writes the value on one line, without spaces.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Json::Null => write!(f, "null"),
+            &Json::Bool(b) => write!(f, "{}", b),
+            &Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            &Json::Number(n) => write!(f, "{}", n),
+            &Json::String(ref s) => write_string(f, s),
+            &Json::Array(ref elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            &Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, &(ref name, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Writes a string between quotes, escaping the quotes, the backslashes and the control chars.
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Reads a value from the chars of a text.
+struct Reader {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl Reader {
+    /// Creates an error at the current char.
+    fn error(&self, message: &str) -> String {
+        format!("invalid json at char {} : {}", self.current, message)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.current < self.chars.len() && self.chars[self.current].is_whitespace() {
+            self.current += 1;
+        }
+    }
+
+    /// Returns the next char that is not a space, without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.get(self.current).cloned()
+    }
+
+    /// Consumes the given word if it is next.
+    fn keyword(&mut self, word: &str) -> bool {
+        let end = self.current + word.chars().count();
+        match end <= self.chars.len() && self.chars[self.current..end].iter().cloned().eq(word.chars()) {
+            true => {
+                self.current = end;
+                true
+            }
+            false => false,
+        }
+    }
+
+    /// Consumes the given char, that must be next.
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() == Some(c) {
+            true => {
+                self.current += 1;
+                Ok(())
+            }
+            false => Err(self.error(&format!("expected {}", c))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some(c) if c == '-' || c.is_digit(10) => self.number(),
+            Some(_) if self.keyword("null") => Ok(Json::Null),
+            Some(_) if self.keyword("true") => Ok(Json::Bool(true)),
+            Some(_) if self.keyword("false") => Ok(Json::Bool(false)),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        if self.peek() == Some('}') {
+            self.current += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(self.error("expected the name of a field"));
+            }
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            match self.peek() {
+                Some(',') => self.current += 1,
+                _ => break,
+            }
+        }
+        self.expect('}')?;
+        Ok(Json::Object(fields))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        if self.peek() == Some(']') {
+            self.current += 1;
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.current += 1,
+                _ => break,
+            }
+        }
+        self.expect(']')?;
+        Ok(Json::Array(elements))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.current;
+        while self.current < self.chars.len()
+            && (self.chars[self.current].is_digit(10) || "+-.eE".contains(self.chars[self.current]))
+        {
+            self.current += 1;
+        }
+        let text = self.chars[start..self.current].iter().collect::<String>();
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| self.error(&format!("invalid number {}", text)))
+    }
+
+    /// Reads a string, the quote is next.
+    /// The escaped utf-16 surrogate pairs are joined, the lone surrogates become U+FFFD.
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.chars.get(self.current) {
+                Some(&c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.current += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = self.chars.get(self.current).cloned();
+                    self.current += 1;
+                    match escaped {
+                        Some('n') => s.push('\n'),
+                        Some('r') => s.push('\r'),
+                        Some('t') => s.push('\t'),
+                        Some('b') => s.push('\u{8}'),
+                        Some('f') => s.push('\u{c}'),
+                        Some('u') => {
+                            let mut code = self.hex()?;
+                            while code >= 0xd800 && code < 0xdc00 && self.keyword("\\u") {
+                                let low = self.hex()?;
+                                if low >= 0xdc00 && low < 0xe000 {
+                                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                } else {
+                                    // a lone high surrogate, the code unit after it is kept
+                                    s.push('\u{fffd}');
+                                    code = low;
+                                }
+                            }
+                            s.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        Some(c) => s.push(c),
+                        None => return Err(self.error("unterminated string")),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Reads the four hexadecimal digits of an escaped char.
+    fn hex(&mut self) -> Result<u32, String> {
+        let end = (self.current + 4).min(self.chars.len());
+        let digits = self.chars[self.current..end].iter().collect::<String>();
+        self.current = end;
+        u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid escaped char"))
+    }
+}
+
+#[cfg(test)]
+mod tests_json {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = Json::parse(
+            "{\"id\": 1, \"params\": {\"text\": \"a\\n\\\"b\\\" \\u00e9\\ud83d\\ude00\", \"ok\": [true, null, -2.5e1]}}",
+        ).unwrap();
+        assert_eq!(value.get("id").as_f64(), Some(1.0));
+        assert_eq!(value.get("params").get("text").as_str(), Some("a\n\"b\" é😀"));
+        assert_eq!(
+            value.get("params").get("ok"),
+            &Json::Array(vec![Json::Bool(true), Json::Null, Json::Number(-25.0)])
+        );
+        assert!(value.get("missing").get("field").is_null());
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2] 3").is_err());
+    }
+
+    #[test]
+    fn test_lone_surrogates() {
+        let value = Json::parse(
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\",\"params\":{\"x\":\"\\ud83d\\u0041\"}}",
+        ).unwrap();
+        assert_eq!(value.get("params").get("x").as_str(), Some("\u{fffd}A"));
+        let value = Json::parse("[\"\\ud83d\\ud83d\\ude00\", \"\\ude00\\ud83d\"]").unwrap();
+        assert_eq!(
+            value,
+            Json::Array(vec![Json::string("\u{fffd}😀"), Json::string("\u{fffd}\u{fffd}")])
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let value = Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("id", Json::Number(3.0)),
+            ("result", Json::Array(vec![Json::string("tab\there"), Json::Number(0.5), Json::Null])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(text, "{\"jsonrpc\":\"2.0\",\"id\":3,\"result\":[\"tab\\there\",0.5,null]}");
+        assert_eq!(Json::parse(&text), Ok(value));
+    }
+}
@@ -0,0 +1,437 @@
+//! The language server, speaking the Language Server Protocol, see the lisaa-lsp binary.
+//!
+//! The messages are JSON-RPC objects preceded by a Content-Length header.
+//! The documents are sent whole on each change, they are checked again each time
+//! and their errors are published with the ones of the files they import.
+//! It answers to the definition, hover and completion requests.
+//! The characters of the positions are counted in utf-16 code units, like the protocol does by default.
+mod analysis;
+pub mod json;
+
+use self::analysis::{completions, Analysis};
+use self::json::Json;
+use diagnostic::{Diagnostic, Span};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// The kinds of completion items of the protocol.
+const METHOD_KIND: f64 = 2.0;
+const FIELD_KIND: f64 = 5.0;
+
+/// An open document, with the last analysis of a version that parsed.
+/// It is kept while the document is being edited, so that the requests still find something.
+struct Document {
+    file: String,
+    text: String,
+    analysis: Option<Analysis>,
+}
+
+/// The state of the server : the open documents and the files having errors.
+pub struct Server<'a> {
+    output: &'a mut Write,
+    documents: HashMap<String, Document>,
+    /// The uris of the files with errors, by document.
+    published: HashMap<String, Vec<String>>,
+    search_path: Vec<String>,
+    shut_down: bool,
+}
+
+/// Answers the messages read from the input until the exit notification.
+/// Returns an error if the input ends or if the client exits without asking to shut down first.
+pub fn serve(input: &mut BufRead, output: &mut Write, search_path: Vec<String>) -> Result<(), String> {
+    let mut server = Server::new(output, search_path);
+    loop {
+        let message = match read_message(input)? {
+            Some(message) => message,
+            None => return Err("the input ended before the exit notification".to_string()),
+        };
+        if !server.handle(&message)? {
+            return match server.shut_down {
+                true => Ok(()),
+                false => Err("exit without shutdown".to_string()),
+            };
+        }
+    }
+}
+
+/// Reads a message : the headers, an empty line and the content. Returns None at the end of the input.
+pub fn read_message(input: &mut BufRead) -> Result<Option<Json>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        match input.read_line(&mut header) {
+            Ok(0) => return Ok(None),
+            Ok(_) => (),
+            Err(e) => return Err(format!("could not read a message : {}", e)),
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or("message without Content-Length".to_string())?;
+    let mut content = vec![0; length];
+    input
+        .read_exact(&mut content)
+        .map_err(|e| format!("could not read a message : {}", e))?;
+    Json::parse(&String::from_utf8_lossy(&content)).map(Some)
+}
+
+/// Writes a message with its header.
+pub fn write_message(output: &mut Write, message: &Json) -> Result<(), String> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("could not write a message : {}", e))
+}
+
+impl<'a> Server<'a> {
+    /// Creates a server writing to the given output, the imports are searched in the search path.
+    pub fn new(output: &'a mut Write, search_path: Vec<String>) -> Self {
+        Server {
+            output: output,
+            documents: HashMap::new(),
+            published: HashMap::new(),
+            search_path: search_path,
+            shut_down: false,
+        }
+    }
+
+    /// Handles a request or a notification, returns false on exit.
+    /// The unknown notifications are ignored, the unknown requests get an error.
+    pub fn handle(&mut self, message: &Json) -> Result<bool, String> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let id = message.get("id");
+        if id.is_null() {
+            match method {
+                "exit" => return Ok(false),
+                "textDocument/didOpen" => {
+                    let document = params.get("textDocument");
+                    self.update(document.get("uri"), document.get("text"))?;
+                }
+                "textDocument/didChange" => {
+                    let text = params
+                        .get("contentChanges")
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .map_or(&Json::Null, |change| change.get("text"));
+                    self.update(params.get("textDocument").get("uri"), text)?;
+                }
+                "textDocument/didClose" => {
+                    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+                    self.documents.remove(uri);
+                    self.publish(uri, vec![])?;
+                }
+                _ => (),
+            }
+            return Ok(true);
+        }
+        let result = match method {
+            _ if self.shut_down => Err((-32600, "the server is shut down".to_string())),
+            "initialize" => Ok(Json::object(vec![
+                (
+                    "capabilities",
+                    Json::object(vec![
+                        ("positionEncoding", Json::string("utf-16")),
+                        ("textDocumentSync", Json::Number(1.0)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("hoverProvider", Json::Bool(true)),
+                        (
+                            "completionProvider",
+                            Json::object(vec![(
+                                "triggerCharacters",
+                                Json::Array(vec![Json::string(".")]),
+                            )]),
+                        ),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object(vec![("name", Json::string("lisaa-lsp"))]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err((-32601, format!("unknown method : {}", method))),
+        };
+        let response = match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            Err((code, message)) => Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::Number(code as f64)),
+                        ("message", Json::String(message)),
+                    ]),
+                ),
+            ]),
+        };
+        write_message(self.output, &response)?;
+        Ok(true)
+    }
+
+    /// Checks the new text of a document and publishes its errors.
+    fn update(&mut self, uri: &Json, text: &Json) -> Result<(), String> {
+        let (uri, text) = match (uri.as_str(), text.as_str()) {
+            (Some(uri), Some(text)) => (uri.to_string(), text.to_string()),
+            _ => return Ok(()),
+        };
+        let file = file_of(&uri);
+        let (analysis, errors) = Analysis::new(&file, &text, &self.search_path);
+        let previous = self.documents.remove(&uri).and_then(|d| d.analysis);
+        self.documents.insert(
+            uri.clone(),
+            Document {
+                file: file,
+                text: text,
+                analysis: analysis.or(previous),
+            },
+        );
+        self.publish(&uri, errors)
+    }
+
+    /// Publishes the errors and the warnings of a document, the ones in other files are published for these files.
+    /// The files that had errors and have none now are cleared.
+    fn publish(&mut self, uri: &str, errors: Vec<Diagnostic>) -> Result<(), String> {
+        let mut by_uri = BTreeMap::new();
+        for old in self.published.remove(uri).unwrap_or(vec![]) {
+            by_uri.insert(old, vec![]);
+        }
+        by_uri.insert(uri.to_string(), vec![]);
+        for error in errors {
+            let (target, range) = match error.span().as_ref().and_then(|s| uri_of(&s.file).map(|u| (u, s))) {
+                Some((target, span)) => (target, range(span, self.source(&span.file).as_ref())),
+                None => (uri.to_string(), range(&Span::new(String::new(), 1, 1, 0), None)),
+            };
+            by_uri.entry(target).or_insert(vec![]).push(Json::object(vec![
+                ("range", range),
+                ("severity", Json::Number(if error.is_warning() { 2.0 } else { 1.0 })),
+                ("source", Json::string("lisaa")),
+                ("message", Json::String(error.message().clone())),
+            ]));
+        }
+        let with_errors = by_uri
+            .iter()
+            .filter(|&(_, d)| !d.is_empty())
+            .map(|(u, _)| u.clone())
+            .collect::<Vec<String>>();
+        self.published.insert(uri.to_string(), with_errors);
+        for (target, diagnostics) in by_uri {
+            let notification = Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("method", Json::string("textDocument/publishDiagnostics")),
+                (
+                    "params",
+                    Json::object(vec![
+                        ("uri", Json::String(target)),
+                        ("diagnostics", Json::Array(diagnostics)),
+                    ]),
+                ),
+            ]);
+            write_message(self.output, &notification)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the open document of a request and the position asked, as a line and a column of a span.
+    fn position(&self, params: &Json) -> Option<(&Document, usize, usize)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let position = params.get("position");
+        let line = position.get("line").as_f64()? as usize + 1;
+        let character = position.get("character").as_f64()? as usize;
+        let document = self.documents.get(uri)?;
+        let text = document.text.lines().nth(line - 1).unwrap_or("");
+        Some((document, line, column_of(text, character)))
+    }
+
+    /// Returns the text of a file : the one of its open document, or else the one on the disk.
+    fn source(&self, file: &str) -> Option<String> {
+        match self.documents.values().find(|d| d.file == file) {
+            Some(document) => Some(document.text.clone()),
+            None => fs::read_to_string(file).ok(),
+        }
+    }
+
+    /// Finds the location of the declaration at the position, null if there is none or if it is not in a file.
+    fn definition(&self, params: &Json) -> Json {
+        let found = self.position(params).and_then(|(document, line, column)| {
+            document
+                .analysis
+                .as_ref()?
+                .definition(&document.text, line, column)
+        });
+        match found.and_then(|span| uri_of(&span.file).map(|uri| (uri, span))) {
+            Some((uri, span)) => {
+                let range = range(&span, self.source(&span.file).as_ref());
+                Json::object(vec![("uri", Json::String(uri)), ("range", range)])
+            }
+            None => Json::Null,
+        }
+    }
+
+    /// Describes the expression at the position, null if there is none.
+    fn hover(&self, params: &Json) -> Json {
+        let found = self
+            .position(params)
+            .and_then(|(document, line, column)| document.analysis.as_ref()?.hover(line, column));
+        match found {
+            Some((description, span)) => Json::object(vec![
+                (
+                    "contents",
+                    Json::object(vec![
+                        ("kind", Json::string("markdown")),
+                        ("value", Json::String(format!("```lisaa\n{}\n```", description))),
+                    ]),
+                ),
+                ("range", range(&span, self.source(&span.file).as_ref())),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    /// Lists the methods and attributes that can follow the dot before the position.
+    fn completion(&self, params: &Json) -> Json {
+        let found = match self.position(params) {
+            Some((document, line, column)) => {
+                completions(&document.file, &document.text, &self.search_path, line, column)
+            }
+            None => vec![],
+        };
+        Json::Array(
+            found
+                .into_iter()
+                .map(|c| {
+                    Json::object(vec![
+                        ("label", Json::String(c.label)),
+                        ("kind", Json::Number(if c.is_method { METHOD_KIND } else { FIELD_KIND })),
+                        ("detail", Json::String(c.detail)),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Converts a span to a range of the protocol, whose lines and characters start at 0.
+/// The characters are counted in utf-16 code units in the line of the source, without the source in chars.
+fn range(span: &Span, source: Option<&String>) -> Json {
+    let text = source
+        .and_then(|s| s.lines().nth(span.line.saturating_sub(1)))
+        .unwrap_or("");
+    let position = |column: usize| {
+        let chars = column.saturating_sub(1);
+        let units = text.chars().take(chars).map(|c| c.len_utf16()).sum::<usize>();
+        let after = chars.saturating_sub(text.chars().count());
+        Json::object(vec![
+            ("line", Json::Number(span.line.saturating_sub(1) as f64)),
+            ("character", Json::Number((units + after) as f64)),
+        ])
+    };
+    Json::object(vec![
+        ("start", position(span.column)),
+        ("end", position(span.column + span.len)),
+    ])
+}
+
+/// Converts a character of the protocol, in utf-16 code units from 0, to the column of a span in the line.
+fn column_of(line: &str, character: usize) -> usize {
+    let (mut units, mut column) = (0, 1);
+    for c in line.chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        column += 1;
+    }
+    column + character.saturating_sub(units)
+}
+
+/// Returns the path of a file:// uri, the other uris are kept as they are.
+fn file_of(uri: &str) -> String {
+    if !uri.starts_with("file://") {
+        return uri.to_string();
+    }
+    let encoded = uri["file://".len()..].as_bytes();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = match encoded[i] {
+            b'%' if i + 2 < encoded.len() => {
+                u8::from_str_radix(&String::from_utf8_lossy(&encoded[i + 1..i + 3]), 16).ok()
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the file:// uri of a file, None for the files of the bundled standard library.
+fn uri_of(file: &str) -> Option<String> {
+    if file.starts_with('<') {
+        return None;
+    }
+    let path = match Path::new(file).is_absolute() {
+        true => Path::new(file).to_path_buf(),
+        false => env::current_dir().ok()?.join(file),
+    };
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    Some(uri)
+}
+
+#[cfg(test)]
+mod tests_lsp {
+    use super::*;
+
+    #[test]
+    fn test_utf16_positions() {
+        // each emoji is one char but two utf-16 code units
+        let source = "import string\nString s = \"\u{1f600}\u{1f600}\"; s.si".to_string();
+        let line = source.lines().nth(1).unwrap();
+        assert_eq!(column_of(line, 4), 5);
+        assert_eq!(column_of(line, 21), 20);
+        assert_eq!(column_of(line, 30), 29);
+        let range = range(&Span::new("a.lisaa".to_string(), 2, 20, 2), Some(&source));
+        assert_eq!(range.get("start").get("line").as_f64(), Some(1.0));
+        assert_eq!(range.get("start").get("character").as_f64(), Some(21.0));
+        assert_eq!(range.get("end").get("character").as_f64(), Some(23.0));
+    }
+}
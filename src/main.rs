@@ -11,14 +11,22 @@ extern crate rand;
 extern crate lazy_static;
 extern crate time;
 mod compile;
+mod diagnostic;
+mod disassemble;
+mod embed;
 //mod compile_req;
 mod expression;
+mod format;
 mod generic_inference;
 mod keywords;
+mod lint;
 mod lisaa;
+mod module;
 mod monomorphise;
 mod native;
+mod optimize;
 mod parser;
+mod repl;
 mod scanner;
 pub mod statement;
 mod token;
@@ -29,10 +37,12 @@ mod vm;
 #[allow(unused_imports)]
 use std::io::{self, Read};
 
-use clap::{App, Arg};
-use lisaa::Lisaa;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use lisaa::{Level, Lint, Lisaa, TraceOptions, LINTS};
+use std::fs;
 #[allow(unused_imports)]
 use std::fs::File;
+use std::env;
 use std::process::exit;
 fn main() {
     let matches = App::new("Lisaa")
@@ -53,18 +63,268 @@ fn main() {
                 .default_value("0")
                 .required(true),
         )
+        .arg(
+            Arg::with_name("EMIT")
+                .long("emit")
+                .short("o")
+                .value_name("OUTPUT")
+                .takes_value(true)
+                .help("compile the input to the given .lisaac file instead of running it"),
+        )
+        .arg(
+            Arg::with_name("DISASSEMBLE")
+                .long("disassemble")
+                .short("d")
+                .help("print the listing of the compiled code instead of running it"),
+        )
+        .arg(
+            Arg::with_name("REPL")
+                .long("repl")
+                .short("i")
+                .help("start an interactive session instead of running the input"),
+        )
+        .arg(
+            Arg::with_name("PATH")
+                .long("path")
+                .short("I")
+                .value_name("DIR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("search the imports in the given directory, before the ones of LISAA_PATH"),
+        )
+        .arg(
+            Arg::with_name("NO_BOUNDS_CHECKS")
+                .long("no-bounds-checks")
+                .help("do not check that the slice indexes are in bounds, for benchmarks"),
+        )
+        .arg(
+            Arg::with_name("OPT_LEVEL")
+                .long("opt-level")
+                .short("O")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .help("0 by default, 1 folds constants and drops dead code, 2 fuses instructions"),
+        )
+        .arg(
+            Arg::with_name("ALLOW")
+                .long("allow")
+                .short("A")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("do not report the problems found by the given lint, or by all of them"),
+        )
+        .arg(
+            Arg::with_name("WARN")
+                .long("warn")
+                .short("W")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("report the problems found by the given lint as warnings, the default"),
+        )
+        .arg(
+            Arg::with_name("DENY")
+                .long("deny")
+                .short("D")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("make the problems found by the given lint errors, or by all of them"),
+        )
+        .arg(
+            Arg::with_name("TRACE")
+                .long("trace")
+                .help("print each executed instruction with the stack and the heap to stderr"),
+        )
+        .arg(
+            Arg::with_name("TRACE_OUTPUT")
+                .long("trace-output")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("write the trace to the given file instead of stderr, implies --trace"),
+        )
+        .arg(
+            Arg::with_name("TRACE_FN")
+                .long("trace-fn")
+                .value_name("FUNCTION")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("only trace the given function, implies --trace"),
+        )
+        .arg(
+            Arg::with_name("TRACE_RANGE")
+                .long("trace-range")
+                .value_name("START..END")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("only trace the instructions in the given range, implies --trace"),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats the given files in place")
+                .arg(
+                    Arg::with_name("FILES")
+                        .index(1)
+                        .multiple(true)
+                        .required(true)
+                        .help("the files to format"),
+                )
+                .arg(
+                    Arg::with_name("CHECK")
+                        .long("check")
+                        .help("do not write the files, fail if one of them is not formatted"),
+                ),
+        )
         .get_matches();
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        exit(format_files(matches));
+    }
 
     let input_file = matches.value_of("INPUT").unwrap();
     let verbose = matches.value_of("VERBOSE").unwrap();
     let mut stdout = io::stdout();
+    let mut lisaa = Lisaa::new(input_file.to_owned(), &mut stdout, verbose == "1");
+    for dir in matches.values_of("PATH").into_iter().flat_map(|dirs| dirs) {
+        lisaa.search_path(dir);
+    }
+    if let Some(dirs) = env::var_os("LISAA_PATH") {
+        for dir in env::split_paths(&dirs) {
+            lisaa.search_path(&dir.to_string_lossy());
+        }
+    }
+    if let Err(err) = set_lint_levels(&matches, &mut lisaa) {
+        eprintln!("error: {}", err);
+        exit(1)
+    }
+    if matches.is_present("NO_BOUNDS_CHECKS") {
+        lisaa.bounds_checks(false);
+    }
+    if let Some(level) = matches.value_of("OPT_LEVEL") {
+        match level.parse::<usize>() {
+            Ok(level) => lisaa.optimize(level),
+            Err(_) => {
+                eprintln!("error: invalid optimization level : {}, expected a number", level);
+                exit(1)
+            }
+        };
+    }
+    if ["TRACE", "TRACE_OUTPUT", "TRACE_FN", "TRACE_RANGE"]
+        .iter()
+        .any(|arg| matches.is_present(arg))
+    {
+        let ranges = matches
+            .values_of("TRACE_RANGE")
+            .map(|ranges| ranges.map(parse_range).collect())
+            .unwrap_or(Ok(vec![]));
+        let ranges = ranges.unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            exit(1)
+        });
+        lisaa.trace(TraceOptions {
+            output: matches.value_of("TRACE_OUTPUT").map(|s| s.to_owned()),
+            functions: matches
+                .values_of("TRACE_FN")
+                .map(|f| f.map(|s| s.to_owned()).collect())
+                .unwrap_or(vec![]),
+            ranges: ranges,
+        });
+    }
+    let result = match matches.value_of("EMIT") {
+        Some(output) => lisaa.emit(output),
+        None if matches.is_present("DISASSEMBLE") => lisaa.disassemble(),
+        None if matches.is_present("REPL") => lisaa.repl(&mut io::stdin().lock()),
+        None if input_file.ends_with(".lisaac") => lisaa.run_compiled(),
+        None => lisaa.run(),
+    };
     exit(
-        match Lisaa::new(input_file.to_owned(), &mut stdout, verbose == "1").run() {
+        match result {
             Ok(_) => 0,
             Err(err) => {
-                eprintln!("error: {:?}", err);
+                eprintln!("error: {}", err);
                 1
             }
         },
     );
 }
+
+/// Formats the files given to the fmt command, or only checks them with --check.
+/// Returns the exit code : 1 if a file could not be read, parsed or written, or is not formatted when checking.
+fn format_files(matches: &ArgMatches) -> i32 {
+    let check = matches.is_present("CHECK");
+    let mut code = 0;
+    for file in matches.values_of("FILES").into_iter().flat_map(|files| files) {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: could not read {} : {}", file, e);
+                code = 1;
+                continue;
+            }
+        };
+        match format::format(&source, file) {
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}\n", error.render(Some(&source)));
+                }
+                code = 1;
+            }
+            Ok(ref formatted) if formatted == &source => (),
+            Ok(_) if check => {
+                eprintln!("{} is not formatted", file);
+                code = 1;
+            }
+            Ok(formatted) => {
+                if let Err(e) = fs::write(file, formatted) {
+                    eprintln!("error: could not write {} : {}", file, e);
+                    code = 1;
+                }
+            }
+        }
+    }
+    code
+}
+
+/// Sets the levels of the lints given with --allow, --warn and --deny, in this order.
+/// The name all sets the level of all the lints.
+fn set_lint_levels(matches: &ArgMatches, lisaa: &mut Lisaa) -> Result<(), String> {
+    let options = [("ALLOW", Level::Allow), ("WARN", Level::Warn), ("DENY", Level::Deny)];
+    for &(option, level) in options.iter() {
+        for name in matches.values_of(option).into_iter().flat_map(|names| names) {
+            let lints = match (name, Lint::from_name(name)) {
+                ("all", _) => LINTS.to_vec(),
+                (_, Some(lint)) => vec![lint],
+                (_, None) => {
+                    let names = LINTS.iter().map(|l| l.name()).collect::<Vec<&str>>();
+                    return Err(format!(
+                        "unknown lint : {}, expected all or one of : {}",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+            };
+            for lint in lints {
+                lisaa.lint(lint, level);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses an instruction range given as START..END.
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let bounds = range
+        .split("..")
+        .map(|b| b.trim().parse::<usize>())
+        .collect::<Vec<_>>();
+    match bounds.as_slice() {
+        [Ok(start), Ok(end)] if start <= end => Ok((*start, *end)),
+        _ => Err(format!("invalid instruction range : {}, expected START..END", range)),
+    }
+}
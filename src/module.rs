@@ -0,0 +1,840 @@
+//! The modules of a program : what each source declares, imports and makes public.
+//!
+//! All the declarations end up in the same program. Two modules can declare the same name :
+//! functions with other arguments are overloads of each other, while the functions with the same signature,
+//! the classes and the traits are qualified by their module in the program, see Module::internal.
+//! A module sees its own declarations and the public ones of the modules it imports,
+//! `import string::{String, println}` only brings the names listed.
+//! The public names of an imported module can also be qualified by its name : string::String.
+//! A public import (pub import stdlib) makes the names it brings public too.
+//!
+//! Before the typechecking, the names used are checked and replaced by the names of the declarations in the program.
+//! A name used without its module is ambiguous if the modules seen declare it under different names.
+//! Methods are not checked, they can be called wherever a value of their type is.
+use diagnostic::{Diagnostic, Span};
+use expression::{Callee, Expr, ExprEnum};
+use statement::{ClassDecl, FunctionDecl, FunctionSig, Program, Statement, TraitDecl, TypeParam};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use types::LisaaType;
+
+/// An import of a module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// The module as written in the import : tests/scripts/imp/dot
+    pub path: String,
+    /// The names imported, None if the module is imported as a whole.
+    pub names: Option<Vec<String>>,
+    /// If the names imported are public.
+    pub public: bool,
+    /// The position of the import, to show the errors.
+    pub span: Span,
+    /// The file found for the module, set once the sources are searched.
+    pub file: String,
+}
+
+impl Import {
+    /// Creates an import of the given module, its file is not known yet.
+    pub fn new(path: String, names: Option<Vec<String>>, public: bool, span: Span) -> Self {
+        Import {
+            file: path.clone(),
+            path: path,
+            names: names,
+            public: public,
+            span: span,
+        }
+    }
+}
+
+/// Returns the name qualifying the names declared in the file : tests/scripts/imp/dot.lisaa gives dot.
+pub fn module_name(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or(file.to_string())
+}
+
+/// A source of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// The file of the module, as written in the spans.
+    file: String,
+    /// The name used to qualify the names of the module, the file without its directory and extension.
+    name: String,
+    imports: Vec<Import>,
+    /// The functions, methods (Class::method), classes and traits declared.
+    declared: Vec<String>,
+    /// The signatures of the functions and methods declared, with their names.
+    signatures: Vec<(String, String)>,
+    public: HashSet<String>,
+    /// The names also declared by a module added before this one, see internal.
+    qualified: HashSet<String>,
+}
+
+impl Module {
+    /// Creates an empty module for the given file.
+    pub fn new(file: String) -> Self {
+        Module {
+            name: module_name(&file),
+            file: file,
+            imports: vec![],
+            declared: vec![],
+            signatures: vec![],
+            public: HashSet::new(),
+            qualified: HashSet::new(),
+        }
+    }
+    /// Returns the file of the module.
+    pub fn file(&self) -> &String {
+        &self.file
+    }
+    /// Returns the name qualifying the names of the module.
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    /// Returns the imports of the module.
+    pub fn imports(&self) -> &Vec<Import> {
+        &self.imports
+    }
+    /// Returns the imports of the module as mutable, to set their files.
+    pub fn imports_mut(&mut self) -> &mut Vec<Import> {
+        &mut self.imports
+    }
+    /// Adds an import to the module.
+    pub fn import(&mut self, import: Import) {
+        self.imports.push(import);
+    }
+    /// Records a declaration of the module, overloads are declared once.
+    pub fn declare(&mut self, name: String, public: bool) {
+        if public {
+            self.public.insert(name.clone());
+        }
+        if !self.declared.contains(&name) {
+            self.declared.push(name);
+        }
+    }
+    /// Records a function or a method of the module, with its signature.
+    pub fn declare_function(&mut self, sig: &FunctionSig, public: bool) {
+        self.declare(sig.name.clone(), public);
+        self.signatures.push((sig.name.clone(), sig.to_string()));
+    }
+    /// Checks if the module declares the name, written as in the module or as in the program.
+    pub fn declares(&self, name: &str) -> bool {
+        self.declared.iter().any(|d| d == name || self.internal(d) == name)
+    }
+    /// Returns the name of a declaration of the module in the program.
+    /// A name already declared by an other module is qualified, unless it is a function whose signatures differ :
+    /// the second area(num) becomes circle::area.
+    pub fn internal(&self, name: &str) -> String {
+        match self.qualified.contains(name) {
+            true => format!("{}::{}", self.name, name),
+            false => name.to_string(),
+        }
+    }
+    /// Checks if both modules declare the name, and can not share it :
+    /// it is a class or a trait in one of them, or they declare a function with the same signature.
+    fn collides(&self, other: &Module, name: &str) -> bool {
+        let overloads = |m: &Module| {
+            m.signatures
+                .iter()
+                .filter(|s| s.0 == name)
+                .map(|s| s.1.clone())
+                .collect::<Vec<String>>()
+        };
+        let (mine, theirs) = (overloads(self), overloads(other));
+        self.declared.iter().any(|d| d == name)
+            && other.declared.iter().any(|d| d == name)
+            && (mine.is_empty() || theirs.is_empty() || mine.iter().any(|s| theirs.contains(s)))
+    }
+    /// Checks if the name is declared public by the module.
+    pub fn is_public(&self, name: &str) -> bool {
+        self.public.contains(name)
+    }
+}
+
+/// Adds a module to the list, a module already there gets the new declarations and imports.
+/// The names it shares with the modules already there are qualified, see Module::internal.
+/// Returns an error if a method is declared by two modules with the same signature,
+/// the method of a class qualified by its module is not the same as the others.
+pub fn add_module(modules: &mut Vec<Module>, mut module: Module) -> Result<(), String> {
+    let qualified = module
+        .declared
+        .iter()
+        .filter(|name| !name.contains("::"))
+        .filter(|name| {
+            modules
+                .iter()
+                .any(|m| m.file != module.file && m.collides(&module, name))
+        })
+        .cloned()
+        .collect::<Vec<String>>();
+    module.qualified.extend(qualified);
+    for &(ref name, ref signature) in module.signatures.iter() {
+        let class = name.split("::").next().unwrap_or("");
+        if !name.contains("::") || module.qualified.contains(class) {
+            continue;
+        }
+        let other = modules.iter().find(|m| {
+            m.file != module.file
+                && !m.qualified.contains(class)
+                && m.signatures.iter().any(|s| &s.1 == signature)
+        });
+        if let Some(other) = other {
+            return Err(format!(
+                "{} is declared in both modules {} and {}",
+                signature, other.name, module.name
+            ));
+        }
+    }
+    match modules.iter().position(|m| m.file == module.file) {
+        Some(i) => {
+            let existing = &mut modules[i];
+            for name in module.declared {
+                let public = module.public.contains(&name);
+                existing.declare(name, public);
+            }
+            for signature in module.signatures {
+                if !existing.signatures.contains(&signature) {
+                    existing.signatures.push(signature);
+                }
+            }
+            existing.qualified.extend(module.qualified);
+            for import in module.imports {
+                if !existing.imports.contains(&import) {
+                    existing.imports.push(import);
+                }
+            }
+        }
+        None => modules.push(module),
+    }
+    Ok(())
+}
+
+/// Finds the module declaring the name.
+pub fn declaring<'a>(modules: &'a [Module], name: &str) -> Option<&'a Module> {
+    modules.iter().find(|m| m.declares(name))
+}
+
+/// Finds the module of the given file.
+fn find<'a>(modules: &'a [Module], file: &str) -> Option<&'a Module> {
+    modules.iter().find(|m| &m.file == file)
+}
+
+/// Finds the module declaring a name the module declares or brings with its public imports.
+fn origin<'a>(
+    modules: &'a [Module],
+    module: &'a Module,
+    name: &str,
+    seen: &mut HashSet<String>,
+) -> Option<&'a Module> {
+    if module.declared.iter().any(|d| d == name) {
+        return Some(module);
+    }
+    if !seen.insert(module.file.clone()) {
+        return None;
+    }
+    for import in module.imports.iter().filter(|i| i.public) {
+        let brings = import
+            .names
+            .as_ref()
+            .map_or(true, |names| names.iter().any(|n| n == name));
+        if let (true, Some(imported)) = (brings, find(modules, &import.file)) {
+            if let Some(found) = origin(modules, imported, name, seen) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+/// The names an import of the module brings : its public names and the ones of its public imports.
+fn exports(modules: &[Module], module: &Module, seen: &mut HashSet<String>) -> HashSet<String> {
+    let mut names = module.public.clone();
+    if !seen.insert(module.file.clone()) {
+        return names;
+    }
+    for import in module.imports.iter().filter(|i| i.public) {
+        match (&import.names, find(modules, &import.file)) {
+            (&Some(ref imported), _) => names.extend(imported.iter().cloned()),
+            (&None, Some(imported)) => names.extend(exports(modules, imported, seen)),
+            (&None, None) => (),
+        }
+    }
+    names
+}
+
+/// The modules whose declarations an import can bring : the imported one and the ones it imports publicly.
+pub fn imported<'a>(modules: &'a [Module], import: &Import) -> Vec<&'a Module> {
+    let mut found: Vec<&Module> = vec![];
+    let mut next = vec![import.file.clone()];
+    while let Some(file) = next.pop() {
+        if found.iter().any(|m| m.file == file) {
+            continue;
+        }
+        if let Some(module) = find(modules, &file) {
+            next.extend(module.imports.iter().filter(|i| i.public).map(|i| i.file.clone()));
+            found.push(module);
+        }
+    }
+    found
+}
+
+/// Checks the names used by the declarations of a module and replaces the qualified ones.
+/// The scope is the module with all its imports, the modules are all the ones of the program.
+pub fn resolve(
+    program: &mut Program,
+    scope: &Module,
+    modules: &[Module],
+) -> Result<(), Vec<Diagnostic>> {
+    let mut resolver = Resolver::new(scope, modules);
+    resolver.check_imports();
+    let functions = program.functions_mut().drain().collect::<Vec<_>>();
+    for (_, mut function) in functions {
+        resolver.function(&mut function);
+        resolver.rename_function(&mut function);
+        program
+            .functions_mut()
+            .insert(function.signature().clone(), function);
+    }
+    let classes = program.classes_mut().drain().collect::<Vec<_>>();
+    for (_, mut class) in classes {
+        resolver.class(&mut class);
+        class.set_name(scope.internal(class.name()));
+        program.classes_mut().insert(class.name().clone(), class);
+    }
+    let traits = program.traits_mut().drain().collect::<Vec<_>>();
+    for (_, mut tr) in traits {
+        resolver.trait_decl(&mut tr);
+        tr.set_name(scope.internal(tr.name()));
+        program.traits_mut().insert(tr.name().clone(), tr);
+    }
+    resolver.result()
+}
+
+/// Checks the names used by a function of the module, see resolve.
+pub fn resolve_function(
+    function: &mut FunctionDecl,
+    scope: &Module,
+    modules: &[Module],
+) -> Result<(), Vec<Diagnostic>> {
+    let mut resolver = Resolver::new(scope, modules);
+    resolver.function(function);
+    resolver.result()
+}
+
+/// Walks the declarations of a module, knowing the local variables and the type parameters.
+struct Resolver<'a> {
+    scope: &'a Module,
+    modules: &'a [Module],
+    /// The names usable without qualification, with the modules declaring them.
+    visible: HashMap<String, Vec<&'a Module>>,
+    /// The local variables of the function, the ones of the inner scopes are last.
+    locals: Vec<String>,
+    type_params: Vec<String>,
+    /// The position of the statement or the name of the function checked, for the errors in types.
+    context: (Option<Span>, String),
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(scope: &'a Module, modules: &'a [Module]) -> Self {
+        let mut visible: HashMap<String, Vec<&'a Module>> = HashMap::new();
+        for name in scope.declared.iter() {
+            visible.insert(name.clone(), vec![scope]);
+        }
+        for import in scope.imports.iter() {
+            let imported = match find(modules, &import.file) {
+                Some(imported) => imported,
+                None => continue,
+            };
+            let names = match import.names {
+                Some(ref names) => names.clone(),
+                None => exports(modules, imported, &mut HashSet::new())
+                    .into_iter()
+                    .collect(),
+            };
+            for name in names {
+                if let Some(found) = origin(modules, imported, &name, &mut HashSet::new()) {
+                    let declaring = visible.entry(name).or_insert_with(Vec::new);
+                    if !declaring.iter().any(|m| m.file == found.file) {
+                        declaring.push(found);
+                    }
+                }
+            }
+        }
+        Resolver {
+            scope: scope,
+            modules: modules,
+            visible: visible,
+            locals: vec![],
+            type_params: vec![],
+            context: (None, String::new()),
+            errors: vec![],
+        }
+    }
+
+    fn result(self) -> Result<(), Vec<Diagnostic>> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.errors),
+        }
+    }
+
+    /// Reports an error at the position given or, if there is none, at the current context.
+    fn report(&mut self, message: String, span: Option<&Span>) {
+        let error = match (span.or(self.context.0.as_ref()), &self.context.1) {
+            (Some(span), _) => Diagnostic::at(message, span.clone()),
+            (None, function) => Diagnostic::new(format!("in {} : {}", function, message)),
+        };
+        self.errors.push(error);
+    }
+
+    /// Checks that the names listed by the imports are public in their modules.
+    fn check_imports(&mut self) {
+        let mut errors = vec![];
+        for import in self.scope.imports.iter() {
+            let (names, module) = match (&import.names, find(self.modules, &import.file)) {
+                (&Some(ref names), Some(module)) => (names, module),
+                _ => continue,
+            };
+            let public = exports(self.modules, module, &mut HashSet::new());
+            for name in names.iter().filter(|n| !public.contains(*n)) {
+                let message = match module.declares(name) {
+                    true => format!("{} is private to module {}", name, module.name),
+                    false => format!("module {} has no {}", module.name, name),
+                };
+                errors.push(Diagnostic::at(message, import.span.clone()));
+            }
+        }
+        self.errors.extend(errors);
+    }
+
+    /// Returns the name in the program of the declaration a name refers to,
+    /// reporting the error if it can not be used there.
+    /// The names no module declares, like the local variables or the natives, are left alone.
+    fn name(&mut self, name: &str, span: Option<&Span>) -> String {
+        self.qualified_name(name, span).0
+    }
+
+    /// Resolves a name like name does, with the module declaring it if the name is qualified.
+    fn qualified_name(&mut self, name: &str, span: Option<&Span>) -> (String, Option<&'a Module>) {
+        let (qualifier, plain) = match name.find("::") {
+            Some(i) => (Some(&name[..i]), &name[i + 2..]),
+            None => (None, name),
+        };
+        let mut resolved = plain.to_string();
+        let mut declaring_module = None;
+        let message = match qualifier {
+            Some(q) if q == self.scope.name && self.scope.declared.iter().any(|d| d == plain) => {
+                resolved = self.scope.internal(plain);
+                declaring_module = Some(self.scope);
+                None
+            }
+            Some(q) => match self.qualified_module(q) {
+                None => Some(format!(
+                    "unknown module {}, it must be imported to use {}",
+                    q, name
+                )),
+                Some(module) => {
+                    let public = exports(self.modules, module, &mut HashSet::new());
+                    let found = origin(self.modules, module, plain, &mut HashSet::new());
+                    if let Some(found) = found {
+                        resolved = found.internal(plain);
+                        declaring_module = Some(found);
+                    }
+                    match (public.contains(plain), module.declares(plain)) {
+                        (true, _) => None,
+                        (false, true) => Some(format!("{} is private to module {}", plain, q)),
+                        (false, false) => Some(format!("module {} has no {}", q, plain)),
+                    }
+                }
+            },
+            None if self.visible.contains_key(plain) => {
+                let declaring = &self.visible[plain];
+                resolved = declaring[0].internal(plain);
+                match declaring.iter().all(|m| m.internal(plain) == resolved) {
+                    true => None,
+                    false => {
+                        let names = declaring.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+                        Some(format!(
+                            "ambiguous name {}, it is declared in modules {} and {}, qualify it with its module",
+                            plain,
+                            names[..names.len() - 1].join(", "),
+                            names[names.len() - 1]
+                        ))
+                    }
+                }
+            }
+            None => match declaring(self.modules, plain) {
+                Some(module) if !module.is_public(plain) => {
+                    Some(format!("{} is private to module {}", plain, module.name))
+                }
+                Some(module) => Some(format!(
+                    "{} is declared in module {} which is not imported here",
+                    plain, module.name
+                )),
+                None => None,
+            },
+        };
+        if let Some(message) = message {
+            self.report(message, span);
+        }
+        (resolved, declaring_module)
+    }
+
+    /// Finds the module imported by the scope with the given name.
+    fn qualified_module(&self, name: &str) -> Option<&'a Module> {
+        let modules = self.modules;
+        self.scope
+            .imports
+            .iter()
+            .filter_map(|i| find(modules, &i.file))
+            .find(|m| &m.name == name)
+    }
+
+    fn lisaa_type(&mut self, tp: &mut LisaaType) {
+        match tp {
+            &mut LisaaType::Class(ref mut name, ref mut params) => {
+                if !self.type_params.contains(name) && !["Self", "slice"].contains(&name.as_str()) {
+                    *name = self.name(name, None);
+                }
+                for param in params.iter_mut() {
+                    self.lisaa_type(param);
+                }
+            }
+            &mut LisaaType::Pointer(ref mut inner) => self.lisaa_type(inner),
+            &mut LisaaType::FunctionPointer(ref mut args, ref mut ret) => {
+                for arg in args.iter_mut() {
+                    self.lisaa_type(arg);
+                }
+                self.lisaa_type(ret);
+            }
+            _ => (),
+        }
+    }
+
+    /// Resolves the traits of the type parameters, their names are then types.
+    fn type_param_list(&mut self, params: &[TypeParam]) -> Vec<TypeParam> {
+        let mut resolved = vec![];
+        for param in params {
+            self.type_params.push(param.name().clone());
+            let trait_name = match param.trait_name().as_ref() {
+                "Any" => "Any".to_string(),
+                name => self.name(name, None),
+            };
+            resolved.push(TypeParam::new(param.name().clone(), trait_name));
+        }
+        resolved
+    }
+
+    fn signature(&mut self, sig: &FunctionSig) -> FunctionSig {
+        let mut sig = sig.clone();
+        sig.type_args = self.type_param_list(&sig.type_args);
+        for arg in sig.args.iter_mut() {
+            self.lisaa_type(arg);
+        }
+        self.lisaa_type(&mut sig.ret_type);
+        if let Some(ref mut self_type) = sig.self_type {
+            self.lisaa_type(self_type);
+        }
+        sig
+    }
+
+    fn function(&mut self, function: &mut FunctionDecl) {
+        let (params, locals) = (self.type_params.len(), self.locals.len());
+        let context = (self.context.0.clone(), function.name().clone());
+        let context = ::std::mem::replace(&mut self.context, context);
+        let sig = self.signature(function.signature());
+        function.set_signature(sig);
+        self.locals
+            .extend(function.args().iter().map(|a| a.name().to_string()));
+        self.statement(&mut function.scope);
+        self.type_params.truncate(params);
+        self.locals.truncate(locals);
+        self.context = context;
+    }
+
+    /// Gives a function of the module its name in the program, see Module::internal.
+    /// A method follows its class : Square::area is a method of the class named by the type of self.
+    fn rename_function(&mut self, function: &mut FunctionDecl) {
+        let mut sig = function.signature().clone();
+        sig.name = match (sig.name.rfind("::"), &sig.self_type) {
+            (Some(i), &Some(LisaaType::Class(ref class, _))) => {
+                format!("{}::{}", class, &sig.name[i + 2..])
+            }
+            (Some(_), _) => sig.name.clone(),
+            (None, _) => self.scope.internal(&sig.name),
+        };
+        function.name = sig.name.clone();
+        function.set_signature(sig);
+    }
+
+    fn class(&mut self, class: &mut ClassDecl) {
+        self.context = (None, class.name().clone());
+        self.type_params = class
+            .type_params()
+            .iter()
+            .map(|t| t.name().clone())
+            .collect();
+        for attr in class.attributes_mut().iter_mut() {
+            self.context.0 = Some(attr.expr().span().clone());
+            self.lisaa_type(attr.val_type_mut());
+            self.expr(attr.expr_mut());
+        }
+        self.type_params.clear();
+    }
+
+    fn trait_decl(&mut self, tr: &mut TraitDecl) {
+        self.context = (None, tr.name().clone());
+        for sub in tr.sub_traits_mut().iter_mut() {
+            if sub.as_str() != "Any" {
+                *sub = self.name(sub, None);
+            }
+        }
+        for method in tr.methods_mut().values_mut() {
+            *method = self.signature(method);
+            self.type_params.clear();
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Statement) {
+        match statement {
+            &mut Statement::ExprStatement(ref mut e)
+            | &mut Statement::ReturnStatement(ref mut e) => self.expr(e),
+            &mut Statement::Assignment(ref mut a) => {
+                self.expr(a.assignee_mut());
+                self.expr(a.expr_mut());
+            }
+            &mut Statement::Declaration(ref mut d) => {
+                self.context.0 = Some(d.expr().span().clone());
+                self.lisaa_type(d.val_type_mut());
+                self.expr(d.expr_mut());
+                self.locals.push(d.name().clone());
+            }
+            &mut Statement::Scope(ref mut statements) => {
+                let locals = self.locals.len();
+                for s in statements.iter_mut() {
+                    self.statement(s);
+                }
+                self.locals.truncate(locals);
+            }
+            &mut Statement::IfStatement(ref mut i) => {
+                self.expr(i.condition_mut());
+                self.statement(i.statement_mut());
+                if let Some(e) = i.else_statement_mut() {
+                    self.statement(e);
+                }
+            }
+            &mut Statement::WhileStatement(ref mut w) => {
+                self.expr(w.condition_mut());
+                self.statement(w.statement_mut());
+            }
+            &mut Statement::BreakStatement | &mut Statement::Native(_) => (),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let span = expr.span().clone();
+        match expr.expr_mut() {
+            &mut ExprEnum::Unary(ref mut u) => self.expr(u.expression_mut()),
+            // the right hand side is the name of an attribute or a method.
+            &mut ExprEnum::GetAttr(ref mut b) => self.expr(b.lhs_mut()),
+            &mut ExprEnum::Literal(_) => (),
+            &mut ExprEnum::Identifier(ref mut name) => {
+                if !self.locals.contains(name) {
+                    *name = self.name(name, Some(&span));
+                }
+            }
+            &mut ExprEnum::FunctionCall(ref mut call) => {
+                let name = match call.callee() {
+                    &Callee::StaticFunc(ref name) if !self.locals.contains(name) => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                };
+                if let Some(name) = name {
+                    let (plain, module) = self.qualified_name(&name, Some(&span));
+                    if let Some(module) = module {
+                        call.set_module(module.file.clone());
+                    }
+                    call.set_name(&plain);
+                    *call.callee_mut() = Callee::StaticFunc(plain);
+                }
+                if let &mut Callee::Method(ref mut e) = call.callee_mut() {
+                    self.expr(e);
+                }
+                for tp in call.type_args_mut().iter_mut() {
+                    self.lisaa_type(tp);
+                }
+                for arg in call.args_mut().iter_mut() {
+                    self.expr(arg);
+                }
+            }
+            &mut ExprEnum::Deref(ref mut d) => self.expr(d.inner_mut()),
+            &mut ExprEnum::FunctionRef(ref mut f) => {
+                let (plain, module) = self.qualified_name(f.name(), Some(&span));
+                if let Some(module) = module {
+                    f.set_module(module.file.clone());
+                }
+                f.set_name(plain);
+                for tp in f.type_args_mut().iter_mut() {
+                    self.lisaa_type(tp);
+                }
+            }
+            &mut ExprEnum::Closure(ref mut c) => self.function(c.decl_mut()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_module {
+    use super::*;
+    use parser::Parser;
+    use scanner::Scanner;
+
+    /// Parses the sources as modules importing each other by their file names.
+    fn resolve_all(sources: &[(&str, &str)]) -> Result<Vec<Program>, Vec<String>> {
+        let mut parsed = vec![];
+        let mut modules = vec![];
+        for &(file, source) in sources {
+            let tokens = Scanner::new(source.to_string(), file.to_string())
+                .tokens()
+                .unwrap();
+            let (program, module) = Parser::new(tokens).program().unwrap();
+            add_module(&mut modules, module.clone()).map_err(|e| vec![e])?;
+            parsed.push((program, module));
+        }
+        let mut errors = vec![];
+        let mut programs = vec![];
+        for (mut program, module) in parsed {
+            let scope = find(&modules, &module.file).unwrap();
+            if let Err(e) = resolve(&mut program, scope, &modules) {
+                errors.extend(e.iter().map(|e| e.message().clone()));
+            }
+            programs.push(program);
+        }
+        match errors.is_empty() {
+            true => Ok(programs),
+            false => Err(errors),
+        }
+    }
+
+    const GEOMETRY: &str = "pub Class Point { num x = 0; }\nfn helper() -> num { return 1; }\npub fn origin() -> Point { return Point(); }";
+
+    #[test]
+    fn test_qualified_names() {
+        let programs = resolve_all(&[
+            ("geometry", GEOMETRY),
+            (
+                "main",
+                "import geometry::{origin}\nfn main() { geometry::Point p = geometry::origin(); }",
+            ),
+        ])
+        .unwrap();
+        let main = programs[1].functions().values().next().unwrap();
+        match main.scope()[0] {
+            Statement::Declaration(ref d) => {
+                assert_eq!(d.val_type(), &LisaaType::Class("Point".to_string(), vec![]))
+            }
+            ref s => panic!("not a declaration : {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_visibility() {
+        assert_eq!(
+            resolve_all(&[
+                ("geometry", GEOMETRY),
+                ("main", "import geometry::{origin, helper}\nfn main() { Point p = origin(); num helper = 2; helper; }"),
+            ])
+            .err()
+            .unwrap(),
+            vec![
+                "helper is private to module geometry".to_string(),
+                "Point is declared in module geometry which is not imported here".to_string(),
+            ]
+        );
+        assert_eq!(
+            resolve_all(&[
+                ("geometry", GEOMETRY),
+                ("main", "fn main() { geometry::helper(); }")
+            ])
+            .err()
+            .unwrap(),
+            vec![
+                "unknown module geometry, it must be imported to use geometry::helper".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_names_in_modules() {
+        let circle = "pub fn area(num r) -> num { return 3 * r * r; }";
+        let square = "pub fn area(num side) -> num { return side * side; }";
+        let programs = resolve_all(&[
+            ("circle", circle),
+            ("square", square),
+            ("main", "import circle\nimport square\nfn main() { square::area(1); }"),
+        ])
+        .unwrap();
+        assert!(programs[1].functions().keys().any(|f| f.name == "square::area"));
+        let main = programs[2].functions().values().next().unwrap();
+        match main.scope()[0] {
+            Statement::ExprStatement(ref e) => match e.expr() {
+                &ExprEnum::FunctionCall(ref call) => {
+                    assert_eq!(call.name(), "square::area".to_string());
+                    assert_eq!(call.module(), Some(&"square".to_string()));
+                }
+                e => panic!("not a call : {:?}", e),
+            },
+            ref s => panic!("not an expression : {:?}", s),
+        }
+        assert_eq!(
+            resolve_all(&[
+                ("circle", circle),
+                ("square", square),
+                ("main", "import circle\nimport square\nfn main() { area(1); }"),
+            ])
+            .err()
+            .unwrap(),
+            vec![
+                "ambiguous name area, it is declared in modules circle and square, qualify it with its module"
+                    .to_string()
+            ]
+        );
+        // the overloads of other modules are not qualified.
+        let programs = resolve_all(&[
+            ("circle", circle),
+            ("main", "import circle\nfn area(bool b) -> num { return 0; }\nfn main() { area(1); }"),
+        ])
+        .unwrap();
+        assert!(programs[1].functions().keys().all(|f| f.name != "main::area"));
+    }
+
+    #[test]
+    fn test_duplicate_methods() {
+        assert_eq!(
+            resolve_all(&[
+                ("geometry", GEOMETRY),
+                ("main", "import geometry\nmethod helper() -> num of Point { return 1; }"),
+                ("other", "import geometry\nmethod helper() -> num of Point { return 2; }"),
+            ])
+            .err()
+            .unwrap(),
+            vec!["Point::helper() -> num is declared in both modules main and other".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_public_imports() {
+        assert!(resolve_all(&[
+            ("geometry", GEOMETRY),
+            ("shapes", "pub import geometry::{Point}"),
+            (
+                "main",
+                "import shapes\nfn main() { Point p = shapes::Point(); }"
+            ),
+        ])
+        .is_ok());
+    }
+}
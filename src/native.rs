@@ -1,6 +1,7 @@
 use statement::{ClassDecl, FunctionDecl, FunctionSig, Statement, TraitDecl, TypeParam};
 use std::collections::HashMap;
 use types::{LisaaType, TypedVar};
+use vm::ffi::{NativeFunction, NativeType};
 use vm::OP;
 
 /// A native function.
@@ -10,7 +11,9 @@ trait NativeFunc {
     fn rand() -> FunctionDecl;
     fn num_funcs() -> Vec<FunctionDecl>;
     fn char_funcs() -> Vec<FunctionDecl>;
+    fn bool_funcs() -> Vec<FunctionDecl>;
     fn slice_funcs() -> Vec<FunctionDecl>;
+    fn fn_funcs() -> Vec<FunctionDecl>;
 }
 
 impl NativeFunc for FunctionDecl {
@@ -82,7 +85,7 @@ impl NativeFunc for FunctionDecl {
                 "slice::index".to_owned(),
                 vec![TypeParam::new("T".to_string(), "Any".to_string())],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
-                Statement::Native(vec![OP::Add]),
+                Statement::Native(vec![OP::CheckIndex, OP::Add]),
                 LisaaType::Class("T".to_string(), vec![]),
             ),
             FunctionDecl::new_complete(
@@ -91,7 +94,19 @@ impl NativeFunc for FunctionDecl {
                     vec![LisaaType::Class("T".to_string(), vec![])],
                 )),
                 true,
-                "slice::isOject".to_owned(),
+                "slice::len".to_owned(),
+                vec![TypeParam::new("T".to_string(), "Any".to_string())],
+                vec![],
+                Statement::Native(vec![OP::SliceLen]),
+                LisaaType::Num,
+            ),
+            FunctionDecl::new_complete(
+                Some(LisaaType::Class(
+                    "slice".to_string(),
+                    vec![LisaaType::Class("T".to_string(), vec![])],
+                )),
+                true,
+                "slice::isObject".to_owned(),
                 vec![],
                 vec![],
                 Statement::Native(vec![]),
@@ -111,6 +126,76 @@ impl NativeFunc for FunctionDecl {
                 LisaaType::Void,
             )]
     }
+    /// Function values are objects, they can be put in slices of pointers.
+    fn fn_funcs() -> Vec<Self> {
+        vec![FunctionDecl::new_complete(
+            None,
+            true,
+            "fn::isObject".to_owned(),
+            vec![],
+            vec![],
+            Statement::Native(vec![]),
+            LisaaType::Void,
+        )]
+    }
+    fn bool_funcs() -> Vec<Self> {
+        vec![
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::isNotObject".to_owned(),
+                vec![],
+                vec![],
+                Statement::Native(vec![]),
+                LisaaType::Void,
+            ),
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::andand".to_owned(),
+                vec![],
+                vec![TypedVar::new(LisaaType::Bool, "b".to_string())],
+                Statement::Native(vec![OP::AndAnd]),
+                LisaaType::Bool,
+            ),
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::oror".to_owned(),
+                vec![],
+                vec![TypedVar::new(LisaaType::Bool, "b".to_string())],
+                Statement::Native(vec![OP::OrOr]),
+                LisaaType::Bool,
+            ),
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::not".to_owned(),
+                vec![],
+                vec![],
+                Statement::Native(vec![OP::Not]),
+                LisaaType::Bool,
+            ),
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::equals".to_owned(),
+                vec![],
+                vec![TypedVar::new(LisaaType::Bool, "b".to_string())],
+                Statement::Native(vec![OP::Eq]),
+                LisaaType::Bool,
+            ),
+            FunctionDecl::new_complete(
+                None,
+                true,
+                "bool::ne".to_owned(),
+                vec![],
+                vec![TypedVar::new(LisaaType::Bool, "b".to_string())],
+                Statement::Native(vec![OP::Eq, OP::Not]),
+                LisaaType::Bool,
+            ),
+        ]
+    }
     fn num_funcs() -> Vec<Self> {
         vec![
             FunctionDecl::new_complete(
@@ -158,24 +243,6 @@ impl NativeFunc for FunctionDecl {
                 Statement::Native(vec![OP::Swap2, OP::Mod]),
                 LisaaType::Num,
             ),
-            FunctionDecl::new_complete(
-                None,
-                true,
-                "num::andand".to_owned(),
-                vec![],
-                vec![TypedVar::new(LisaaType::Num, "n".to_string())],
-                Statement::Native(vec![OP::AndAnd]),
-                LisaaType::Num,
-            ),
-            FunctionDecl::new_complete(
-                None,
-                true,
-                "num::oror".to_owned(),
-                vec![],
-                vec![TypedVar::new(LisaaType::Num, "n".to_string())],
-                Statement::Native(vec![OP::OrOr]),
-                LisaaType::Num,
-            ),
             FunctionDecl::new_complete(
                 None,
                 true,
@@ -203,15 +270,6 @@ impl NativeFunc for FunctionDecl {
                 Statement::Native(vec![OP::Inv, OP::Mul]),
                 LisaaType::Num,
             ),
-            FunctionDecl::new_complete(
-                None,
-                true,
-                "num::not".to_owned(),
-                vec![],
-                vec![],
-                Statement::Native(vec![OP::Not]),
-                LisaaType::Num,
-            ),
             FunctionDecl::new_complete(
                 None,
                 true,
@@ -228,7 +286,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::Eq, OP::Not]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -237,7 +295,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::Eq]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -246,7 +304,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::LowerEq]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -255,7 +313,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::GreaterEq]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -264,7 +322,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::GreaterThan]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -273,7 +331,7 @@ impl NativeFunc for FunctionDecl {
                 vec![],
                 vec![TypedVar::new(LisaaType::Num, "n".to_string())],
                 Statement::Native(vec![OP::LowerThan]),
-                LisaaType::Num,
+                LisaaType::Bool,
             ),
             FunctionDecl::new_complete(
                 None,
@@ -298,12 +356,61 @@ pub fn get_native_funcs(library: &str) -> Vec<FunctionDecl> {
             ];
             base.append(&mut FunctionDecl::num_funcs());
             base.append(&mut FunctionDecl::char_funcs());
+            base.append(&mut FunctionDecl::bool_funcs());
             base.append(&mut FunctionDecl::slice_funcs());
+            base.append(&mut FunctionDecl::fn_funcs());
             base
         } //, time, rand],
         _ => vec![],
     }
 }
+/// Declares a function registered by the host program, it is called by its id.
+pub fn get_ffi_func(id: usize, native: &NativeFunction) -> FunctionDecl {
+    FunctionDecl::new_complete(
+        None,
+        true,
+        native.name.clone(),
+        vec![],
+        native
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| TypedVar::new(get_ffi_type(arg), format!("arg{}", i)))
+            .collect(),
+        Statement::Native(vec![OP::CallNative(id)]),
+        get_ffi_type(&native.ret),
+    )
+}
+
+/// The lisaa type of the values of a native type.
+fn get_ffi_type(native_type: &NativeType) -> LisaaType {
+    match native_type {
+        &NativeType::Num => LisaaType::Num,
+        &NativeType::Char => LisaaType::Char,
+        &NativeType::Bool => LisaaType::Bool,
+        &NativeType::String => LisaaType::Class("String".to_string(), vec![]),
+        &NativeType::Slice(ref inner) => LisaaType::slice(get_ffi_type(inner)),
+        &NativeType::Void => LisaaType::Void,
+    }
+}
+
+/// The native type of the values of a lisaa type, if they can be given to the host.
+pub fn get_native_type(lisaa_type: &LisaaType) -> Option<NativeType> {
+    match lisaa_type {
+        &LisaaType::Num => Some(NativeType::Num),
+        &LisaaType::Char => Some(NativeType::Char),
+        &LisaaType::Bool => Some(NativeType::Bool),
+        &LisaaType::Void => Some(NativeType::Void),
+        &LisaaType::Class(ref name, ref params) if name == "String" && params.is_empty() => {
+            Some(NativeType::String)
+        }
+        &LisaaType::Class(ref name, ref params) if name == "slice" && params.len() == 1 => {
+            get_native_type(&params[0]).map(|inner| NativeType::Slice(Box::new(inner)))
+        }
+        _ => None,
+    }
+}
+
 pub fn get_native_types() -> Vec<ClassDecl> {
     return vec![ClassDecl::new(
         "slice".to_string(),
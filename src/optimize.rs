@@ -0,0 +1,346 @@
+//! The optimizations of the checked program, done before it is compiled.
+//!
+//! The operators of constant nums and bools are computed like their native code would do it,
+//! so the result does not change : a / b is a * (1 / b) and a % b works on integers.
+//! The operations that fail at runtime, like a modulo by zero, are left to fail.
+//! The branches whose condition is constant are replaced by the one that runs,
+//! then the functions no longer called from the roots are dropped.
+use expression::{Callee, Expr, ExprEnum, LiteralExpr, Operator};
+use statement::{FunctionSig, Program, Statement};
+use std::collections::HashSet;
+use types::LisaaType;
+
+/// Optimizes the monomorphised program, keeping the functions called from the roots.
+pub fn optimize(program: &mut Program, roots: &[FunctionSig]) {
+    for function in program.functions_mut().values_mut() {
+        if !function.inline {
+            statement(&mut function.scope);
+        }
+    }
+    let reached = reachable(program, roots);
+    let functions = program
+        .functions()
+        .iter()
+        .filter(|&(sig, _)| reached.contains(sig))
+        .map(|(sig, function)| (sig.clone(), function.clone()))
+        .collect();
+    program.set_functions(functions);
+}
+
+/// A constant value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Num(f64),
+    Bool(bool),
+}
+
+/// Folds the expressions of the statement and removes its dead branches.
+fn statement(statement: &mut Statement) {
+    match statement {
+        &mut Statement::ExprStatement(ref mut e) | &mut Statement::ReturnStatement(ref mut e) => {
+            fold(e)
+        }
+        &mut Statement::Assignment(ref mut a) => {
+            fold(a.expr_mut());
+            fold(a.assignee_mut());
+        }
+        &mut Statement::Declaration(ref mut d) => fold(d.expr_mut()),
+        &mut Statement::Scope(ref mut statements) => {
+            for s in statements.iter_mut() {
+                self::statement(s);
+            }
+            statements.retain(|s| !is_empty(s));
+        }
+        &mut Statement::IfStatement(ref mut i) => {
+            fold(i.condition_mut());
+            self::statement(i.statement_mut());
+            if let Some(else_statement) = i.else_statement_mut() {
+                self::statement(else_statement);
+            }
+        }
+        &mut Statement::WhileStatement(ref mut w) => {
+            fold(w.condition_mut());
+            self::statement(w.statement_mut());
+        }
+        &mut Statement::BreakStatement | &mut Statement::Native(_) => (),
+    }
+    let alive = match statement {
+        &mut Statement::IfStatement(ref i) => match value(i.condition()) {
+            Some(Value::Bool(true)) => Some(scoped(i.statement().clone())),
+            Some(Value::Bool(false)) => Some(match i.else_statement() {
+                Some(else_statement) => scoped(else_statement.clone()),
+                None => Statement::Scope(vec![]),
+            }),
+            _ => None,
+        },
+        &mut Statement::WhileStatement(ref w) => match value(w.condition()) {
+            Some(Value::Bool(false)) => Some(Statement::Scope(vec![])),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(alive) = alive {
+        *statement = alive;
+    }
+}
+
+/// Puts the branch that runs in a scope, its variables are still popped at its end.
+fn scoped(branch: Statement) -> Statement {
+    match branch {
+        Statement::Scope(_) => branch,
+        branch => Statement::Scope(vec![branch]),
+    }
+}
+
+fn is_empty(statement: &Statement) -> bool {
+    match statement {
+        &Statement::Scope(ref statements) => statements.is_empty(),
+        _ => false,
+    }
+}
+
+/// Folds the constant operations of the expression, the inner ones first.
+fn fold(expr: &mut Expr) {
+    match expr.expr_mut() {
+        &mut ExprEnum::Unary(ref mut u) => fold(u.expression_mut()),
+        &mut ExprEnum::GetAttr(ref mut attr) => fold(attr.lhs_mut()),
+        &mut ExprEnum::FunctionCall(ref mut call) => {
+            match call.callee_mut() {
+                &mut Callee::Method(ref mut e) | &mut Callee::Value(ref mut e) => fold(e),
+                &mut Callee::StaticFunc(_) => (),
+            }
+            for arg in call.args_mut() {
+                fold(arg);
+            }
+        }
+        &mut ExprEnum::Deref(ref mut d) => fold(d.inner_mut()),
+        &mut ExprEnum::Closure(ref mut c) => statement(&mut c.decl_mut().scope),
+        &mut ExprEnum::Literal(_)
+        | &mut ExprEnum::Identifier(_)
+        | &mut ExprEnum::FunctionRef(_) => (),
+    }
+    let span = expr.span().clone();
+    match constant(expr) {
+        Some(Value::Num(n)) => *expr = Expr::number(n, span),
+        Some(Value::Bool(b)) => *expr = Expr::boolean(b, span),
+        None => (),
+    }
+}
+
+/// Returns the value of a literal num or bool.
+fn value(expr: &Expr) -> Option<Value> {
+    match (expr.expr(), expr.return_type_uncheck()) {
+        (&ExprEnum::Literal(LiteralExpr::NUMBER(n)), &Some(LisaaType::Num)) => Some(Value::Num(n)),
+        (&ExprEnum::Literal(LiteralExpr::NUMBER(n)), &Some(LisaaType::Bool)) => {
+            Some(Value::Bool(n != 0.0))
+        }
+        _ => None,
+    }
+}
+
+/// Computes the operation if its operands are constant, None if it is not an operation
+/// on constants or if it would fail at runtime.
+fn constant(expr: &Expr) -> Option<Value> {
+    let result = match expr.expr() {
+        &ExprEnum::Unary(ref u) => match (u.operator(), value(u.expression())?) {
+            (Operator::MINUS, Value::Num(n)) => Value::Num(-n),
+            (Operator::Not, Value::Bool(b)) => Value::Bool(!b),
+            _ => return None,
+        },
+        &ExprEnum::FunctionCall(ref call) => {
+            let sig = call.checked_signature()?;
+            let receiver = match call.callee() {
+                &Callee::Method(_) => value(call.callee().get_method()?)?,
+                _ => return None,
+            };
+            let args = call.args().iter().map(value).collect::<Option<Vec<Value>>>()?;
+            operation(&sig.name, receiver, args.first().cloned())?
+        }
+        _ => return None,
+    };
+    match result {
+        Value::Num(n) if !n.is_finite() => None,
+        result => Some(result),
+    }
+}
+
+/// Computes a native method of num or bool, with the operands in the order the vm pops them.
+fn operation(name: &str, lhs: Value, rhs: Option<Value>) -> Option<Value> {
+    let boolean = |b: bool| Value::Bool(b);
+    Some(match (name, lhs, rhs) {
+        ("num::add", Value::Num(a), Some(Value::Num(b))) => Value::Num(b + a),
+        ("num::minus", Value::Num(a), Some(Value::Num(b))) => Value::Num(-b + a),
+        ("num::times", Value::Num(a), Some(Value::Num(b))) => Value::Num(b * a),
+        ("num::divide", Value::Num(a), Some(Value::Num(b))) => Value::Num(1.0 / b * a),
+        ("num::modulo", Value::Num(a), Some(Value::Num(b))) if b as i32 != 0 => {
+            Value::Num((a as i32).wrapping_rem(b as i32) as f64)
+        }
+        ("num::or", Value::Num(a), Some(Value::Num(b))) => Value::Num((b as i64 | a as i64) as f64),
+        ("num::negate", Value::Num(a), None) => Value::Num(-a),
+        ("num::equals", Value::Num(a), Some(Value::Num(b))) => boolean(b == a),
+        ("num::ne", Value::Num(a), Some(Value::Num(b))) => boolean(b != a),
+        ("num::ge", Value::Num(a), Some(Value::Num(b))) => boolean(b <= a),
+        ("num::le", Value::Num(a), Some(Value::Num(b))) => boolean(b >= a),
+        ("num::less", Value::Num(a), Some(Value::Num(b))) => boolean(b > a),
+        ("num::greater", Value::Num(a), Some(Value::Num(b))) => boolean(b < a),
+        ("bool::andand", Value::Bool(a), Some(Value::Bool(b))) => boolean(a && b),
+        ("bool::oror", Value::Bool(a), Some(Value::Bool(b))) => boolean(a || b),
+        ("bool::not", Value::Bool(a), None) => boolean(!a),
+        ("bool::equals", Value::Bool(a), Some(Value::Bool(b))) => boolean(a == b),
+        ("bool::ne", Value::Bool(a), Some(Value::Bool(b))) => boolean(a != b),
+        _ => return None,
+    })
+}
+
+/// Finds the functions called from the roots, directly or not.
+fn reachable(program: &Program, roots: &[FunctionSig]) -> HashSet<FunctionSig> {
+    let mut reached = HashSet::new();
+    let mut next = roots.to_vec();
+    while let Some(sig) = next.pop() {
+        if !reached.insert(sig.clone()) {
+            continue;
+        }
+        if let Some(function) = program.functions().get(&sig) {
+            called_by_statement(&function.scope, &mut next);
+        }
+    }
+    reached
+}
+
+/// Adds the functions called or referenced by the statement.
+fn called_by_statement(statement: &Statement, called: &mut Vec<FunctionSig>) {
+    match statement {
+        &Statement::ExprStatement(ref e) | &Statement::ReturnStatement(ref e) => {
+            called_by(e, called)
+        }
+        &Statement::Assignment(ref a) => {
+            called_by(a.assignee(), called);
+            called_by(a.expr(), called);
+        }
+        &Statement::Declaration(ref d) => called_by(d.expr(), called),
+        &Statement::Scope(ref statements) => {
+            for s in statements {
+                called_by_statement(s, called);
+            }
+        }
+        &Statement::IfStatement(ref i) => {
+            called_by(i.condition(), called);
+            called_by_statement(i.statement(), called);
+            if let Some(else_statement) = i.else_statement() {
+                called_by_statement(else_statement, called);
+            }
+        }
+        &Statement::WhileStatement(ref w) => {
+            called_by(w.condition(), called);
+            called_by_statement(w.statement(), called);
+        }
+        &Statement::BreakStatement | &Statement::Native(_) => (),
+    }
+}
+
+/// Adds the functions called or referenced by the expression.
+fn called_by(expr: &Expr, called: &mut Vec<FunctionSig>) {
+    match expr.expr() {
+        &ExprEnum::Unary(ref u) => called_by(u.expression(), called),
+        &ExprEnum::GetAttr(ref attr) => called_by(attr.lhs(), called),
+        &ExprEnum::FunctionCall(ref call) => {
+            match call.callee() {
+                &Callee::Method(ref e) | &Callee::Value(ref e) => called_by(e, called),
+                &Callee::StaticFunc(_) => (),
+            }
+            called.extend(call.checked_signature().cloned());
+            for arg in call.args() {
+                called_by(arg, called);
+            }
+        }
+        &ExprEnum::Deref(ref d) => called_by(d.inner(), called),
+        &ExprEnum::FunctionRef(ref reference) => {
+            called.extend(reference.checked_signature().cloned())
+        }
+        &ExprEnum::Closure(ref c) => called_by_statement(&c.decl().scope, called),
+        &ExprEnum::Literal(_) | &ExprEnum::Identifier(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests_optimize {
+    use super::*;
+    use parser::Parser;
+    use scanner::Scanner;
+    use typecheck::TypeChecker;
+
+    fn optimized(source: &str) -> Program {
+        let tokens = Scanner::new(source.to_string(), "test.lisaa".to_string())
+            .tokens()
+            .unwrap();
+        let (mut program, _) = Parser::new(tokens).program().unwrap();
+        TypeChecker::new().resolve(&mut program).unwrap();
+        let main = FunctionSig::new(vec![], vec![], LisaaType::Void, "main".to_string(), None);
+        optimize(&mut program, &[main]);
+        program
+    }
+
+    fn main_body(program: &Program) -> Vec<Statement> {
+        program
+            .functions()
+            .values()
+            .find(|f| f.name() == "main")
+            .unwrap()
+            .scope()
+            .clone()
+    }
+
+    fn declared(statement: &Statement) -> Option<Value> {
+        match statement {
+            &Statement::Declaration(ref d) => value(d.expr()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_folds_constants() {
+        let program = optimized(
+            "fn main() {\n    num a = (1 + 2) * 4 - 10 / 4;\n    bool b = !(a > 3) || 2 >= 2;\n\
+             num c = -(7 % 3);\n    num d = a * 2;\n    print(d);\n}",
+        );
+        let body = main_body(&program);
+        assert_eq!(declared(&body[0]), Some(Value::Num(9.5)));
+        assert_eq!(declared(&body[1]), None);
+        assert_eq!(declared(&body[2]), Some(Value::Num(-1.0)));
+        assert_eq!(declared(&body[3]), None);
+    }
+
+    #[test]
+    fn test_keeps_runtime_errors() {
+        let program =
+            optimized("fn main() {\n    num a = 7 % 0;\n    num b = 1 / 0;\n    print(a + b);\n}");
+        let body = main_body(&program);
+        assert_eq!(declared(&body[0]), None);
+        assert_eq!(declared(&body[1]), None);
+    }
+
+    #[test]
+    fn test_removes_dead_branches_and_functions() {
+        let program = optimized(
+            "fn debug() {\n    print('d');\n}\nfn other() {\n    print('o');\n}\n\
+             fn main() {\n    if 1 > 2 && true {\n        debug();\n    } else {\n\
+             other();\n    }\n\
+             while false {\n        debug();\n    }\n    if false {\n        debug();\n    }\n}",
+        );
+        let mut names = program
+            .functions()
+            .values()
+            .filter(|f| !f.inline)
+            .map(|f| f.name().clone())
+            .collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names, vec!["main".to_string(), "other".to_string()]);
+        match main_body(&program).as_slice() {
+            [Statement::Scope(ref branch)] => match branch.as_slice() {
+                [Statement::ExprStatement(_)] => (),
+                other => panic!("expected the call of other, got {:?}", other),
+            },
+            other => panic!("expected the else branch only, got {:?}", other),
+        }
+    }
+}
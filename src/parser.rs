@@ -1,51 +1,21 @@
 //! Contains the code for the parser,
 //! currently only contains enough to parse expressions and return parse errors.
+use diagnostic::Diagnostic;
 use expression::{Expr, Operator};
+use module::{Import, Module};
 use statement::{
     Assignment, ClassDecl, Declaration, Element, FunctionDecl, FunctionSig, IfStatement, Program,
     Statement, TraitDecl, TypeParam, WhileStatement,
 };
 use std::collections::HashMap;
-use std::fmt;
 use token::{Token, TokenType};
 use types::{LisaaType, TypedVar};
 
-#[derive(Debug)]
-/// The struct for a parse error, contains just enough information to show
-/// the user what happend and where.
-pub struct ParseError {
-    token: Token,
-    message: String,
-}
-
-impl ParseError {
-    /// Creates a new parse error.
-    pub fn new(token: Token, message: String) -> Self {
-        ParseError {
-            token: token,
-            message: message,
-        }
-    }
-}
-
-impl fmt::Display for ParseError {
-    /// Formats the error to a user readable format.
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Error {} : at {:?}:{} line {}",
-            self.message,
-            self.token.get_type(),
-            self.token.get_lexeme(),
-            self.token.get_line()
-        )
-    }
-}
-
-/// The parser, contains the tokens and a cursor.
+/// The parser, contains the tokens, a cursor and the errors found so far.
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<Diagnostic>,
 }
 
 impl Parser {
@@ -54,6 +24,7 @@ impl Parser {
         Parser {
             tokens: tokens,
             current: 0,
+            errors: vec![],
         }
     }
     /// parses the given list of token
@@ -61,68 +32,183 @@ impl Parser {
     /// If no error occurs, we return the list of the parsed expression.
     ///
     /// If an error occurs, we continue to parse, looking for other errors and return all of them
-    /// When an error happens in an expression, we leave the expression to avoid cascading errors.
+    /// When an error happens in a statement, we skip the rest of the statement to avoid cascading errors.
+    /// When it happens elsewhere, we skip everything until the next function, method, class, trait or import.
     ///
     /// But we start again with the new expressions.
-    pub fn program(&mut self) -> Result<(Program, Vec<String>), Vec<ParseError>> {
-        let mut fails = vec![];
+    ///
+    /// Returns the declarations with the module of the file, its imports and its public names.
+    pub fn program(&mut self) -> Result<(Program, Module), Vec<Diagnostic>> {
         let mut functions = HashMap::new();
         let mut classes = HashMap::new();
-        let mut imports = Vec::new();
         let mut traits = HashMap::new();
-        while !self.is_at_end() {
-            match self.element() {
-                Ok(Element::Function(e)) => {
+        let file = self.tokens.last().map(|t| t.span().file.clone()).unwrap_or_default();
+        let mut module = Module::new(file);
+        for (public, element) in self.elements()? {
+            match element {
+                Element::Function(e) => {
+                    module.declare_function(e.signature(), public);
                     let name = e.signature().clone();
                     functions.insert(name, e);
                 }
-                Ok(Element::Class(c)) => {
+                Element::Class(c) => {
                     let name = c.name().to_string();
+                    module.declare(name.clone(), public);
                     classes.insert(name, c);
                 }
-                Ok(Element::Import(s)) => {
-                    imports.push(s);
+                Element::Import(i) => {
+                    module.import(i);
                 }
-                Ok(Element::Trait(t)) => {
+                Element::Trait(t) => {
                     let name = t.name().to_string();
+                    module.declare(name.clone(), public);
                     traits.insert(name, t);
                 }
-                Err(e) => fails.push(ParseError::new(self.previous(), e)),
-            }
-            if fails.len() != 0 {
-                return Err(fails);
             }
         }
+        Ok((Program::new(functions, classes, traits), module))
+    }
 
-        if fails.is_empty() {
-            Ok((Program::new(functions, classes, traits), imports))
+    /// Parses the elements in the order of the file, each one with true if it is public.
+    /// The errors are reported like `program` does.
+    pub fn elements(&mut self) -> Result<Vec<(bool, Element)>, Vec<Diagnostic>> {
+        let mut elements = vec![];
+        while !self.is_at_end() {
+            let start = self.current;
+            let public = self.match_nexts(&[TokenType::PUB]);
+            match self.element(public) {
+                Ok(element) => elements.push((public, element)),
+                Err(e) => {
+                    self.report(e);
+                    // makes sure we always go forward, even if the element failed on its first token.
+                    if self.current == start {
+                        self.advance();
+                    }
+                    self.synchronize();
+                }
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(elements)
         } else {
-            Err(fails)
+            Err(self.errors.drain(..).collect())
+        }
+    }
+
+    /// Records an error at the last consumed token.
+    fn report(&mut self, error: String) {
+        let span = self.previous().span().clone();
+        self.errors.push(Diagnostic::at(error, span));
+    }
+
+    /// Skips the tokens until the start of the next element (function, method, class, trait or import, maybe pub).
+    fn synchronize(&mut self) {
+        while !self.is_at_end() && !self.at_element_start() {
+            self.advance();
         }
     }
 
-    /// A new function declaration.
-    pub fn element(&mut self) -> Result<Element, String> {
+    /// Skips the tokens until the end of the statement that started at the given token.
+    ///
+    /// The statement ends after a semicolon or a block, or before the closing brace of the enclosing scope.
+    /// Nothing is skipped if the failing statement already consumed its semicolon or its closing brace.
+    fn synchronize_statement(&mut self, start: usize) {
+        if self.current > start
+            && (self.previous().is_type(&TokenType::SEMICOLON)
+                || self.previous().is_type(&TokenType::RightCurlyBrace))
+        {
+            return;
+        }
+        let mut depth = 0;
+        while !self.is_at_end() && !self.at_element_start() {
+            match self.peek().get_type() {
+                &TokenType::SEMICOLON if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                &TokenType::LeftCurlyBrace => depth += 1,
+                &TokenType::RightCurlyBrace if depth == 0 => return,
+                &TokenType::RightCurlyBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => (),
+            }
+            self.advance();
+        }
+    }
+
+    /// Checks if the next token starts a new element.
+    /// fn followed by a parenthesis is a function type or a closure, not a function declaration.
+    fn at_element_start(&self) -> bool {
+        match self.peek().get_type() {
+            &TokenType::FUN => self.peek_twice().get_type() != &TokenType::LeftParen,
+            &TokenType::METHOD
+            | &TokenType::CLASS
+            | &TokenType::TRAIT
+            | &TokenType::IMPORT
+            | &TokenType::PUB => true,
+            _ => false,
+        }
+    }
+
+    /// A new function declaration, public if it followed the pub keyword.
+    pub fn element(&mut self, public: bool) -> Result<Element, String> {
         match self.peek().get_type() {
             &TokenType::FUN => Ok(Element::Function(self.parse_function_decl()?)),
             &TokenType::CLASS => Ok(Element::Class(self.parse_class_decl()?)),
+            &TokenType::METHOD if public => {
+                Err("a method can not be pub, it is visible wherever its type is".to_string())
+            }
             &TokenType::METHOD => Ok(Element::Function(self.parse_method_decl()?)),
-            &TokenType::IMPORT => Ok(Element::Import(self.parse_import()?)),
+            &TokenType::IMPORT => Ok(Element::Import(self.parse_import(public)?)),
             &TokenType::TRAIT => Ok(Element::Trait(self.parse_trait()?)),
             _ => Err("error : expected function or class declaration there".to_string()),
         }
     }
 
-    pub fn parse_import(&mut self) -> Result<String, String> {
+    /// Parses an import of a whole module or of some of its names :
+    /// import tests/scripts/imp/dot
+    /// import string::{String, println}
+    pub fn parse_import(&mut self, public: bool) -> Result<Import, String> {
         // skip the import keyword
         self.advance();
+        let mut span = self.peek().span().clone();
         let mut to_import = self.advance().get_lexeme().to_owned();
         while self.peek().is_type(&TokenType::SLASH) {
             self.advance();
             to_import.push('/');
             to_import.push_str(self.advance().get_lexeme())
         }
-        return Ok(to_import.clone());
+        span.len = to_import.chars().count();
+        let names = match self.match_nexts(&[TokenType::DOUBLECOLON]) {
+            true => {
+                self.expect(TokenType::LeftCurlyBrace)?;
+                let mut names = vec![self.expect_ident("{")?];
+                while self.match_nexts(&[TokenType::COMMA]) {
+                    names.push(self.expect_ident(",")?);
+                }
+                self.expect(TokenType::RightCurlyBrace)?;
+                Some(names)
+            }
+            false => None,
+        };
+        return Ok(Import::new(to_import, names, public, span));
+    }
+
+    /// Parses the rest of a name qualified by its module, the first identifier is already consumed :
+    /// string::String
+    pub fn qualified_name(&mut self, first: &str) -> String {
+        if self.peek().is_type(&TokenType::DOUBLECOLON)
+            && self.peek_twice().is_type(&TokenType::IDENTIFIER)
+        {
+            self.advance();
+            return format!("{}::{}", first, self.advance().get_lexeme());
+        }
+        first.to_string()
     }
 
     /// Parses a trait declaration of the following form :
@@ -130,11 +216,14 @@ impl Parser {
     pub fn parse_trait(&mut self) -> Result<TraitDecl, String> {
         // skip the trait keyword
         self.advance();
+        let span = self.peek().span().clone();
         let name = self.expect_ident(" trait ")?;
         self.expect(TokenType::EQUAL)?;
         let (traits, funcs) = self.parse_trait_expr()?;
         self.expect(TokenType::SEMICOLON)?;
-        Ok(TraitDecl::new(name, traits, funcs))
+        let mut decl = TraitDecl::new(name, traits, funcs);
+        decl.set_span(span);
+        Ok(decl)
     }
 
     /// Parses a trait's signature, eg :
@@ -146,9 +235,10 @@ impl Parser {
         let mut traits = vec![];
         {
             let mut get_next = |clo_self: &mut Self| match clo_self.peek().get_type().clone() {
-                TokenType::IDENTIFIER => {
-                    Ok(traits.push(clo_self.advance().get_lexeme().to_string()))
-                }
+                TokenType::IDENTIFIER => Ok({
+                    let first = clo_self.advance().get_lexeme().to_string();
+                    traits.push(clo_self.qualified_name(&first))
+                }),
                 TokenType::METHOD => Ok({
                     let method = clo_self.parse_signature()?;
                     methods.insert(method.name().clone(), method);
@@ -219,6 +309,7 @@ impl Parser {
     pub fn parse_method_decl(&mut self) -> Result<FunctionDecl, String> {
         self.advance();
         // skip the func keyword
+        let span = self.peek().span().clone();
         let name = self.expect_ident("method")?;
         let type_parameters = self.parse_type_list()?;
         let arguments = self.func_args()?;
@@ -234,21 +325,44 @@ impl Parser {
             scope,
             return_type,
         );
-        res.set_self(LisaaType::Class(class_name, type_params));
+        res.set_self(match class_name.as_ref() {
+            "num" => LisaaType::Num,
+            "char" => LisaaType::Char,
+            "bool" => LisaaType::Bool,
+            _ => LisaaType::Class(class_name, type_params),
+        });
+        res.set_span(span);
         Ok(res)
     }
 
     pub fn parse_class_decl(&mut self) -> Result<ClassDecl, String> {
         self.advance();
         // skip the func keyword
+        let span = self.peek().span().clone();
         let name = self.expect_ident("class")?;
         let type_parameters = self.parse_type_list()?;
         self.expect(TokenType::LeftCurlyBrace)?;
         let mut attrs = vec![];
+        // an attribute that fails is reported and skipped like a statement.
         while self.peek().get_type() != &TokenType::RightCurlyBrace {
-            let tp = self.parse_type()?;
-            let decl = self.parse_declaration(tp)?.into_decl();
-            attrs.push(decl);
+            if self.is_at_end() || self.at_element_start() {
+                return Err("Expected closing brace at the end of class".to_string());
+            }
+            let start = self.current;
+            match self
+                .parse_type()
+                .and_then(|tp| self.parse_declaration(tp))
+            {
+                Ok(decl) => attrs.push(decl.into_decl()),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize_statement(start);
+                    // the class already has an error, its missing brace is not reported too.
+                    if self.is_at_end() || self.at_element_start() {
+                        return Ok(ClassDecl::new(name, attrs, type_parameters));
+                    }
+                }
+            }
         }
         self.advance();
         match attrs.len() > 62 {
@@ -257,24 +371,25 @@ impl Parser {
                 name,
                 attrs.len()
             )),
-            _ => Ok(ClassDecl::new(name, attrs, type_parameters)),
+            _ => {
+                let mut decl = ClassDecl::new(name, attrs, type_parameters);
+                decl.set_span(span);
+                Ok(decl)
+            }
         }
     }
 
     pub fn parse_function_decl(&mut self) -> Result<FunctionDecl, String> {
         self.advance(); // skip the func keyword
+        let span = self.peek().span().clone();
         let name = self.expect_ident("function")?;
         let type_parameters = self.parse_type_list()?;
         let arguments = self.func_args()?;
         let return_type = self.func_return_type()?;
         let scope = self.scope()?;
-        Ok(FunctionDecl::new(
-            name,
-            type_parameters,
-            arguments,
-            scope,
-            return_type,
-        ))
+        let mut decl = FunctionDecl::new(name, type_parameters, arguments, scope, return_type);
+        decl.set_span(span);
+        Ok(decl)
     }
 
     pub fn expect_ident(&mut self, after: &'static str) -> Result<String, String> {
@@ -305,7 +420,8 @@ impl Parser {
         while self.peek().get_type() == &TokenType::IDENTIFIER {
             let type_name = self.advance().get_lexeme().to_string();
             self.expect(TokenType::COLON)?;
-            let trait_name = self.advance().get_lexeme().to_string();
+            let first = self.advance().get_lexeme().to_string();
+            let trait_name = self.qualified_name(&first);
             self.advance(); // take the > or a comma.
             args.push(TypeParam::new(type_name, trait_name));
         }
@@ -330,7 +446,7 @@ impl Parser {
         loop {
             match self.peek().get_type() {
                 &TokenType::RightParen => break,
-                &TokenType::IDENTIFIER => {
+                &TokenType::IDENTIFIER | &TokenType::FUN => {
                     args.push(self.typed_identifier()?);
                     match self.peek().get_type() {
                         &TokenType::RightParen => break,
@@ -361,7 +477,8 @@ impl Parser {
                 Ok(LisaaType::Class(String::from("Self"), vec![]))
             }
             TokenType::IDENTIFIER => {
-                let ident = self.advance().get_lexeme().to_string();
+                let first = self.advance().get_lexeme().to_string();
+                let ident = self.qualified_name(&first);
                 match ident.as_ref() {
                     "slice" => {
                         self.expect(TokenType::LESS)?;
@@ -371,12 +488,26 @@ impl Parser {
                     }
                     "num" => Ok(LisaaType::Num),
                     "char" => Ok(LisaaType::Char),
+                    "bool" => Ok(LisaaType::Bool),
                     i => Ok({
                         let type_params = self.parse_known_type_list()?;
                         LisaaType::Class(i.to_string(), type_params)
                     }),
                 }
             }
+            TokenType::FUN => {
+                self.advance();
+                self.expect(TokenType::LeftParen)?;
+                let mut args = vec![];
+                while !self.match_nexts(&[TokenType::RightParen]) {
+                    if !args.is_empty() {
+                        self.expect(TokenType::COMMA)?;
+                    }
+                    args.push(self.parse_type()?);
+                }
+                let ret_type = self.func_return_type()?;
+                Ok(LisaaType::function_pointer(args, ret_type))
+            }
             other => Err(format!("Expected Type or \"Self\" here, got : {:?}", other)),
         }
     }
@@ -425,17 +556,24 @@ impl Parser {
         }
     }
 
-    /// Parses all the statements in a scope.
+    /// Parses an if statement with its optional else statement.
     ///
-    /// scopes have implicit semicolons, it will be added if it does not exists.
+    /// An else if is parsed as an else whose statement is the next if.
     pub fn parse_if(&mut self) -> Result<Statement, String> {
         self.advance();
         let condition = self.expression()?;
         let next_statement = self.statement()?;
-        // add implicit semicolon.
+        let else_statement = match self.peek().is_type(&TokenType::ELSE) {
+            true => {
+                self.advance();
+                Some(self.statement()?)
+            }
+            false => None,
+        };
         Ok(Statement::IfStatement(IfStatement::new(
             condition,
             next_statement,
+            else_statement,
         )))
     }
 
@@ -477,14 +615,23 @@ impl Parser {
     /// Parses all the statements in a scope.
     ///
     /// scopes have implicit semicolons, it will be added if it does not exists.
+    ///
+    /// A statement that fails to parse is reported and skipped, so the following ones are still checked.
     pub fn parse_scope(&mut self) -> Result<Statement, String> {
         self.advance();
         let mut statements = vec![];
         while !self.peek().is_type(&TokenType::RightCurlyBrace) {
-            statements.push(self.statement()?);
-            if self.is_at_end() {
+            if self.is_at_end() || self.at_element_start() {
                 return Err("Expected closing brace at the end of scope".to_string());
             }
+            let start = self.current;
+            match self.statement() {
+                Ok(statement) => statements.push(statement),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize_statement(start);
+                }
+            }
         }
         self.advance();
         Ok(Statement::Scope(statements))
@@ -495,12 +642,18 @@ impl Parser {
     /// If it is a < we have a type.
     /// If it is an identifier we also have a type.
     /// In the two cases we parse the type followed by the variable's name then an assignment.
+    /// A statement starting with fn is the declaration of a function value.
     /// Else we go to the assignment part.
     pub fn declaration(&mut self) -> Result<Statement, String> {
         let decl = match self.peek().get_type() {
+            &TokenType::FUN => {
+                let tp = self.parse_type()?;
+                return self.parse_declaration(tp);
+            }
             &TokenType::IDENTIFIER => {
                 if self.peek_twice().get_type() == &TokenType::LESS
                     || self.peek_twice().get_type() == &TokenType::IDENTIFIER
+                    || self.at_qualified_type()
                 {
                     let tp = self.parse_type()?;
                     return self.parse_declaration(tp);
@@ -512,6 +665,14 @@ impl Parser {
         };
         self.expect_semicolon(decl?)
     }
+    /// Checks if the next tokens are a qualified type followed by a name or type arguments :
+    /// string::String s or geometry::Box<num> b
+    fn at_qualified_type(&self) -> bool {
+        let nth = |n: usize| self.tokens[(self.current + n).min(self.tokens.len() - 1)].get_type();
+        nth(1) == &TokenType::DOUBLECOLON
+            && nth(2) == &TokenType::IDENTIFIER
+            && (nth(3) == &TokenType::IDENTIFIER || nth(3) == &TokenType::LESS)
+    }
     /// Parses a declaration
     /// When this function is called we know that we have a type and the next val is an identifier.
     pub fn parse_declaration(&mut self, val_type: LisaaType) -> Result<Statement, String> {
@@ -571,7 +732,7 @@ impl Parser {
                 expr,
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             );
             expr = new_expr;
         }
@@ -587,7 +748,7 @@ impl Parser {
                 expr,
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             );
             expr = new_expr;
         }
@@ -608,7 +769,7 @@ impl Parser {
                 expr,
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             );
             expr = new_expr;
         }
@@ -624,7 +785,7 @@ impl Parser {
                 expr,
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             );
             expr = new_expr;
         }
@@ -641,7 +802,7 @@ impl Parser {
                 expr,
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             );
             expr = new_expr;
         }
@@ -656,7 +817,7 @@ impl Parser {
             return Ok(Expr::unary(
                 Operator::from_token(&previous)?,
                 right,
-                previous.get_line(),
+                previous.span().clone(),
             ));
         }
         let lit = self.literal()?;
@@ -683,6 +844,14 @@ impl Parser {
     pub fn parse_constructor(&mut self, lit: Expr) -> Result<Expr, String> {
         self.expect(TokenType::DOUBLECOLON)?;
         let mut tp = LisaaType::Class(lit.get_identifier()?.clone(), self.parse_known_type_list()?);
+        if !self.check(&TokenType::LeftParen) {
+            // not called : a reference to an instance of a generic function.
+            return Ok(Expr::function_ref(
+                lit.get_identifier()?.clone(),
+                tp.type_args(),
+                lit.span().clone(),
+            ));
+        }
         self.expect(TokenType::LeftParen)?;
         let args = if self.peek().get_type() == &TokenType::RightParen {
             vec![]
@@ -692,7 +861,7 @@ impl Parser {
         self.expect(TokenType::RightParen)?;
         return Ok(Expr::constructor_call(
             tp.get_constructor_call(args),
-            lit.get_line(),
+            lit.span().clone(),
         ));
     }
 
@@ -723,17 +892,17 @@ impl Parser {
 
     /// Returns a call with the given arguments, the call must be a method or a fucntion.
     pub fn callable(&mut self, args: Vec<Expr>, lit: Expr) -> Result<Expr, String> {
-        let line = lit.get_line();
+        let span = lit.span().clone();
         if let Ok(_) = lit.get_identifier() {
             return Ok(Expr::function_call(
                 lit.get_identifier()
                     .map_err(|_| "function calls only allowed on identifier")?
                     .to_string(),
                 args,
-                line,
+                span,
             ));
         }
-        Ok(Expr::method_call(lit, args, line))
+        Ok(Expr::method_call(lit, args, span))
     }
 
     pub fn parse_indexing(&mut self, lit: Expr) -> Result<Expr, String> {
@@ -744,15 +913,15 @@ impl Parser {
             lit,
             Operator::INDEX,
             index,
-            self.previous().get_line(),
+            self.previous().span().clone(),
         )))
     }
 
     pub fn parse_getattr(&mut self, lit: Expr) -> Result<Expr, String> {
         self.expect(TokenType::DOT)?;
         let next = self.advance();
-        let name = Expr::identifier(next.get_lexeme().to_owned(), next.get_line());
-        Ok(Expr::deref(Expr::getattr(lit, name, next.get_line())))
+        let name = Expr::identifier(next.get_lexeme().to_owned(), next.span().clone());
+        Ok(Expr::deref(Expr::getattr(lit, name, next.span().clone())))
     }
 
     pub fn expect(&mut self, token_type: TokenType) -> Result<(), String> {
@@ -778,27 +947,49 @@ impl Parser {
             match token.get_type() {
                 &TokenType::NUMBER => Ok(Expr::number(
                     token.get_lexeme().parse::<f64>().unwrap(),
-                    token.get_line(),
+                    token.span().clone(),
                 )),
                 &TokenType::STRING => Ok(Expr::string(
                     token.get_lexeme().to_string(),
-                    token.get_line(),
+                    token.span().clone(),
                 )),
                 &TokenType::NIL => Err("nil no longer supported".to_string()),
-                &TokenType::FALSE => Ok(Expr::number(0.0, token.get_line())),
-                &TokenType::TRUE => Ok(Expr::number(1.0, token.get_line())),
+                &TokenType::FALSE => Ok(Expr::boolean(false, token.span().clone())),
+                &TokenType::TRUE => Ok(Expr::boolean(true, token.span().clone())),
                 &TokenType::IDENTIFIER => Ok(Expr::identifier(
-                    token.get_lexeme().to_string(),
-                    token.get_line(),
+                    self.qualified_name(token.get_lexeme()),
+                    token.span().clone(),
                 )),
                 &TokenType::CHAR => Ok(Expr::char(
                     token.get_lexeme().chars().next().unwrap(),
-                    token.get_line(),
+                    token.span().clone(),
                 )),
+                &TokenType::FUN => self.closure(token),
                 _ => Err("Cant parse literal".to_string()),
             }
         }
     }
+    /// Parses an anonymous function, the fn keyword is already consumed :
+    /// fn(num a, num b) -> num { return a + b; }
+    pub fn closure(&mut self, keyword: Token) -> Result<Expr, String> {
+        let arguments = self.func_args()?;
+        let return_type = self.func_return_type()?;
+        if !self.check(&TokenType::LeftCurlyBrace) {
+            return Err("Expected the body of the closure".to_string());
+        }
+        let scope = self.parse_scope()?;
+        Ok(Expr::closure(
+            FunctionDecl::new(
+                "closure".to_string(),
+                vec![],
+                arguments,
+                scope,
+                return_type,
+            ),
+            keyword.span().clone(),
+        ))
+    }
+
     /// Given a list of token types, matches one of them if possible and consume it
     /// If no matches were found, do nothing.
     pub fn match_nexts(&mut self, tokens: &[TokenType]) -> bool {
@@ -817,13 +1008,14 @@ impl Parser {
     }
 
     /// Peeks for the next token, without conduming it.
+    /// Never goes past the end of file token.
     pub fn peek_twice(&self) -> &Token {
-        &self.tokens[self.current + 1]
+        &self.tokens[(self.current + 1).min(self.tokens.len() - 1)]
     }
 
     /// Peeks for the next token, without conduming it.
     pub fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+        &self.tokens[self.current.min(self.tokens.len() - 1)]
     }
 
     /// Advance and consume the next token, returning it
@@ -845,6 +1037,81 @@ impl Parser {
 
     /// Checks if we are at the end of the file.
     pub fn is_at_end(&self) -> bool {
-        self.current >= self.tokens.len()
+        self.current >= self.tokens.len() || self.tokens[self.current].is_type(&TokenType::EOF)
+    }
+}
+
+#[cfg(test)]
+mod tests_parser {
+    use super::*;
+    use scanner::Scanner;
+
+    fn parse(source: &str) -> Result<(Program, Module), Vec<Diagnostic>> {
+        let tokens = Scanner::new(source.to_string(), "test.lisaa".to_string())
+            .tokens()
+            .unwrap();
+        Parser::new(tokens).program()
+    }
+
+    fn error_lines(source: &str) -> Vec<usize> {
+        parse(source)
+            .err()
+            .unwrap()
+            .iter()
+            .map(|e| e.span().as_ref().unwrap().line)
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_every_statement_error() {
+        let source = "fn main(){\n    num a = 3 +;\n    num b = 4;\n    print(b)\n    print(a);\n}";
+        assert_eq!(error_lines(source), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_resynchronizes_at_next_element() {
+        let source = "fn broken( {\n    return 1;\n}\nfn main(){\n    num x = ;\n}\nClass A {\n    num b\n}";
+        assert_eq!(error_lines(source), vec![1, 5, 9]);
+    }
+
+    #[test]
+    fn test_keeps_independent_errors() {
+        let consumed_end = "fn main(){\n    num a = ;\n    num b = 3\n    print(b);\n}";
+        assert_eq!(error_lines(consumed_end), vec![2, 3]);
+        let in_class = "Class A {\n    num x = 1\n    num y = 2;\n}\nfn f(){\n    num c = 1 +;\n}";
+        assert_eq!(error_lines(in_class), vec![2, 6]);
+        let last_attribute = "Class A {\n    num x = 1;\n    num y = 2\n}\nfn f(){\n    num c = 1 +;\n}";
+        assert_eq!(error_lines(last_attribute), vec![3, 6]);
+        let unclosed_class = "Class A {\n    num x = 1;\nfn f(){\n    num c = 1 +;\n}";
+        assert_eq!(error_lines(unclosed_class), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_unterminated_scope() {
+        assert_eq!(error_lines("fn main(){\n    num a = 1;\n"), vec![2]);
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let source = "fn main(){ if a b(); else if c { d(); } else e(); f(); }";
+        let (program, _) = parse(source).unwrap();
+        let main = program.functions().values().next().unwrap();
+        let body = match main.scope {
+            Statement::Scope(ref body) => body,
+            ref s => panic!("not a scope : {:?}", s),
+        };
+        assert_eq!(body.len(), 2);
+        let first = match body[0] {
+            Statement::IfStatement(ref i) => i,
+            ref s => panic!("not an if : {:?}", s),
+        };
+        let second = match first.else_statement() {
+            Some(&Statement::IfStatement(ref i)) => i,
+            s => panic!("not an else if : {:?}", s),
+        };
+        match (second.statement(), second.else_statement()) {
+            (&Statement::Scope(_), Some(&Statement::ExprStatement(_))) => (),
+            s => panic!("wrong branches : {:?}", s),
+        }
     }
 }
@@ -0,0 +1,250 @@
+//! The state of an interactive session, see Lisaa::repl.
+//!
+//! The declarations entered are merged into a growing program.
+//! The other inputs are lines : each one is checked and compiled as a function taking the variables
+//! declared by the previous lines, its own declarations stay on the stack of the vm for the next lines.
+use diagnostic::Span;
+use statement::{FunctionDecl, FunctionSig, Program, Statement};
+use std::collections::HashSet;
+use token::{Token, TokenType};
+use types::{LisaaType, TypedVar};
+
+/// The name of the function holding the current line, it can not clash with the user's ones.
+pub const LINE_FUNCTION: &str = "repl_line";
+
+/// What the user entered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// Functions, methods, classes, traits or imports, maybe pub.
+    Declarations(String),
+    /// Statements to run, ending by ; or }.
+    Statements(String),
+    /// An expression whose value is printed.
+    Expression(String),
+}
+
+impl Input {
+    /// Finds what kind of input the text is, by its first word and its last char.
+    pub fn new(text: &str) -> Self {
+        let text = text.trim().to_string();
+        let first_word = text
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or("");
+        let named_fn = first_word == "fn"
+            && text[2..]
+                .trim_start()
+                .chars()
+                .next()
+                .map_or(false, |c| c.is_alphabetic());
+        if named_fn || ["Class", "method", "trait", "import", "pub"].contains(&first_word) {
+            Input::Declarations(text)
+        } else if text.ends_with(';') || text.ends_with('}') {
+            Input::Statements(text)
+        } else {
+            Input::Expression(text)
+        }
+    }
+
+    /// Returns the text entered, without the spaces around it.
+    pub fn text(&self) -> &str {
+        match self {
+            &Input::Declarations(ref text) | &Input::Statements(ref text) | &Input::Expression(ref text) => text,
+        }
+    }
+}
+
+/// Checks if the text may go on, because a brace or a parenthesis is not closed yet.
+/// The braces in strings and chars are ignored.
+pub fn is_incomplete(text: &str) -> bool {
+    let (mut depth, mut quote) = (0, None);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '{') | (None, '(') => depth += 1,
+            (None, '}') | (None, ')') => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+/// The program and the variables built by the previous inputs.
+pub struct Session {
+    /// The declarations entered and imported so far, not monomorphised.
+    program: Program,
+    /// The files of the modules already imported.
+    imported: HashSet<String>,
+    /// The variables declared by the previous lines, in their order on the stack.
+    variables: Vec<TypedVar>,
+}
+
+impl Session {
+    /// Creates an empty session.
+    pub fn new() -> Self {
+        Session {
+            program: Program::empty(),
+            imported: HashSet::new(),
+            variables: vec![],
+        }
+    }
+
+    /// Returns the program built so far.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Returns the modules imported so far.
+    pub fn imported(&self) -> &HashSet<String> {
+        &self.imported
+    }
+
+    /// Replaces the program and the imported modules, once the new declarations typecheck.
+    pub fn set_program(&mut self, program: Program, imported: HashSet<String>) {
+        self.program = program;
+        self.imported = imported;
+    }
+
+    /// Wraps the tokens of a line, ending with EOF, in the function holding it, parsed before its arguments are added.
+    /// An expression is printed if print is true, which only typechecks if its type has toString.
+    /// The tokens added are placed at the start or at the end of the line, so the errors point to what was entered.
+    pub fn line_tokens(input: &Input, print: bool, mut tokens: Vec<Token>) -> Vec<Token> {
+        let end = tokens.pop().expect("the tokens end with EOF");
+        let start = match tokens.first() {
+            Some(first) => Span::new(first.span().file.clone(), first.span().line, first.span().column, 0),
+            None => end.span().clone(),
+        };
+        let at = |span: &Span, added: &[(TokenType, &str)]| {
+            added
+                .iter()
+                .map(|&(ref t, lexeme)| Token::new(span.clone(), t.clone(), lexeme.to_string()))
+                .collect::<Vec<Token>>()
+        };
+        let mut wrapped = at(
+            &start,
+            &[
+                (TokenType::FUN, "fn"),
+                (TokenType::IDENTIFIER, "repl"),
+                (TokenType::LeftParen, "("),
+                (TokenType::RightParen, ")"),
+                (TokenType::LeftCurlyBrace, "{"),
+            ],
+        );
+        match (input, print) {
+            (&Input::Expression(_), true) => {
+                wrapped.extend(at(&start, &[(TokenType::LeftParen, "(")]));
+                wrapped.extend(tokens);
+                wrapped.extend(at(
+                    end.span(),
+                    &[
+                        (TokenType::RightParen, ")"),
+                        (TokenType::DOT, "."),
+                        (TokenType::IDENTIFIER, "toString"),
+                        (TokenType::LeftParen, "("),
+                        (TokenType::RightParen, ")"),
+                        (TokenType::DOT, "."),
+                        (TokenType::IDENTIFIER, "println"),
+                        (TokenType::LeftParen, "("),
+                        (TokenType::RightParen, ")"),
+                        (TokenType::SEMICOLON, ";"),
+                    ],
+                ));
+            }
+            (&Input::Expression(_), false) => {
+                wrapped.extend(tokens);
+                wrapped.extend(at(end.span(), &[(TokenType::SEMICOLON, ";")]));
+            }
+            (&Input::Statements(_), _) | (&Input::Declarations(_), _) => wrapped.extend(tokens),
+        }
+        wrapped.extend(at(end.span(), &[(TokenType::RightCurlyBrace, "}")]));
+        wrapped.push(end);
+        wrapped
+    }
+
+    /// Turns the parsed function of a line into the function taking the variables of the previous lines.
+    /// Returns an error if it returns, as it would leave the frame of the session.
+    pub fn line_function(&self, parsed: FunctionDecl) -> Result<FunctionDecl, String> {
+        if parsed.scope().iter().any(returns) {
+            return Err("return is not allowed outside of a function".to_string());
+        }
+        Ok(FunctionDecl::new(
+            LINE_FUNCTION.to_string(),
+            vec![],
+            self.variables.clone(),
+            parsed.scope,
+            LisaaType::Void,
+        ))
+    }
+
+    /// The signature of the function holding the current line.
+    pub fn line_signature(&self) -> FunctionSig {
+        FunctionSig::new(
+            vec![],
+            self.variables.clone(),
+            LisaaType::Void,
+            LINE_FUNCTION.to_string(),
+            None,
+        )
+    }
+
+    /// Keeps the variables declared by a line that ran, they are now on the stack.
+    pub fn declare(&mut self, line: &FunctionDecl) {
+        for statement in line.scope() {
+            if let &Statement::Declaration(ref d) = statement {
+                self.variables
+                    .push(TypedVar::new(d.val_type().clone(), d.name().clone()));
+            }
+        }
+    }
+}
+
+/// Checks if the statement contains a return, outside of the closures.
+fn returns(statement: &Statement) -> bool {
+    match statement {
+        &Statement::ReturnStatement(_) => true,
+        &Statement::Scope(ref statements) => statements.iter().any(returns),
+        &Statement::IfStatement(ref i) => {
+            returns(i.statement()) || i.else_statement().map_or(false, returns)
+        }
+        &Statement::WhileStatement(ref w) => returns(w.statement()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests_repl {
+    use super::*;
+
+    #[test]
+    fn test_inputs() {
+        assert_eq!(
+            Input::new("fn double(num a) -> num { return a * 2; }"),
+            Input::Declarations("fn double(num a) -> num { return a * 2; }".to_string())
+        );
+        assert_eq!(
+            Input::new("pub Class Point { num x = 0; }"),
+            Input::Declarations("pub Class Point { num x = 0; }".to_string())
+        );
+        assert_eq!(
+            Input::new(" import string\n"),
+            Input::Declarations("import string".to_string())
+        );
+        assert_eq!(
+            Input::new("fn(num) -> num f = double;"),
+            Input::Statements("fn(num) -> num f = double;".to_string())
+        );
+        assert_eq!(
+            Input::new("double(21)"),
+            Input::Expression("double(21)".to_string())
+        );
+        assert!(is_incomplete("fn double(num a) -> num {"));
+        assert!(!is_incomplete("\"{\".println();"));
+        assert!(!is_incomplete("'}'"));
+    }
+}
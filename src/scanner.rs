@@ -1,45 +1,73 @@
 //! Scanner here
 //!
+use diagnostic::{Diagnostic, Span};
 use keywords::KEYWORDS;
 use token::{Token, TokenType};
 /// The scanner, will scan the tokens as expected.
-/// Does not report the position of the tokens in the lines but will soon.
+/// Reports the file, line and column of the tokens.
 pub struct Scanner {
     tokens: Vec<Token>,
+    /// The comments, in their order.
+    comments: Vec<Token>,
     source: Vec<char>,
+    file: String,
     start: usize,
     current: usize,
     line: usize,
+    /// The position of the first char of the current line.
+    line_start: usize,
+    /// The line where the current token started.
+    start_line: usize,
+    /// The column where the current token started.
+    start_column: usize,
 }
 impl Scanner {
-    /// Creates a new scanner for the given source code.
-    pub fn new(source: String) -> Self {
+    /// Creates a new scanner for the given source code, coming from the given file.
+    pub fn new(source: String, file: String) -> Self {
         Scanner {
             tokens: vec![],
+            comments: vec![],
             source: source.chars().collect(),
+            file: file,
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
     /// Returns the list of tokens contained in the code.
-    pub fn tokens(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokens(&mut self) -> Result<Vec<Token>, Diagnostic> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
             let token = self.scan_token()?;
-            if !token.ignore() {
+            if token.is_type(&TokenType::COMMENT) {
+                self.comments.push(token);
+            } else if !token.ignore() {
                 self.tokens.push(token);
             }
         }
-        let _end = self.token(TokenType::EOF, "");
-        //self.tokens.push(end);
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
+        let end = self.token(TokenType::EOF, "");
+        self.tokens.push(end);
         Ok(self.tokens.clone())
     }
 
-    /// Creates an error with the given message at the current line.
-    pub fn error(&self, message: String) -> Result<Token, String> {
-        Err(format!("[line : {}] Error : {}", self.line, message))
+    /// Returns the comments found by `tokens`, they are not part of the tokens.
+    /// The lexeme of a comment is its text, starting with the slashes.
+    pub fn comments(&self) -> &Vec<Token> {
+        &self.comments
+    }
+
+    /// Creates an error with the given message at the current token.
+    pub fn error(&self, message: String) -> Result<Token, Diagnostic> {
+        Err(Diagnostic::at(message, self.span()))
     }
 
     /// Checks if we are at the end of the file.
@@ -48,7 +76,7 @@ impl Scanner {
     }
 
     /// Scan the tokens, basically match everything known and returns an error if we can't
-    fn scan_token(&mut self) -> Result<Token, String> {
+    fn scan_token(&mut self) -> Result<Token, Diagnostic> {
         return match self.advance() {
             '[' => Ok(self.token(TokenType::LeftBrace, "")),
             ']' => Ok(self.token(TokenType::RightBrace, "")),
@@ -100,13 +128,15 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
-                    Ok(self.token(TokenType::IGNORE, ""))
+                    let text: String = self.source[self.start..self.current].iter().collect();
+                    Ok(self.token(TokenType::COMMENT, text.trim_end()))
                 } else {
                     Ok(self.token(TokenType::SLASH, ""))
                 }
             }
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
                 Ok(self.token(TokenType::IGNORE, ""))
             }
             ' ' => Ok(self.token(TokenType::IGNORE, "")),
@@ -117,13 +147,13 @@ impl Scanner {
             '0'...'9' => self.number(),
             'a'...'z' => self.identifier(),
             'A'...'Z' => self.identifier(),
-            c => Err(format!("unexpected token : {}", c)),
+            c => self.error(format!("unexpected token : {}", c)),
         };
     }
 
     /// Parses an identifier at the current position
     /// If it is a known keyword, register it as a keyword.
-    fn identifier(&mut self) -> Result<Token, String> {
+    fn identifier(&mut self) -> Result<Token, Diagnostic> {
         while self.peek().is_alphanumeric() && !self.is_at_end() {
             self.advance();
         }
@@ -134,7 +164,7 @@ impl Scanner {
         }
     }
 
-    fn scan_char(&mut self) -> Result<Token, String> {
+    fn scan_char(&mut self) -> Result<Token, Diagnostic> {
         if self.is_at_end() {
             return self.error("Unterminated char declaration".to_string());
         }
@@ -148,18 +178,19 @@ impl Scanner {
         }
         match self.match_next('\'') {
             false => self.error("char must be only 1 character long".to_string()),
-            true => Ok(Token::char(self.line, fin)),
+            true => Ok(Token::char(self.span(), fin)),
         }
     }
 
     /// Parses a string literal at the given position
     /// Checks for unterminated string.
     /// It allows multiline strings.
-    fn string(&mut self) -> Result<Token, String> {
+    fn string(&mut self) -> Result<Token, Diagnostic> {
         let mut chars = vec![];
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             chars.push(match self.advance() {
                 '\\' => match self.advance() {
@@ -182,7 +213,7 @@ impl Scanner {
     }
 
     /// Parses a number at the given position. (float or int)
-    fn number(&mut self) -> Result<Token, String> {
+    fn number(&mut self) -> Result<Token, Diagnostic> {
         while self.peek().is_numeric() && !self.is_at_end() {
             self.advance();
         }
@@ -233,8 +264,18 @@ impl Scanner {
         self.source[self.current - 1]
     }
 
+    /// Returns the position of the current token.
+    /// A token spanning over multiple lines is only underlined on its first line.
+    fn span(&self) -> Span {
+        let len = match self.start_line == self.line {
+            true => self.current - self.start,
+            false => 1,
+        };
+        Span::new(self.file.clone(), self.start_line, self.start_column, len)
+    }
+
     /// Creates a new token at the given line and position.
     fn token(&mut self, token: TokenType, lexeme: &str) -> Token {
-        Token::new(self.line, token, lexeme.to_string())
+        Token::new(self.span(), token, lexeme.to_string())
     }
 }
@@ -1,13 +1,33 @@
-//! Used to run a script and get its output in a buffer.
+//! Used to run a script and capture what it prints, for the tests.
+//!
+//! The golden tests are the scripts test_NAME.lisaa of a directory, with their expectation files :
+//! expect_NAME contains what the script prints and, if the script must fail,
+//! expect_NAME.stderr contains the errors shown, compilation or runtime ones.
+//! The positions of the failing instructions are left out of the runtime errors,
+//! they change whenever the compiled code does.
+//! Blessing the tests rewrites the expectation files with what the scripts do now.
 use lisaa::Lisaa;
-use std::io::Cursor;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-/// Maybe just run all the scripts in the scripts dir starting by test
-/// This allows to run a script and create
+/// Runs a script like the interpreter does, but keeps its output in memory.
 /// Be carefull to not have different tests using the same file names.
 /// Just call the main with the name of the test
 pub struct Script {
     run: String,
+    opt_level: usize,
+}
+
+/// What a script printed and how it ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// What the program printed.
+    pub stdout: String,
+    /// The errors, as the interpreter shows them on stderr.
+    pub stderr: String,
+    /// The exit status the interpreter would have : 0 on success, 1 on error.
+    pub status: i32,
 }
 
 impl Script {
@@ -15,15 +35,225 @@ impl Script {
     pub fn new(main: &str) -> Self {
         Script {
             run: main.to_string(),
+            opt_level: 0,
+        }
+    }
+
+    /// Optimizes the script at the given level before running it.
+    pub fn optimize(&mut self, level: usize) -> &mut Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Runs the program and returns its output, whatever its size.
+    pub fn run(&mut self) -> Output {
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let result = {
+            let mut lisaa = Lisaa::new(self.run.clone(), &mut stdout, true);
+            lisaa.error_output(&mut stderr).optimize(self.opt_level);
+            lisaa.run().map(|_| ())
+        };
+        let status = match result {
+            Ok(()) => 0,
+            Err(err) => {
+                stderr.extend_from_slice(format!("error: {}\n", err).as_bytes());
+                1
+            }
+        };
+        Output {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            status: status,
+        }
+    }
+}
+
+/// A script of the golden tests with its expectation files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Golden {
+    /// The name of the test, test_NAME.lisaa without test_ and the extension.
+    pub name: String,
+    dir: PathBuf,
+}
+
+impl Golden {
+    /// Finds all the test_NAME.lisaa scripts directly in the directory, sorted by name.
+    pub fn discover(dir: &str) -> Result<Vec<Golden>, String> {
+        let entries =
+            fs::read_dir(dir).map_err(|e| format!("could not read directory : {}, error : {}", dir, e))?;
+        let mut tests = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("could not read directory : {}, error : {}", dir, e))?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with("test_") && file_name.ends_with(".lisaa") {
+                tests.push(Golden {
+                    name: file_name["test_".len()..file_name.len() - ".lisaa".len()].to_string(),
+                    dir: Path::new(dir).to_path_buf(),
+                });
+            }
+        }
+        tests.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tests)
+    }
+
+    /// Returns the path of the script.
+    pub fn script(&self) -> PathBuf {
+        self.dir.join(format!("test_{}.lisaa", self.name))
+    }
+
+    /// Runs the script and compares what it does with the expectation files.
+    /// When blessing, the files are rewritten instead : expect_NAME.stderr only exists if the script failed.
+    pub fn check(&self, bless: bool) -> Result<(), String> {
+        let script = self.script();
+        let (stdout_file, stderr_file) = (
+            self.dir.join(format!("expect_{}", self.name)),
+            self.dir.join(format!("expect_{}.stderr", self.name)),
+        );
+        let output = Script::new(&script.to_string_lossy()).run();
+        let errors = strip_instructions(&output.stderr);
+        if bless {
+            write_file(&stdout_file, &output.stdout)?;
+            return match output.status {
+                0 if stderr_file.exists() => fs::remove_file(&stderr_file)
+                    .map_err(|e| format!("could not remove {} : {}", stderr_file.display(), e)),
+                0 => Ok(()),
+                _ => write_file(&stderr_file, &errors),
+            };
+        }
+        let mut failures = vec![];
+        let expected_errors = match stderr_file.exists() {
+            true => Some(read_file(&stderr_file)?),
+            false => None,
+        };
+        match (&expected_errors, output.status) {
+            (&None, 0) | (&Some(_), 1) => (),
+            (&None, _) => failures.push(format!("the script failed :\n{}", output.stderr)),
+            (&Some(_), _) => failures.push("the script was expected to fail".to_string()),
+        }
+        if let Some(diff) = output.diff_stdout(&read_file(&stdout_file)?) {
+            failures.push(format!("unexpected output :\n{}", diff));
+        }
+        if let Some(diff) = expected_errors.and_then(|e| diff(&e, &errors)) {
+            failures.push(format!("unexpected errors :\n{}", diff));
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(format!("{} : {}", script.display(), failures.join("\n"))),
         }
     }
-    /// Runs the program and returns its output.
-    pub fn run_program(&mut self, output_size: usize) -> Result<String, String> {
-        let mut output = ['a'].iter().cycle().take(output_size).collect::<String>();
-        {
-            let mut output_stream = Cursor::new(unsafe { output.as_bytes_mut() });
-            Lisaa::new(self.run.clone(), &mut output_stream, true).run()?;
+}
+
+/// Removes the positions of the instructions from the runtime errors :
+/// "division by zero at instruction 65 in down(num) -> num" becomes "division by zero in down(num) -> num".
+fn strip_instructions(errors: &str) -> String {
+    let marker = " at instruction ";
+    let mut stripped = String::new();
+    let mut rest = errors;
+    while let Some(start) = rest.find(marker) {
+        let after = &rest[start + marker.len()..];
+        let digits = after.find(|c: char| !c.is_digit(10)).unwrap_or(after.len());
+        stripped.push_str(&rest[..start]);
+        if digits == 0 {
+            stripped.push_str(marker);
+        }
+        rest = &after[digits..];
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Reads an expectation file, ignoring the windows line endings.
+fn read_file(path: &Path) -> Result<String, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| format!("could not read file : {}, error : {}", path.display(), e))?;
+    Ok(contents.replace("\r", ""))
+}
+
+/// Writes an expectation file.
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("could not write file : {}, error : {}", path.display(), e))
+}
+
+impl Output {
+    /// Compares what the program printed with the expected output.
+    /// Returns the differences, or None if they are the same.
+    pub fn diff_stdout(&self, expected: &str) -> Option<String> {
+        diff(expected, &self.stdout)
+    }
+}
+
+/// Shows the lines that differ between the expected and the actual text, None if they are the same.
+/// The lines missing are prefixed with -, the lines added with + and the common ones with two spaces.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let (old, new) = (
+        expected.split('\n').collect::<Vec<&str>>(),
+        actual.split('\n').collect::<Vec<&str>>(),
+    );
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
         }
-        Ok(output)
+    }
+    let mut result = String::from("--- expected\n+++ actual\n");
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            result.push_str(&format!("  {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            result.push_str(&format!("- {}\n", old[i]));
+            i += 1;
+        } else {
+            result.push_str(&format!("+ {}\n", new[j]));
+            j += 1;
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests_script {
+    use super::*;
+
+    #[test]
+    fn test_strip_instructions() {
+        assert_eq!(
+            strip_instructions("error: division by zero at instruction 65 in down(num) -> num\n"),
+            "error: division by zero in down(num) -> num\n"
+        );
+        assert_eq!(
+            strip_instructions("error: stopped at instruction count\n"),
+            "error: stopped at instruction count\n"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+        assert_eq!(
+            diff("1\n2\n3\n", "1\n3\n4\n"),
+            Some("--- expected\n+++ actual\n  1\n- 2\n  3\n+ 4\n  \n".to_string())
+        );
+        assert_eq!(
+            diff("a\n", "b\n"),
+            Some("--- expected\n+++ actual\n- a\n+ b\n  \n".to_string())
+        );
+        assert_eq!(
+            diff("done\n", "done"),
+            Some("--- expected\n+++ actual\n  done\n- \n".to_string())
+        );
     }
 }
@@ -1,6 +1,9 @@
 //! The module for statement.
+use diagnostic::Span;
 use expression::Expr;
+use module::{self, Import, Module};
 use std::collections::HashMap;
+use std::fmt;
 use types::{LisaaType, TypedVar};
 use vm::OP;
 
@@ -11,6 +14,8 @@ pub struct Program {
     functions: HashMap<FunctionSig, FunctionDecl>,
     classes: HashMap<String, ClassDecl>,
     traits: HashMap<String, TraitDecl>,
+    /// The modules the declarations come from.
+    modules: Vec<Module>,
 }
 impl Program {
     /// Creates an empty program.
@@ -19,6 +24,7 @@ impl Program {
             functions: HashMap::new(),
             classes: HashMap::new(),
             traits: HashMap::new(),
+            modules: vec![],
         }
     }
     /// Creates a new program with the given classes and functions.
@@ -31,12 +37,29 @@ impl Program {
             functions: funcs,
             classes: classes,
             traits: traits,
+            modules: vec![],
         }
     }
     /// Get the classes in the program.
     pub fn classes(&self) -> &HashMap<String, ClassDecl> {
         &self.classes
     }
+    /// Get the classes in the program as mutable.
+    pub fn classes_mut(&mut self) -> &mut HashMap<String, ClassDecl> {
+        &mut self.classes
+    }
+    /// Get the modules the declarations come from.
+    pub fn modules(&self) -> &Vec<Module> {
+        &self.modules
+    }
+    /// Makes the program the declarations of the given module.
+    pub fn set_module(&mut self, module: Module) {
+        self.modules = vec![module];
+    }
+    /// Returns the module declaring the function, class or trait with the given name.
+    pub fn module_of(&self, name: &str) -> Option<&Module> {
+        module::declaring(&self.modules, name)
+    }
     /// Get the functions in the program.
     pub fn traits(&self) -> &HashMap<String, TraitDecl> {
         &self.traits
@@ -72,14 +95,19 @@ impl Program {
         }
     }
     /// Merges this program with an other.
+    /// The names declared by several modules are already qualified, see Module::internal.
     pub fn merge(
         &mut self,
         Program {
             functions,
             classes,
             traits,
+            modules,
         }: Program,
     ) -> Result<(), String> {
+        for m in modules {
+            module::add_module(&mut self.modules, m)?;
+        }
         for func in functions {
             if self.functions.get(&func.0).is_some() {
                 return Err(format!("function already exists : {:?}", func.0));
@@ -113,7 +141,7 @@ pub enum Element {
     /// A class declaration.
     Class(ClassDecl),
     /// Import a module
-    Import(String),
+    Import(Import),
     /// declares a trait
     Trait(TraitDecl),
 }
@@ -124,6 +152,8 @@ pub struct TraitDecl {
     name: String,
     sub_traits: Vec<String>,
     methods: HashMap<String, FunctionSig>,
+    /// Where the trait is declared, None for the native ones.
+    span: Option<Span>,
 }
 
 impl TraitDecl {
@@ -140,12 +170,25 @@ impl TraitDecl {
             name: name,
             sub_traits: sub_traits,
             methods: methods,
+            span: None,
         }
     }
+    /// Returns where the trait is declared.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+    /// Sets where the trait is declared.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
     /// Returns the name of the class.
     pub fn name(&self) -> &String {
         &self.name
     }
+    /// Renames the trait, see Module::internal.
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
     /// Returns the name of the class.
     pub fn methods(&self) -> &HashMap<String, FunctionSig> {
         &self.methods
@@ -154,6 +197,14 @@ impl TraitDecl {
     pub fn sub_traits(&self) -> &Vec<String> {
         &self.sub_traits
     }
+    /// Returns the sub traits as mutable.
+    pub fn sub_traits_mut(&mut self) -> &mut Vec<String> {
+        &mut self.sub_traits
+    }
+    /// Returns the methods as mutable.
+    pub fn methods_mut(&mut self) -> &mut HashMap<String, FunctionSig> {
+        &mut self.methods
+    }
 }
 
 /// A class declaration
@@ -162,6 +213,8 @@ pub struct ClassDecl {
     name: String,
     attributes: Vec<Declaration>,
     type_params: Vec<TypeParam>,
+    /// Where the class is declared, None for the native ones.
+    span: Option<Span>,
 }
 
 impl ClassDecl {
@@ -171,8 +224,17 @@ impl ClassDecl {
             name: name,
             attributes: attrs,
             type_params: type_params,
+            span: None,
         }
     }
+    /// Returns where the class is declared.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+    /// Sets where the class is declared.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
     /// Returns the type of object, this is a number between 0 and 64 for which the bits represents
     /// the positions of the pointers.
     /// eg (int, ptr, int, char, ptr) would be 010011 -> 2+16+32 => 50
@@ -180,7 +242,7 @@ impl ClassDecl {
         self.attributes
             .iter()
             .enumerate()
-            .filter(|(i, a)| a.val_type() != &LisaaType::Num && a.val_type() != &LisaaType::Char)
+            .filter(|(i, a)| a.val_type().is_pointer_type())
             .map(|(id, a)| 2u64.pow(id as u32))
             .sum::<u64>() + 2u64.pow(self.attributes.len() as u32)
     }
@@ -188,10 +250,22 @@ impl ClassDecl {
     pub fn name(&self) -> &String {
         &self.name
     }
+    /// Renames the class, see Module::internal.
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
     /// Returns the name of the class.
     pub fn type_params(&self) -> &Vec<TypeParam> {
         &self.type_params
     }
+    /// Returns the attributes.
+    pub fn attributes(&self) -> &Vec<Declaration> {
+        &self.attributes
+    }
+    /// Returns the attributes as mutable.
+    pub fn attributes_mut(&mut self) -> &mut Vec<Declaration> {
+        &mut self.attributes
+    }
     /// Get the declaration of an attribute given its name.
     pub fn get_attr(&self, attr_name: &String) -> Option<&Declaration> {
         self.attributes.iter().find(|d| &d.val_name == attr_name)
@@ -223,6 +297,7 @@ impl ClassDecl {
             inline: false,
             scope: self.create_constructor_scope(),
             arguments: vec![],
+            span: self.span.clone(),
         }
     }
     /// Returns the basic isObject method
@@ -239,6 +314,7 @@ impl ClassDecl {
             inline: true,
             scope: Statement::Scope(vec![]),
             arguments: vec![],
+            span: None,
         }
     }
     /// Creates the code for the constructor.
@@ -370,6 +446,24 @@ impl FunctionSig {
     }
 }
 
+/// Shows the signature like it is written in the code, with the type parameters replaced :
+/// String::add(String) -> String
+impl fmt::Display for FunctionSig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|a| format!("{}", a).replace("class ", ""))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{}({})", self.name, args)?;
+        match self.ret_type {
+            LisaaType::Void => Ok(()),
+            ref t => write!(f, " -> {}", format!("{}", t).replace("class ", "")),
+        }
+    }
+}
+
 /// A function declaration
 #[derive(Debug, Clone)]
 pub struct FunctionDecl {
@@ -384,6 +478,8 @@ pub struct FunctionDecl {
     pub signature: FunctionSig,
     /// The function argument's names
     pub arguments: Vec<TypedVar>,
+    /// Where the function is declared, None for the natives and the generated functions.
+    pub span: Option<Span>,
 }
 
 impl FunctionDecl {
@@ -401,6 +497,7 @@ impl FunctionDecl {
             signature: FunctionSig::new(type_args, args.clone(), ret_type, name, None),
             scope: scope,
             arguments: args,
+            span: None,
         }
     }
 
@@ -419,6 +516,7 @@ impl FunctionDecl {
             ),
             scope: Statement::Native(vec![]),
             arguments: args_typevar,
+            span: None,
         }
     }
 
@@ -438,8 +536,13 @@ impl FunctionDecl {
             signature: FunctionSig::new(type_args, args.clone(), ret_type, name, self_type),
             scope: scope,
             arguments: args,
+            span: None,
         }
     }
+    /// Sets where the function is declared.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
     /// set the type of the "self" if it is a method.
     pub fn set_self(&mut self, tp: LisaaType) {
         self.signature.self_type = Some(tp)
@@ -596,19 +699,26 @@ impl Declaration {
     pub fn val_type(&self) -> &LisaaType {
         &self.val_type
     }
+    /// Returns the type of the value as mutable.
+    pub fn val_type_mut(&mut self) -> &mut LisaaType {
+        &mut self.val_type
+    }
 }
-/// Represents an if statement, its condition and the statement to exeute if it is true.
+/// Represents an if statement, its condition, the statement to exeute if it is true and the one
+/// to execute otherwise. An else if is an else statement containing an other if statement.
 #[derive(Debug, Clone)]
 pub struct IfStatement {
     cond: Expr,
     statement: Box<Statement>,
+    else_statement: Option<Box<Statement>>,
 }
 impl IfStatement {
-    /// Creates a new if statement with the following condition and statement to execute.
-    pub fn new(cond: Expr, statement: Statement) -> Self {
+    /// Creates a new if statement with the following condition and statements to execute.
+    pub fn new(cond: Expr, statement: Statement, else_statement: Option<Statement>) -> Self {
         IfStatement {
             cond: cond,
             statement: Box::new(statement),
+            else_statement: else_statement.map(Box::new),
         }
     }
     /// Returns the condition to execute.
@@ -619,6 +729,10 @@ impl IfStatement {
     pub fn statement(&self) -> &Statement {
         &*self.statement
     }
+    /// Returns the statement to execute if the condition is false.
+    pub fn else_statement(&self) -> Option<&Statement> {
+        self.else_statement.as_ref().map(|s| &**s)
+    }
     /// Returns the condition to execute.
     pub fn condition_mut(&mut self) -> &mut Expr {
         &mut self.cond
@@ -627,6 +741,10 @@ impl IfStatement {
     pub fn statement_mut(&mut self) -> &mut Statement {
         &mut *self.statement
     }
+    /// Returns the statement to execute if the condition is false.
+    pub fn else_statement_mut(&mut self) -> Option<&mut Statement> {
+        self.else_statement.as_mut().map(|s| &mut **s)
+    }
 }
 
 /// Represents an while statement, its condition and the statement to exeute if it is true.
@@ -634,6 +752,8 @@ impl IfStatement {
 pub struct WhileStatement {
     cond: Expr,
     statement: Box<Statement>,
+    /// Written as a for, see `Statement::for_statement`.
+    is_for: bool,
 }
 impl WhileStatement {
     /// Creates a new while statement with the following condition and statement to execute.
@@ -641,8 +761,13 @@ impl WhileStatement {
         WhileStatement {
             cond: cond,
             statement: Box::new(statement),
+            is_for: false,
         }
     }
+    /// Checks if the loop was written as a for, the formatter writes it back the same way.
+    pub fn is_for(&self) -> bool {
+        self.is_for
+    }
     /// Returns the condition to execute.
     pub fn condition(&self) -> &Expr {
         &self.cond
@@ -670,7 +795,8 @@ impl Statement {
         }
     }
 
-    /// A for statement is just a init cond followed by a while
+    /// A for statement is just a init cond followed by a while :
+    /// a scope containing the init and a while whose statement is a scope of the inner statement and the repeated one.
     pub fn for_statement(
         init: Statement,
         cond: Expr,
@@ -678,7 +804,8 @@ impl Statement {
         inner: Statement,
     ) -> Statement {
         let inner_scope = Statement::Scope(vec![inner, repeat]);
-        let inner_while = Statement::WhileStatement(WhileStatement::new(cond, inner_scope));
-        Statement::Scope(vec![init, inner_while])
+        let mut inner_while = WhileStatement::new(cond, inner_scope);
+        inner_while.is_for = true;
+        Statement::Scope(vec![init, Statement::WhileStatement(inner_while)])
     }
 }
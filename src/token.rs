@@ -1,5 +1,6 @@
 //! The tokens are defined here
 //!
+use diagnostic::Span;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 /// All the different types of tokens.
@@ -114,34 +115,38 @@ pub enum TokenType {
     WHILE,
     /// The ignore keyword, tells that this keyword may be ignored.
     IGNORE,
+    /// A comment, kept aside by the scanner for the formatter, the parser never sees it.
+    COMMENT,
     /// The import keyword import a module in scope
     IMPORT,
+    /// The pub keyword, makes a declaration visible from the other modules
+    PUB,
 
     /// The End of the File
     EOF,
 }
 
 #[derive(Debug, Clone)]
-/// The structure of the token, contains the line, the type and the content.
+/// The structure of the token, contains the position, the type and the content.
 pub struct Token {
-    line: usize,
+    span: Span,
     token_type: TokenType,
     lexeme: String,
 }
 
 impl Token {
-    /// Creates a new token at the given line with the given type.
-    pub fn new(line: usize, token_type: TokenType, lexeme: String) -> Self {
+    /// Creates a new token at the given position with the given type.
+    pub fn new(span: Span, token_type: TokenType, lexeme: String) -> Self {
         Token {
-            line: line,
+            span: span,
             token_type: token_type,
             lexeme: lexeme,
         }
     }
 
-    pub fn char(line: usize, ch: char) -> Self {
+    pub fn char(span: Span, ch: char) -> Self {
         Token {
-            line: line,
+            span: span,
             token_type: TokenType::CHAR,
             lexeme: ch.to_string(),
         }
@@ -169,6 +174,11 @@ impl Token {
 
     /// returns the line at wich the token arrived.
     pub fn get_line(&self) -> usize {
-        self.line
+        self.span.line
+    }
+
+    /// returns the position of the token in the source.
+    pub fn span(&self) -> &Span {
+        &self.span
     }
 }
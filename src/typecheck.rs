@@ -1,5 +1,9 @@
-use expression::{BinaryExpr, Callee, Expr, ExprEnum, FunctionCall, Operator, UnaryExpr};
+use diagnostic::{Diagnostic, Span};
+use expression::{
+    BinaryExpr, Callee, Closure, Expr, ExprEnum, FunctionCall, FunctionRef, Operator, UnaryExpr,
+};
 use generic_inference::Inferer;
+use module::module_name;
 use native::{get_any_trait, get_native_funcs, get_native_types};
 use statement::{
     Assignment, ClassDecl, Declaration, FunctionDecl, FunctionSig, IfStatement, Program, Statement,
@@ -49,9 +53,21 @@ impl Scope {
     }
 }
 
+/// A closure being checked, with the position of its scope in the scopes
+/// and the variables of the enclosing scopes it uses.
+#[derive(Debug)]
+struct ClosureScope {
+    scope: usize,
+    captures: Vec<TypedVar>,
+}
+
 /// The type checker
 /// Contains a program and functions to resolve types/verify consistency.
 /// Also check for lvalues and assignment.
+///
+/// Errors are collected instead of stopping the checking :
+/// a failing statement is reported and the checker goes on with the next one,
+/// a failing expression is given the error type so the expressions using it do not report again.
 pub struct TypeChecker {
     called_functions: HashMap<FunctionSig, (FunctionDecl, Vec<TypeParam>, Vec<LisaaType>)>,
     functions: HashMap<FunctionSig, FunctionDecl>,
@@ -61,6 +77,9 @@ pub struct TypeChecker {
     classes: HashMap<String, ClassDecl>,
     traits: HashMap<String, HashMap<String, FunctionSig>>,
     scopes: Vec<Scope>,
+    /// The closures being checked, the innermost last.
+    closures: Vec<ClosureScope>,
+    errors: Vec<Diagnostic>,
 }
 
 impl TypeChecker {
@@ -74,8 +93,15 @@ impl TypeChecker {
             current_morphisation : HashMap::new(),
             classes: HashMap::new(),
             traits: HashMap::new(),
+            closures: vec![],
+            errors: vec![],
         }
     }
+    /// Adds a function that is not in the program, like the ones registered by the host.
+    /// Must be called before resolve.
+    pub fn add_function(&mut self, func: FunctionDecl) {
+        self.functions.insert(func.signature().clone(), func);
+    }
     /// Add a lib to the program.
     pub fn add_natives(&mut self, lib: &str) {
         for f in get_native_funcs(lib) {
@@ -97,24 +123,29 @@ impl TypeChecker {
 
     /// Finds the variable in the scope or the scope of its parent.
     pub fn get_var(&self, var_name: &str) -> Option<TypedVar> {
+        self.find_var(var_name).map(|(_, var)| var)
+    }
+
+    /// Finds the variable and the position of the scope declaring it.
+    fn find_var(&self, var_name: &str) -> Option<(usize, TypedVar)> {
         let current_scope = self.scopes.last().unwrap().depth;
         let len = self.scopes.len();
         for sc in 0..current_scope {
             if self.scopes[len - sc - 1].has_var(var_name) {
-                return self.scopes[len - sc - 1].get_var(var_name);
+                return self.scopes[len - sc - 1]
+                    .get_var(var_name)
+                    .map(|var| (len - sc - 1, var));
             }
         }
         None
     }
     /// Checks if the type of the given expression matches with the given type.
-    pub fn check_type(&self, expr: &Expr, expected: &LisaaType) -> Result<(), String> {
+    pub fn check_type(&self, expr: &Expr, expected: &LisaaType) -> Result<(), Diagnostic> {
         let (lhs, rhs) = (expr.return_type().max_deref(), expected.max_deref());
         if !lhs.0.is_equivalent(&rhs.0) {
-            return Err(format!(
-                "Line : {}, Expected : {}, got : {}",
-                expr.get_line(),
-                expected,
-                expr.return_type()
+            return Err(Diagnostic::at(
+                format!("Expected : {}, got : {}", expected, expr.return_type()),
+                expr.span().clone(),
             ));
         }
         Ok(())
@@ -122,41 +153,115 @@ impl TypeChecker {
 
     /// Resolve types if possible
     /// The aim is to traverse the tree and resolve the return type of all expressions.
-    pub fn resolve(&mut self, program: &mut Program) -> Result<(), String> {
-        self.functions = program.functions().clone();
-        self.classes = program.classes().clone();
-        self.add_natives("base");
-        program
-            .traits_mut()
-            .insert("Any".to_string(), get_any_trait());
-        self.complete_traits(program.traits())?;
-        for (_, mut func) in program.functions_mut() {
-            self.function(&mut func)?;
-        }
-        let funcs = match program.functions().get(&FunctionSig::new(
+    /// Returns all the errors found.
+    ///
+    /// The functions are only monomorphised if they all typecheck,
+    /// so the same error is not reported for the generic function and its instances.
+    pub fn resolve(&mut self, program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+        self.check_program(program)?;
+        self.monomorphise_main(program)
+    }
+
+    /// Keeps the functions called from main, monomorphised, in a program checked by check_program.
+    pub fn monomorphise_main(&mut self, program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+        let main = match program.functions().get(&FunctionSig::new(
             vec![],
             vec![],
             LisaaType::Void,
             "main".to_string(),
             None,
         )) {
-            Some(main) => Ok(self.monomorphise(main.clone())?),
-            None => Err("No main function found".to_string()),
-        }?;
+            Some(main) => main.clone(),
+            None => return Err(vec![Diagnostic::new("No main function found".to_string())]),
+        };
+        let funcs = self.monomorphise(vec![main]);
+        self.take_errors()?;
         program.set_functions(funcs);
         Ok(())
     }
 
-    /// Monomorphise all functions and return them all
-    ///
-    pub fn monomorphise(
+    /// Resolves a program embedded in another one, that does not need a main function.
+    /// All the functions that are neither generic nor methods are kept, so they can be called by the host.
+    /// Returns their signatures.
+    pub fn resolve_library(&mut self, program: &mut Program) -> Result<Vec<FunctionSig>, Vec<Diagnostic>> {
+        self.check_program(program)?;
+        self.monomorphise_library(program)
+    }
+
+    /// Keeps the functions of a library, see resolve_library, in a program checked by check_program.
+    pub fn monomorphise_library(
         &mut self,
-        mut main: FunctionDecl,
-    ) -> Result<HashMap<FunctionSig, FunctionDecl>, String> {
+        program: &mut Program,
+    ) -> Result<Vec<FunctionSig>, Vec<Diagnostic>> {
+        let roots = program
+            .functions()
+            .values()
+            .filter(|f| f.type_args().is_empty() && f.self_type().is_none())
+            .cloned()
+            .collect::<Vec<FunctionDecl>>();
+        let signatures = roots.iter().map(|f| f.signature().clone()).collect();
+        let funcs = self.monomorphise(roots);
+        self.take_errors()?;
+        program.set_functions(funcs);
+        Ok(signatures)
+    }
+
+    /// Typechecks all the functions of the program, before their monomorphisation.
+    /// The expressions get their type even if errors are found.
+    pub fn check_program(&mut self, program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+        let natives = get_native_funcs("base");
+        for (sig, func) in program.functions().iter() {
+            if self.functions.contains_key(sig) || natives.iter().any(|n| n.signature() == sig) {
+                let message = format!("{} is already defined by a native function", sig);
+                self.report(match func.span {
+                    Some(ref span) => Diagnostic::at(message, span.clone()),
+                    None => Diagnostic::new(message),
+                });
+            }
+        }
+        self.functions.extend(program.functions().clone());
+        self.classes = program.classes().clone();
+        self.add_natives("base");
+        program
+            .traits_mut()
+            .insert("Any".to_string(), get_any_trait());
+        if let Err(e) = self.complete_traits(program.traits()) {
+            return Err(vec![e.into()]);
+        }
+        for (_, mut func) in program.functions_mut() {
+            self.function(&mut func);
+        }
+        self.take_errors()
+    }
+
+    /// Returns the errors found since the last call, if any, sorted by position.
+    fn take_errors(&mut self) -> Result<(), Vec<Diagnostic>> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let mut errors: Vec<Diagnostic> = self.errors.drain(..).collect();
+        errors.sort_by_key(|e| {
+            e.span()
+                .as_ref()
+                .map(|s| (s.file.clone(), s.line, s.column))
+        });
+        Err(errors)
+    }
+
+    /// Records an error and keeps checking.
+    fn report(&mut self, error: Diagnostic) {
+        self.errors.push(error);
+    }
+
+    /// Monomorphise all functions called from the roots (main) and return them all
+    ///
+    pub fn monomorphise(&mut self, roots: Vec<FunctionDecl>) -> HashMap<FunctionSig, FunctionDecl> {
         self.called_functions = HashMap::new();
-        self.function(&mut main)?;
         let mut morphised = HashMap::new();
-        morphised.insert(main.signature().clone(), main);
+        for mut root in roots {
+            self.function(&mut root);
+            morphised.insert(root.signature().clone(), root);
+        }
         while self.called_functions.len() > 0 {
             let (key, mut new_decl, params, actual) = {
                 let (key, mut decl) = self.called_functions.iter_mut().next().unwrap();
@@ -166,13 +271,13 @@ impl TypeChecker {
             if !morphised.contains_key(&key) {
                 if !new_decl.inline {
                     self.set_current_morphisation(params, actual);
-                    self.function(&mut new_decl)?;
+                    self.function(&mut new_decl);
                 }
                 morphised.insert(key, new_decl);
                 //println!("called functions by main : {:?}", self.called_functions.iter().map(|(s, d)|&s.name).collect::<Vec<&String>>());
             }
         }
-        Ok(morphised)
+        morphised
     }
 
     /// sets the current morphisation's values :
@@ -231,13 +336,15 @@ impl TypeChecker {
     }
 
     /// Resolve/check types for a function declaration
+    /// The errors are reported and the function is left in any case.
     /// TODO: add checks that type params appear in arguments.
-    pub fn function(&mut self, func: &mut FunctionDecl) -> Result<(), String> {
+    pub fn function(&mut self, func: &mut FunctionDecl) {
         self.add_function_in_scope(func);
-        self.add_func_type_args(func)?;
-        self.check_function(func)?;
+        match self.add_func_type_args(func) {
+            Ok(()) => self.check_function(func),
+            Err(e) => self.report(e),
+        }
         self.leave_function(func);
-        Ok(())
     }
 
     /// leave the function, remove the scope and the functions for type arguments.
@@ -246,28 +353,32 @@ impl TypeChecker {
         self.local_functions = HashSet::new();
     }
 
-    /// typechecks the function.
-    pub fn check_function(&mut self, func: &mut FunctionDecl) -> Result<(), String> {
+    /// typechecks the function, statement by statement.
+    pub fn check_function(&mut self, func: &mut FunctionDecl) {
         let (ret_type, name) = (func.ret_type().clone(), func.name().to_string());
         for st in func.scope_mut() {
-            self.statement(st)?;
+            if let Err(e) = self.statement(st) {
+                self.report(e);
+                continue;
+            }
             if let &mut Statement::ReturnStatement(ref mut expr) = st {
-                self.check_type(&expr, &ret_type).map_err(|_| {
-                    format!(
-                        "line : {}\n\tFunction {} returns {} but value is of type {}",
-                        &expr.get_line(),
-                        name,
-                        &ret_type,
-                        &expr.return_type()
-                    )
-                })?;
+                if let Err(_) = self.check_type(&expr, &ret_type) {
+                    self.report(Diagnostic::at(
+                        format!(
+                            "Function {} returns {} but value is of type {}",
+                            name,
+                            &ret_type,
+                            &expr.return_type()
+                        ),
+                        expr.span().clone(),
+                    ));
+                }
             }
         }
-        Ok(())
     }
 
     /// Adds the type arguments and theyre constructors in scope.
-    pub fn add_func_type_args(&mut self, func: &mut FunctionDecl) -> Result<(), String> {
+    pub fn add_func_type_args(&mut self, func: &mut FunctionDecl) -> Result<(), Diagnostic> {
         for arg in func.type_args() {
             let trait_name = arg.trait_name().clone();
             self.create_type_param(arg.name().clone(), trait_name);
@@ -352,39 +463,69 @@ impl TypeChecker {
     }
 
     /// Resolve what needs to be resolved in a statement.
-    pub fn statement(&mut self, statement: &mut Statement) -> Result<(), String> {
+    pub fn statement(&mut self, statement: &mut Statement) -> Result<(), Diagnostic> {
         match statement {
             &mut Statement::Assignment(ref mut a) => self.assignment(a),
             &mut Statement::Declaration(ref mut d) => self.declaration(d),
-            &mut Statement::ExprStatement(ref mut e) => self.expression(e),
+            &mut Statement::ExprStatement(ref mut e) => Ok(self.expression(e)),
             &mut Statement::Scope(ref mut s) => self.scope(s),
             &mut Statement::IfStatement(ref mut i) => self.if_statement(i),
             &mut Statement::WhileStatement(ref mut i) => self.while_statement(i),
             &mut Statement::BreakStatement => Ok(()),
-            &mut Statement::ReturnStatement(ref mut e) => self.expression(e),
+            &mut Statement::ReturnStatement(ref mut e) => Ok(self.expression(e)),
             &mut Statement::Native(_) => Ok(()),
         }
     }
 
-    pub fn if_statement(&mut self, if_statement: &mut IfStatement) -> Result<(), String> {
-        self.expression(if_statement.condition_mut())?;
-        self.statement(if_statement.statement_mut())?;
-        Ok(())
+    /// Checks the condition and the two branches, an error in a branch does not stop the other.
+    pub fn if_statement(&mut self, if_statement: &mut IfStatement) -> Result<(), Diagnostic> {
+        self.condition(if_statement.condition_mut());
+        if let Err(e) = self.branch(if_statement.statement_mut()) {
+            self.report(e);
+        }
+        match if_statement.else_statement_mut() {
+            Some(else_statement) => self.branch(else_statement),
+            None => Ok(()),
+        }
     }
 
-    pub fn while_statement(&mut self, while_statement: &mut WhileStatement) -> Result<(), String> {
-        self.expression(while_statement.condition_mut())?;
-        self.statement(while_statement.statement_mut())?;
-        Ok(())
+    /// Checks a conditional statement in its own scope, like the compiler does.
+    pub fn branch(&mut self, statement: &mut Statement) -> Result<(), Diagnostic> {
+        if let &mut Statement::Scope(_) = statement {
+            return self.statement(statement);
+        }
+        let depth = self.scopes.len();
+        self.scopes.push(Scope::new(depth));
+        let result = self.statement(statement);
+        self.scopes.pop();
+        result
+    }
+
+    pub fn while_statement(
+        &mut self,
+        while_statement: &mut WhileStatement,
+    ) -> Result<(), Diagnostic> {
+        self.condition(while_statement.condition_mut());
+        self.statement(while_statement.statement_mut())
+    }
+
+    /// Checks the condition of an if or a while, it must be a bool.
+    pub fn condition(&mut self, condition: &mut Expr) {
+        self.expression(condition);
+        if let Err(e) = self.check_type(condition, &LisaaType::Bool) {
+            self.report(e);
+        }
     }
 
     /// Checks a scope
-    /// checks every statement in the scope (easy this one).
-    pub fn scope(&mut self, scope: &mut Vec<Statement>) -> Result<(), String> {
+    /// checks every statement in the scope (easy this one), reporting the failing ones.
+    pub fn scope(&mut self, scope: &mut Vec<Statement>) -> Result<(), Diagnostic> {
         let depth = self.scopes.len();
         self.scopes.push(Scope::new(depth));
         for st in scope {
-            self.statement(st)?;
+            if let Err(e) = self.statement(st) {
+                self.report(e);
+            }
         }
         self.scopes.pop();
         Ok(())
@@ -394,12 +535,12 @@ impl TypeChecker {
     /// Resolve the expression's types.
     /// checks that types match.
     /// creates a new variable with the given type in the scope.
-    pub fn declaration(&mut self, decl: &mut Declaration) -> Result<(), String> {
+    /// The variable is created even if the types do not match, to avoid unknown variable errors.
+    pub fn declaration(&mut self, decl: &mut Declaration) -> Result<(), Diagnostic> {
         let val_type = self.replace_gen(decl.val_type());
-        self.expression(decl.expr_mut())?;
-        self.check_type(decl.expr(), &val_type)?;
+        self.expression(decl.expr_mut());
         self.create_var(TypedVar::new(val_type.clone(), decl.name().to_string()));
-        Ok(())
+        self.check_type(decl.expr(), &val_type)
     }
 
 
@@ -408,6 +549,10 @@ impl TypeChecker {
     pub fn replace_gen(&self, declared_type : &LisaaType) -> LisaaType {
         match declared_type {
             LisaaType::Pointer(ref t) => LisaaType::Pointer(Box::new(self.replace_gen(t))),
+            LisaaType::FunctionPointer(ref args, ref ret) => LisaaType::function_pointer(
+                args.iter().map(|a| self.replace_gen(a)).collect(),
+                self.replace_gen(ret),
+            ),
             LisaaType::Class(ref n, ref args) => match self.current_morphisation.get(n){
                 Some(actual_type) => actual_type.clone(),
                 None => LisaaType::Class(n.clone(), args.iter().map(|a|self.replace_gen(a)).collect()),
@@ -421,11 +566,25 @@ impl TypeChecker {
     /// then what it is assigned to (must be a lvalue).
     /// then if they match.
     /// If we assign to a dereferenced value we need to asign behind the pointer.
-    pub fn assignment(&mut self, assignment: &mut Assignment) -> Result<(), String> {
-        self.expression(assignment.expr_mut())?;
-        self.expression(assignment.assignee_mut())?;
+    pub fn assignment(&mut self, assignment: &mut Assignment) -> Result<(), Diagnostic> {
+        self.expression(assignment.expr_mut());
+        self.expression(assignment.assignee_mut());
+        if assignment.assignee().return_type().is_error() {
+            return Ok(());
+        }
         if !self.is_assignee(assignment.assignee()) {
-            return Err(format!("can only assign to pointer or local variables "));
+            return Err(Diagnostic::at(
+                format!("can only assign to pointer or local variables "),
+                assignment.assignee().span().clone(),
+            ));
+        }
+        if let &ExprEnum::Identifier(ref id) = assignment.assignee().expr() {
+            if self.is_captured(id) {
+                return Err(Diagnostic::at(
+                    format!("can not assign to {}, the closure only has a copy of it", id),
+                    assignment.assignee().span().clone(),
+                ));
+            }
         }
         self.check_type(assignment.expr(), &assignment.assignee().return_type())?;
         self.deref_assignment(assignment.assignee_mut());
@@ -446,30 +605,46 @@ impl TypeChecker {
 
     /// Sets the expression's return type.
     /// Sets the type of incoming and outcoming variables so the compiler will know what it needs to.
-    pub fn expression(&mut self, expr: &mut Expr) -> Result<(), String> {
+    /// If the expression is wrong, the error is reported and the expression gets the error type.
+    /// Errors without location are given the position of the expression.
+    pub fn expression(&mut self, expr: &mut Expr) {
         let ret_type = expr.return_type_uncheck().clone();
-        let line = expr.get_line();
+        let span = expr.span().clone();
+        if let Some(name) = self.named_function(expr) {
+            *expr.expr_mut() = ExprEnum::FunctionRef(FunctionRef::new(name, vec![]));
+        }
         let tp = match expr.expr_mut() {
             &mut ExprEnum::Literal(_) => Ok(ret_type.unwrap()),
             &mut ExprEnum::Unary(ref mut u) => self.unary(u),
             &mut ExprEnum::GetAttr(ref mut b) => self.getattr(b),
-            &mut ExprEnum::Identifier(ref mut i) => self.identifier(i, line),
-            &mut ExprEnum::FunctionCall(ref mut f) => self.function_call(f, line),
+            &mut ExprEnum::Identifier(ref mut i) => self.identifier(i),
+            &mut ExprEnum::FunctionCall(ref mut f) => self.function_call(f, &span),
             &mut ExprEnum::Deref(ref mut d) => {
-                self.expression(d.inner_mut())?;
+                self.expression(d.inner_mut());
                 Ok(d.inner().return_type())
             }
-        }?;
-        expr.set_type(self.replace_gen(&tp));
-        Ok(())
+            &mut ExprEnum::FunctionRef(ref mut f) => self.function_ref(f),
+            &mut ExprEnum::Closure(ref mut c) => self.closure(c),
+        };
+        let tp = match tp {
+            Ok(tp) => self.replace_gen(&tp),
+            Err(e) => {
+                self.report(e.or_at(&span));
+                LisaaType::Error
+            }
+        };
+        expr.set_type(tp);
     }
 
     /// Parses a ___.___
-    pub fn getattr(&mut self, expr: &mut BinaryExpr) -> Result<LisaaType, String> {
+    pub fn getattr(&mut self, expr: &mut BinaryExpr) -> Result<LisaaType, Diagnostic> {
         if expr.operator() != Operator::Get {
-            return Err(format!("Not a . operator"));
+            return Err(format!("Not a . operator").into());
         } else {
-            self.expression(expr.lhs_mut())?;
+            self.expression(expr.lhs_mut());
+            if expr.lhs().return_type().is_error() {
+                return Ok(LisaaType::Error);
+            }
             let rhs = expr.rhs().get_identifier()?;
             expr.lhs()
                 .return_type()
@@ -478,13 +653,24 @@ impl TypeChecker {
                     &self.get_classes_with_type_params(),
                     &self.get_functions_with_type_params(),
                 )
-                .map_err(|e| format!("{} line {}", e, expr.lhs().get_line()))
+                .map_err(|e| Diagnostic::at(e, expr.rhs().span().clone()))
+        }
+    }
+
+    /// Returns the type parameters of the current scope and of the scopes containing it.
+    pub fn type_params_in_scope(&self) -> HashMap<String, String> {
+        let current_scope = self.scopes.last().unwrap().depth;
+        let len = self.scopes.len();
+        let mut type_params = HashMap::new();
+        for sc in (0..current_scope).rev() {
+            type_params.extend(self.scopes[len - sc - 1].type_params.clone());
         }
+        type_params
     }
 
     pub fn get_classes_with_type_params(&self) -> HashMap<String, ClassDecl> {
         let mut new_map = self.classes.clone();
-        for (name, _) in self.scopes.last().unwrap().type_params.iter() {
+        for (name, _) in self.type_params_in_scope() {
             new_map.insert(name.clone(), ClassDecl::new(name.clone(), vec![], vec![]));
         }
         new_map
@@ -493,8 +679,8 @@ impl TypeChecker {
     /// Returns a hashmap of all the functions currently in scope
     pub fn get_functions_with_type_params(&self) -> HashMap<FunctionSig, FunctionDecl> {
         let mut new_map = self.functions.clone();
-        for (type_name, trait_name) in self.scopes.last().unwrap().type_params.iter() {
-            for (func_name, func_sig) in self.traits.get(trait_name).unwrap() {
+        for (type_name, trait_name) in self.type_params_in_scope() {
+            for (func_name, func_sig) in self.traits.get(&trait_name).unwrap() {
                 let complete_name = format!("{}::{}", type_name, func_name);
                 let func_decl = FunctionDecl::from_sig(complete_name, func_sig.clone());
                 new_map.insert(func_decl.signature().clone(), func_decl);
@@ -504,48 +690,240 @@ impl TypeChecker {
     }
 
     /// Returns the type of the given identifier if it exists in scope.
-    pub fn identifier(&mut self, id: &String, line : usize) -> Result<LisaaType, String> {
-        match self.get_var(id) {
-            Some(ref var) => Ok(var.type_var().clone().unwrap()),
-            None => Err(String::from(format!("Unknown variable : {} line {}", id, line))),
+    /// A variable of an enclosing function used in a closure is captured by the closure.
+    pub fn identifier(&mut self, id: &String) -> Result<LisaaType, Diagnostic> {
+        match self.find_var(id) {
+            Some((scope, ref var)) => {
+                self.capture(scope, var);
+                Ok(var.type_var().clone().unwrap())
+            }
+            None => Err(format!("Unknown variable : {}", id).into()),
+        }
+    }
+
+    /// Records the variable in the closures between the scope declaring it and the current scope.
+    fn capture(&mut self, scope: usize, var: &TypedVar) {
+        for closure in self.closures.iter_mut().filter(|c| scope < c.scope) {
+            if !closure.captures.iter().any(|c| c.name() == var.name()) {
+                closure.captures.push(var.clone());
+            }
+        }
+    }
+
+    /// Checks if the variable is declared outside of the closure being checked, so it is captured.
+    fn is_captured(&self, id: &str) -> bool {
+        match (self.find_var(id), self.closures.last()) {
+            (Some((scope, _)), Some(closure)) => scope < closure.scope,
+            _ => false,
+        }
+    }
+
+    /// Returns the name of the function if the expression is an identifier naming a function
+    /// and no variable.
+    fn named_function(&self, expr: &Expr) -> Option<String> {
+        match expr.expr() {
+            &ExprEnum::Identifier(ref id)
+                if self.get_var(id).is_none()
+                    && self.functions.keys().any(|f| f.name() == id) =>
+            {
+                Some(id.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves a function used as a value.
+    /// The instances of generic functions are monomorphised like the called ones.
+    pub fn function_ref(&mut self, func: &mut FunctionRef) -> Result<LisaaType, Diagnostic> {
+        let type_args = func
+            .type_args()
+            .iter()
+            .map(|t| self.replace_gen(t))
+            .collect();
+        let functions = self.module_functions(func.module(), func.name());
+        let (sig, decl) = Inferer::new(
+            functions.as_ref().unwrap_or(&self.functions),
+            &self.local_functions,
+            vec![],
+            type_args,
+            None,
+            func.name().clone(),
+            &self.traits,
+        ).infer_reference()?;
+        let sig = self.module_instance(func.module(), sig);
+        let to_ins = self.try_insert_called_function(&sig, &Some(decl));
+        func.set_signature(sig.clone());
+        if let Some(val) = to_ins {
+            self.called_functions.insert(sig.clone(), val);
         }
+        Ok(LisaaType::function_pointer(sig.args, sig.ret_type))
+    }
+
+    /// Returns the functions without the overloads of the name declared outside of the file,
+    /// None if the name is not qualified by a module.
+    fn module_functions(
+        &self,
+        file: Option<&String>,
+        name: &str,
+    ) -> Option<HashMap<FunctionSig, FunctionDecl>> {
+        let file = file?;
+        Some(
+            self.functions
+                .iter()
+                .filter(|&(sig, decl)| {
+                    sig.name != name || decl.span.as_ref().map_or(false, |s| &s.file == file)
+                })
+                .map(|(sig, decl)| (sig.clone(), decl.clone()))
+                .collect(),
+        )
+    }
+
+    /// Qualifies the instance of a generic function of the module by its module name
+    /// when an other module declares a function with the signature of the instance :
+    /// string::println(n) calls string::println(num) when the program declares println(num).
+    fn module_instance(&self, file: Option<&String>, sig: FunctionSig) -> FunctionSig {
+        let file = match file {
+            Some(file) => file,
+            None => return sig,
+        };
+        match self.functions.get(&sig).and_then(|decl| decl.span.as_ref()) {
+            Some(span) if &span.file != file => {
+                let mut sig = sig;
+                sig.name = format!("{}::{}", module_name(file), sig.name);
+                sig
+            }
+            _ => sig,
+        }
+    }
+
+    /// Checks a closure like a function.
+    /// Its scope sees the variables of the enclosing scopes, the ones it uses are captured.
+    pub fn closure(&mut self, closure: &mut Closure) -> Result<LisaaType, Diagnostic> {
+        let mut sig = closure.decl().signature().clone();
+        sig.args = sig.args.iter().map(|a| self.replace_gen(a)).collect();
+        sig.ret_type = self.replace_gen(&sig.ret_type);
+        closure.decl_mut().set_signature(sig.clone());
+        let depth = self.scopes.len();
+        self.scopes.push(Scope::new(depth));
+        self.closures.push(ClosureScope {
+            scope: depth,
+            captures: vec![],
+        });
+        for arg in closure.decl().args().clone() {
+            self.create_var(arg);
+        }
+        self.check_function(closure.decl_mut());
+        let captures = self.closures.pop().unwrap().captures;
+        self.scopes.pop();
+        closure.set_captures(captures);
+        Ok(LisaaType::function_pointer(sig.args, sig.ret_type))
     }
 
     /// Returns the name of the called function.
-    pub fn get_function_name(&mut self, func: &mut FunctionCall) -> Result<String, String> {
-        match func.callee_mut() {
-            &mut Callee::StaticFunc(ref mut s) => Ok(s.to_owned()),
+    /// Returns None if the method is called on a wrong expression or if a function value is called.
+    /// A variable or an attribute holding a function value becomes the callee.
+    pub fn get_function_name(
+        &mut self,
+        func: &mut FunctionCall,
+        span: &Span,
+    ) -> Result<Option<String>, Diagnostic> {
+        let value = match func.callee_mut() {
+            &mut Callee::StaticFunc(ref mut s) => match self.get_var(s) {
+                Some(_) => Expr::identifier(s.clone(), span.clone()),
+                None => return Ok(Some(s.to_owned())),
+            },
             &mut Callee::Method(ref mut e) => {
-                let line = e.get_line();
-                self.expression(e)?;
-                e.return_type().function_name().map_err(|()| {
-                    format!("Line : {}\tNot a method : {:?}\n", line, e.return_type())
-                })
+                self.expression(e);
+                if e.return_type().is_error() {
+                    return Ok(None);
+                }
+                if let (LisaaType::FunctionPointer(..), _) = e.return_type().max_deref() {
+                    (**e).clone()
+                } else {
+                    return e.return_type().function_name().map(Some).map_err(|()| {
+                        Diagnostic::at(
+                            format!("Not a method : {}", e.return_type()),
+                            e.span().clone(),
+                        )
+                    });
+                }
             }
+            &mut Callee::Value(_) => return Ok(None),
+        };
+        *func.callee_mut() = Callee::Value(Box::new(value));
+        Ok(None)
+    }
+
+    /// Checks the call of a function value against the type of the value.
+    pub fn value_call(&mut self, exp: &mut FunctionCall) -> Result<LisaaType, Diagnostic> {
+        let value_type = match exp.callee_mut() {
+            &mut Callee::Value(ref mut value) => {
+                self.expression(value);
+                value.return_type()
+            }
+            _ => panic!("not a call of a function value"),
+        };
+        match value_type.max_deref().0 {
+            LisaaType::FunctionPointer(args, ret) => {
+                if args.len() != exp.args().len() {
+                    return Err(format!(
+                        "the function value takes {} arguments, got {}",
+                        args.len(),
+                        exp.args().len()
+                    ).into());
+                }
+                for (arg, expected) in exp.args().iter().zip(args.iter()) {
+                    self.check_type(arg, expected)?;
+                }
+                Ok(*ret)
+            }
+            LisaaType::Error => Ok(LisaaType::Error),
+            other => Err(format!("Not a function : {}", other).into()),
         }
     }
 
     /// Find the return type of a function call expression and returns it.
     /// Checks that arguments lists are the same size.
     /// Checks for arguments given to the function.
-    pub fn function_call(&mut self, exp: &mut FunctionCall, line : usize) -> Result<LisaaType, String> {
+    /// A call using a wrong expression is not checked.
+    pub fn function_call(
+        &mut self,
+        exp: &mut FunctionCall,
+        span: &Span,
+    ) -> Result<LisaaType, Diagnostic> {
         let args_count_given = exp.args().len();
         let mut given_types = vec![];
         for i in 0..args_count_given {
-            self.expression(&mut exp.args_mut()[i])?;
+            self.expression(&mut exp.args_mut()[i]);
             given_types.push(exp.args_mut()[i].return_type());
         }
-        let name = self.get_function_name(exp)?;
-        let type_args = exp.type_args().clone();
+        let name = self.get_function_name(exp, span)?;
+        if given_types.iter().any(|t| t.is_error()) {
+            return Ok(LisaaType::Error);
+        }
+        if let &Callee::Value(_) = exp.callee() {
+            return self.value_call(exp);
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(LisaaType::Error),
+        };
+        let type_args = exp
+            .type_args()
+            .iter()
+            .map(|t| self.replace_gen(t))
+            .collect();
+        let functions = self.module_functions(exp.module(), &name);
         let (sig, decl) = Inferer::new(
-            &self.functions,
+            functions.as_ref().unwrap_or(&self.functions),
             &self.local_functions,
             given_types,
-            exp.type_args().clone(),
+            type_args,
             exp.callee().get_caller_type(),
             name,
             &self.traits,
-        ).infer(line)?;
+        ).infer()?;
+        let sig = self.module_instance(exp.module(), sig);
         let to_ins = self.try_insert_called_function(&sig, &decl);
         exp.set_signature(sig.clone());
         if let Some(val) = to_ins {
@@ -570,19 +948,22 @@ impl TypeChecker {
     }
 
     /// Find the return type of a unary expression and returns it.
-    pub fn unary(&mut self, exp: &mut UnaryExpr) -> Result<LisaaType, String> {
-        self.expression(exp.expression_mut())?;
+    pub fn unary(&mut self, exp: &mut UnaryExpr) -> Result<LisaaType, Diagnostic> {
+        self.expression(exp.expression_mut());
         let exp_res = exp.expression().return_type();
+        if exp_res.is_error() {
+            return Ok(LisaaType::Error);
+        }
         match exp.operator() {
             Operator::MINUS => match exp_res {
                 LisaaType::Num => Ok(LisaaType::Num),
-                _ => Err(String::from("Operator '-' supported only for primitives")),
+                _ => Err(String::from("Operator '-' supported only for primitives").into()),
             },
             Operator::Not => match exp_res {
-                LisaaType::Num => Ok(LisaaType::Num),
-                _ => Err(String::from("Operator '!' supported only for primitives")),
+                LisaaType::Bool => Ok(LisaaType::Bool),
+                _ => Err(String::from("Operator '!' supported only for bool").into()),
             },
-            e => Err(format!("operator {:?} can not be aplied to one value", e)),
+            e => Err(format!("operator {:?} can not be aplied to one value", e).into()),
         }
     }
 
@@ -597,3 +978,128 @@ impl TypeChecker {
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests_typecheck {
+    use super::*;
+    use parser::Parser;
+    use scanner::Scanner;
+
+    fn check(source: &str) -> Vec<(usize, String)> {
+        let tokens = Scanner::new(source.to_string(), "test.lisaa".to_string())
+            .tokens()
+            .unwrap();
+        let (mut program, _) = Parser::new(tokens).program().unwrap();
+        match TypeChecker::new().resolve(&mut program) {
+            Ok(()) => vec![],
+            Err(errors) => errors
+                .iter()
+                .map(|e| (e.span().as_ref().unwrap().line, e.message().clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_overloads() {
+        let source = "fn f(num a) -> num {\n    return a;\n}\nfn f(char c) -> num {\n    return f(2);\n}\n\
+                      fn g(num a) -> num {\n    return a;\n}\nfn g(char c) -> num {\n    return 0;\n}\n\
+                      fn main(){\n    num a = f('a') + f(1);\n    num b = g(true);\n}";
+        assert_eq!(
+            check(source),
+            vec![(
+                15,
+                "ambiguous call to \"g\", the arguments match : g(char) -> num and g(num) -> num"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_natives_can_not_be_redefined() {
+        let source = "fn print(char c) {\n}\nfn square(num a) -> num {\n    return a;\n}\nfn main(){\n}";
+        assert_eq!(
+            check(source),
+            vec![(1, "print(char) is already defined by a native function".to_string())]
+        );
+        let tokens = Scanner::new(source.to_string(), "test.lisaa".to_string())
+            .tokens()
+            .unwrap();
+        let (mut program, _) = Parser::new(tokens).program().unwrap();
+        let square = program.functions().values().find(|f| f.name == "square").unwrap().clone();
+        let mut checker = TypeChecker::new();
+        checker.add_function(FunctionDecl::from_sig("square".to_string(), square.signature().clone()));
+        let errors = checker.resolve(&mut program).unwrap_err();
+        let messages = errors.iter().map(|e| e.message().as_str()).collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "print(char) is already defined by a native function",
+                "square(num) -> num is already defined by a native function"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_all_errors() {
+        let source = "fn main(){\n    num a = b;\n    char c = 3;\n    print(a);\n}\n\
+                      fn other() -> num {\n    return d;\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (2, "Unknown variable : b".to_string()),
+                (3, "Expected : char, got : num".to_string()),
+                (7, "Unknown variable : d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditions_are_bool() {
+        let source = "fn main(){\n    num a = 1;\n    if a print('a');\n    while a > 0 && !true a = 0;\n\
+                      if !a print('b');\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (3, "Expected : bool, got : num".to_string()),
+                (5, "Operator '!' supported only for bool".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_values() {
+        let source = "fn f(num a) -> num {\n    return a;\n}\nfn main(){\n    fn(num) -> num g = f;\n\
+                      g(1, 2);\n    fn(char) -> num h = f;\n    num b = g(1);\n    b(2);\n\
+                      fn() -> num c = fn() -> num { return b + g(b); };\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (6, "the function value takes 1 arguments, got 2".to_string()),
+                (7, "Expected : fn(char) -> num, got : fn(num) -> num".to_string()),
+                (9, "Not a function : num".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_captured_variables_are_not_assigned() {
+        let source = "fn main(){\n    num calls = 0;\n    fn() count = fn() {\n        calls = calls + 1;\n\
+                      num local = calls;\n        local = local + 1;\n    };\n    calls = 2;\n}";
+        assert_eq!(
+            check(source),
+            vec![(4, "can not assign to calls, the closure only has a copy of it".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_no_cascading_errors() {
+        let source = "fn main(){\n    num a = (b + 1) * 2;\n    num c = a.foo(-b);\n    print(c);\n}";
+        assert_eq!(
+            check(source),
+            vec![
+                (2, "Unknown variable : b".to_string()),
+                (3, "Unknown variable : b".to_string()),
+            ]
+        );
+    }
+}
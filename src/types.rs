@@ -14,12 +14,20 @@ pub enum LisaaType {
     Num,
     /// A Char (heap/stack too)
     Char,
+    /// A boolean, true or false. Stored as a number (1 or 0).
+    Bool,
     /// Nothing.
     Void,
     /// Any type
     Any,
     /// A method, represented by the caller :: the type :: the name.
     Function(Box<LisaaType>, String, Vec<LisaaType>),
+    /// A function value : the types of the arguments and the return type.
+    /// Written fn(num, num) -> num in the code.
+    FunctionPointer(Vec<LisaaType>, Box<LisaaType>),
+    /// The type of an expression that failed to typecheck.
+    /// It matches every type so the error is reported only once.
+    Error,
 }
 
 impl LisaaType {
@@ -31,6 +39,10 @@ impl LisaaType {
     pub fn slice(inner: LisaaType) -> Self {
         LisaaType::Class("slice".to_string(), vec![inner])
     }
+    /// Creates the type of a function value taking the given arguments.
+    pub fn function_pointer(args: Vec<LisaaType>, ret_type: LisaaType) -> Self {
+        LisaaType::FunctionPointer(args, Box::new(ret_type))
+    }
 
     pub fn function_name(&self) -> Result<String, ()> {
         match self {
@@ -43,12 +55,15 @@ impl LisaaType {
     pub fn name(&self) -> String {
         match self {
             &LisaaType::Char => "char".to_string(),
+            &LisaaType::Bool => "bool".to_string(),
             &LisaaType::Num => "num".to_string(),
             &LisaaType::Void => "void".to_string(),
             &LisaaType::Pointer(ref p) => format!("&{}", p),
             &LisaaType::Any => "any".to_string(),
+            &LisaaType::Error => "error".to_string(),
             &LisaaType::Class(ref c, ref t) => c.to_string(),
             &LisaaType::Function(_, ref s, _) => s.to_string(),
+            &LisaaType::FunctionPointer(..) => "fn".to_string(),
         }
     }
 
@@ -60,6 +75,17 @@ impl LisaaType {
             _ => Err(()),
         }
     }
+    /// Checks if the values of this type are pointers into the heap.
+    pub fn is_pointer_type(&self) -> bool {
+        match self {
+            &LisaaType::Num | &LisaaType::Char | &LisaaType::Bool => false,
+            _ => true,
+        }
+    }
+    /// Checks if the type comes from an expression that failed to typecheck.
+    pub fn is_error(&self) -> bool {
+        self.max_deref().0 == LisaaType::Error
+    }
     /// Dereferences if it is a pointer until it is not a pointer anymore
     /// Returns its type + the number of derefs
     pub fn max_deref(&self) -> (Self, usize) {
@@ -76,6 +102,8 @@ impl LisaaType {
             return true;
         } else if let (_, &LisaaType::Any) = (self, other) {
             return true;
+        } else if self.is_error() || other.is_error() {
+            return true;
         } else {
             return self == other;
         }
@@ -99,6 +127,11 @@ impl LisaaType {
                 format!("num::{}", name),
                 vec![],
             )),
+            &LisaaType::Bool => Ok(LisaaType::Function(
+                Box::new(self.clone()),
+                format!("bool::{}", name),
+                vec![],
+            )),
             &LisaaType::Class(ref s, ref t) => Ok(LisaaType::pointer(match classes.get(s) {
                 Some(class) => match class.get_attr(name) {
                     Some(decl) => Ok(Self::morphise_attr(decl.val_type(), class, t)),
@@ -144,7 +177,6 @@ impl LisaaType {
         attr: &String,
         functions: &HashMap<FunctionSig, FunctionDecl>,
     ) -> Result<LisaaType, String> {
-        println!("\n\n\n\nfunctions : {:?}", functions.iter().map(|(s, d)|s.name()).collect::<Vec<&String>>());
         match functions
             .iter()
             .find(|(func, _)| func.name() == &format!("{}::{}", class, attr))
@@ -173,6 +205,7 @@ impl LisaaType {
         match self {
             &LisaaType::Num => vec![],
             &LisaaType::Char => vec![],
+            &LisaaType::Bool => vec![],
             &LisaaType::Class(_, ref t) => t.clone(),
             &LisaaType::Pointer(ref p) => p.type_args(),
             _ => panic!("ok im out "),
@@ -196,12 +229,22 @@ impl fmt::Display for LisaaType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &LisaaType::Char => write!(f, "char"),
+            &LisaaType::Bool => write!(f, "bool"),
             &LisaaType::Num => write!(f, "num"),
             &LisaaType::Void => write!(f, "void"),
             &LisaaType::Pointer(ref p) => write!(f, "&{}", p),
             &LisaaType::Any => write!(f, "any"),
+            &LisaaType::Error => write!(f, "error"),
             &LisaaType::Class(ref c, ref t) => write!(f, "class {}<{}>", c, t.iter().map(|i|format!("{}", i)).collect::<Vec<String>>().join(", ").replace("class ", "").replace("<>", "")),
             &LisaaType::Function(_, ref str, _) => write!(f, "{}", str),
+            &LisaaType::FunctionPointer(ref args, ref ret) => {
+                let args = args.iter().map(|a| format!("{}", a)).collect::<Vec<String>>();
+                let ret = match **ret {
+                    LisaaType::Void => String::new(),
+                    ref t => format!(" -> {}", t),
+                };
+                write!(f, "{}", format!("fn({}){}", args.join(", "), ret).replace("class ", "").replace("<>", ""))
+            }
         }
     }
 }
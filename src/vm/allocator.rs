@@ -38,7 +38,10 @@
 /// !!! !! !!!! ! Important (or not) the heap cannot take more than 2**52 bytes in memory. maybe
 /// this will be important in 2030
 ///
+use super::RuntimeErrorKind;
 use std::collections::BinaryHeap;
+/// The number of allocations between two garbage collections.
+pub const GC_INTERVAL: usize = 64;
 pub const MAX_HEAP_SIZE: u64 = 4503599627370496; //2.0f64.powf(52.0);
 pub const MAX_HEAP_SIZE_F64: f64 = 4503599627370496.0;
 pub const IS_SLICE_BIT: u64 = 0b1000_0000_0000_0000_0000_0000_0000_0000;
@@ -64,6 +67,14 @@ impl ObjectType {
             ObjectType::object(type_bits)
         }
     }
+    /// Parses the type of an object, None if the bits do not describe one :
+    /// an object needs at least the bit giving its size.
+    pub fn parse(type_bits: u64) -> Option<ObjectType> {
+        match type_bits {
+            0 => None,
+            _ => Some(ObjectType::new(type_bits)),
+        }
+    }
     /// Reads the bytes into an array, turn it into a vector of bools then
     pub fn object(type_bits: u64) -> ObjectType {
         let mut bits = (0..64)
@@ -95,6 +106,8 @@ impl ObjectType {
 pub struct Allocator {
     first_hole: f64,
     heap: Vec<f64>,
+    /// The number of allocations since the last garbage collection.
+    allocations: usize,
 }
 
 impl Allocator {
@@ -104,6 +117,7 @@ impl Allocator {
         Allocator {
             first_hole: 0.0,
             heap: vec![0.0, MAX_HEAP_SIZE_F64],
+            allocations: 0,
         }
     }
     /// Allocate some memory of the required size.
@@ -117,6 +131,7 @@ impl Allocator {
         if size == 0 {
             return 0;
         }
+        self.allocations += 1;
         let mut prev_hole = None;
         let mut next_hole = self.first_hole;
         while !(self.heap[next_hole as usize + 1] == (size as f64 + 1.0)
@@ -124,7 +139,6 @@ impl Allocator {
         {
             prev_hole = Some(next_hole);
             next_hole = self.heap[next_hole as usize];
-            println!("next hole {}", next_hole);
         }
         if self.heap[next_hole as usize + 1] == MAX_HEAP_SIZE_F64 {
             self.extend_heap(size + 1);
@@ -142,7 +156,6 @@ impl Allocator {
     }
     /// Frees an object in the heap.
     /// Creates a hole and link it.
-    #[allow(dead_code)]
     pub fn free(&mut self, position: usize, size: usize) {
         // If the first hole is at the end. (no fragmentation at all)
         self.heap[position - 1] = self.first_hole as f64;
@@ -195,17 +208,30 @@ impl Allocator {
     }
 
     /// Sets the given pointer at the given adress
-    pub fn set_ptr(&mut self, adress: usize, value: f64) {
-        if adress > self.heap.len() - 1 {
-            println!("Segmentation fault (Core dumped)");
-            panic!("Program exited");
+    pub fn set_ptr(&mut self, adress: usize, value: f64) -> Result<(), RuntimeErrorKind> {
+        match self.heap.get_mut(adress) {
+            Some(v) => Ok(*v = value),
+            None => Err(RuntimeErrorKind::HeapOutOfRange(adress)),
         }
-        self.heap[adress] = value;
     }
 
     /// Get value at address
-    pub fn get_heap(&self, adress: usize) -> f64 {
-        self.heap[adress]
+    pub fn get_heap(&self, adress: usize) -> Result<f64, RuntimeErrorKind> {
+        self.heap
+            .get(adress)
+            .cloned()
+            .ok_or(RuntimeErrorKind::HeapOutOfRange(adress))
+    }
+    /// Returns the number of elements of the slice at the given adress.
+    /// Empty slices are not allocated, their adress is 0.
+    pub fn slice_len(&self, adress: usize) -> Result<usize, RuntimeErrorKind> {
+        if adress == 0 {
+            return Ok(0);
+        }
+        match ObjectType::new(self.get_heap(adress - 1)?.to_bits()) {
+            ObjectType::Slice(_, size) => Ok(size as usize),
+            ObjectType::Object(..) => Err(RuntimeErrorKind::NotASlice(adress)),
+        }
     }
     /// returns the heap
     /// Everything is casted to an u64
@@ -276,11 +302,63 @@ impl Allocator {
         holes.into_sorted_vec()
     }
 
-    /// given a vec of references to objects,
-    /// Runs garbage collection
-    pub fn run_gc(&mut self) {
-        //let allocated = self.get_allocated();
+    /// Runs the garbage collection if enough allocations were made since the last one.
+    /// The roots are the values of the stack that may reference objects in the heap.
+    pub fn run_gc(&mut self, roots: &[f64]) {
+        if self.allocations >= GC_INTERVAL {
+            self.collect(roots);
+        }
+    }
+
+    /// Mark and sweep :
+    /// Marks every object reachable from the roots, following the pointers given by the object
+    /// types, then frees every object that was not marked.
+    pub fn collect(&mut self, roots: &[f64]) {
+        self.allocations = 0;
+        let objects = self.get_objects();
+        let mut marked = vec![false; objects.len()];
+        let mut to_visit = roots
+            .iter()
+            .filter_map(|r| self.find_object(&objects, *r))
+            .collect::<Vec<usize>>();
+        while let Some(id) = to_visit.pop() {
+            if marked[id] {
+                continue;
+            }
+            marked[id] = true;
+            let position = objects[id];
+            let obj = ObjectType::new(f64::to_bits(self.heap[position]));
+            for ptr in obj.get_pointers() {
+                if let Some(next) = self.find_object(&objects, self.heap[position + 1 + ptr]) {
+                    to_visit.push(next);
+                }
+            }
+        }
+        for (id, position) in objects.into_iter().enumerate() {
+            if !marked[id] {
+                let size = ObjectType::new(f64::to_bits(self.heap[position])).get_size();
+                self.free(position + 1, size + 1);
+            }
+        }
+    }
 
+    /// Finds the object containing the given adress.
+    /// Returns its index in the given list of objects positions (sorted).
+    /// Values that can not be adresses (numbers stored in the same place as pointers) are ignored.
+    fn find_object(&self, objects: &Vec<usize>, value: f64) -> Option<usize> {
+        if value < 1.0 || value.fract() != 0.0 || value >= self.heap.len() as f64 {
+            return None;
+        }
+        let adress = value as usize;
+        let id = match objects.binary_search(&adress) {
+            Ok(_) | Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let obj = ObjectType::new(f64::to_bits(self.heap[objects[id]]));
+        match adress <= objects[id] + obj.get_size() {
+            true => Some(id),
+            false => None,
+        }
     }
 }
 
@@ -399,4 +477,43 @@ mod tests_allocator {
         alloc.alloc(3, 8);
         assert_eq!(alloc.heap(), vec![7, 3, 0, 8, 4, 0, 0, 0, MAX_HEAP_SIZE]);
     }
+
+    #[test]
+    fn test_gc_frees_unreachable() {
+        let mut alloc = Allocator::new();
+        let first = alloc.alloc(3, 8);
+        let sec = alloc.alloc(2, 4);
+        alloc.collect(&[sec as f64]);
+        assert_eq!(
+            alloc.heap(),
+            vec![7, 4, 0, 0, 4, MAX_HEAP_SIZE, 0, 0, MAX_HEAP_SIZE]
+        );
+        assert_eq!(alloc.alloc(3, 8), first);
+    }
+
+    #[test]
+    fn test_gc_follows_pointers() {
+        let mut alloc = Allocator::new();
+        // object of size 1 whose only attribute is a pointer.
+        let outer = alloc.alloc(1, 3);
+        let inner = alloc.alloc(2, IS_SLICE_BIT + IS_PTR_SLICE_BIT + 2);
+        let leaf = alloc.alloc(1, 2);
+        let garbage = alloc.alloc(1, 2);
+        alloc.set_ptr(outer, inner as f64).unwrap();
+        alloc.set_ptr(inner + 1, leaf as f64).unwrap();
+        alloc.collect(&[outer as f64, 12.5, -3.0]);
+        let objects = alloc.get_objects();
+        assert_eq!(objects, vec![outer - 1, inner - 1, leaf - 1]);
+        assert_eq!(alloc.get_holes()[0], garbage - 1);
+    }
+
+    #[test]
+    fn test_gc_keeps_interior_pointers() {
+        let mut alloc = Allocator::new();
+        let slice = alloc.alloc(3, IS_SLICE_BIT + 3);
+        alloc.collect(&[(slice + 2) as f64]);
+        assert_eq!(alloc.get_objects(), vec![slice - 1]);
+        alloc.collect(&[]);
+        assert_eq!(alloc.get_objects(), vec![]);
+    }
 }
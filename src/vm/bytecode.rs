@@ -0,0 +1,283 @@
+//! The binary encoding of compiled programs, stored in .lisaac files.
+//!
+//! A file starts with the magic bytes `LISAAC`, the version of the format (u16)
+//! and the number of instructions (u64).
+//! Then each instruction is a one byte opcode followed by its operand if it has one.
+//! All numbers are little endian, floats are stored as their bits.
+//! A decoded program is checked like the compiler writes it : the jumps and the frames go to
+//! instructions of the program, and each AllocObj follows the PushNum of its size.
+use super::allocator::ObjectType;
+use super::OP;
+
+/// The bytes every compiled file starts with.
+pub const MAGIC: &[u8] = b"LISAAC";
+/// The version of the encoding, to be increased whenever an opcode changes.
+pub const VERSION: u16 = 5;
+
+/// Encodes the program.
+pub fn encode(program: &[OP]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(program.len() as u64).to_le_bytes());
+    for op in program {
+        let (code, operand) = match op {
+            &OP::End => (0, None),
+            &OP::Goto(u) => (1, Some(u as u64)),
+            &OP::Pop => (2, None),
+            &OP::PopN(u) => (3, Some(u as u64)),
+            &OP::SetOffset => (4, None),
+            &OP::OffsetToTop(u) => (5, Some(u as u64)),
+            &OP::PushOffset => (6, None),
+            &OP::GotoTop => (7, None),
+            &OP::Inv => (8, None),
+            &OP::Mul => (9, None),
+            &OP::Mod => (10, None),
+            &OP::GreaterThan => (11, None),
+            &OP::GreaterEq => (12, None),
+            &OP::Eq => (13, None),
+            &OP::Not => (14, None),
+            &OP::Or => (15, None),
+            &OP::OrOr => (16, None),
+            &OP::AndAnd => (17, None),
+            &OP::And => (18, None),
+            &OP::LowerThan => (19, None),
+            &OP::LowerEq => (20, None),
+            &OP::Swap2 => (21, None),
+            &OP::Bring(u) => (22, Some(u as u64)),
+            &OP::Set(u) => (23, Some(u as u64)),
+            &OP::Neg => (24, None),
+            &OP::Add => (25, None),
+            &OP::AllocObj(descriptor) => (26, Some(descriptor)),
+            &OP::AllocSlice => (27, None),
+            &OP::GetHeap => (28, None),
+            &OP::SetHeap => (29, None),
+            &OP::JMPIf => (30, None),
+            &OP::PushNum(n) => (31, Some(n.to_bits())),
+            &OP::PushCopy => (32, None),
+            &OP::PrintChar => (33, None),
+            &OP::ToStr => (34, None),
+            &OP::RandNum => (35, None),
+            &OP::SliceLen => (36, None),
+            &OP::CheckIndex => (37, None),
+            &OP::CallIndirect(u) => (38, Some(u as u64)),
+            &OP::CallNative(u) => (39, Some(u as u64)),
+            &OP::PushFrame(u) => (40, Some(u as u64)),
+            &OP::Return(u) => (41, Some(u as u64)),
+            &OP::PushAdd(n) => (42, Some(n.to_bits())),
+            &OP::PushMul(n) => (43, Some(n.to_bits())),
+            &OP::BringAdd(u) => (44, Some(u as u64)),
+            &OP::BringMul(u) => (45, Some(u as u64)),
+        };
+        bytes.push(code);
+        if let Some(operand) = operand {
+            bytes.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Decodes a program, checking the header.
+pub fn decode(bytes: &[u8]) -> Result<Vec<OP>, String> {
+    let mut reader = Reader {
+        bytes: bytes,
+        position: 0,
+    };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not a compiled lisaa file".to_string());
+    }
+    let mut version = [0; 2];
+    version.copy_from_slice(reader.take(2)?);
+    let version = u16::from_le_bytes(version);
+    if version != VERSION {
+        return Err(format!(
+            "unsupported bytecode version : {}, expected : {}",
+            version, VERSION
+        ));
+    }
+    let len = reader.operand()? as usize;
+    let mut program = Vec::with_capacity(len.min(bytes.len()));
+    for _ in 0..len {
+        let code = reader.take(1)?[0];
+        program.push(match code {
+            0 => OP::End,
+            1 => OP::Goto(reader.operand()? as usize),
+            2 => OP::Pop,
+            3 => OP::PopN(reader.operand()? as usize),
+            4 => OP::SetOffset,
+            5 => OP::OffsetToTop(reader.operand()? as usize),
+            6 => OP::PushOffset,
+            7 => OP::GotoTop,
+            8 => OP::Inv,
+            9 => OP::Mul,
+            10 => OP::Mod,
+            11 => OP::GreaterThan,
+            12 => OP::GreaterEq,
+            13 => OP::Eq,
+            14 => OP::Not,
+            15 => OP::Or,
+            16 => OP::OrOr,
+            17 => OP::AndAnd,
+            18 => OP::And,
+            19 => OP::LowerThan,
+            20 => OP::LowerEq,
+            21 => OP::Swap2,
+            22 => OP::Bring(reader.operand()? as usize),
+            23 => OP::Set(reader.operand()? as usize),
+            24 => OP::Neg,
+            25 => OP::Add,
+            26 => OP::AllocObj(reader.operand()?),
+            27 => OP::AllocSlice,
+            28 => OP::GetHeap,
+            29 => OP::SetHeap,
+            30 => OP::JMPIf,
+            31 => OP::PushNum(f64::from_bits(reader.operand()?)),
+            32 => OP::PushCopy,
+            33 => OP::PrintChar,
+            34 => OP::ToStr,
+            35 => OP::RandNum,
+            36 => OP::SliceLen,
+            37 => OP::CheckIndex,
+            38 => OP::CallIndirect(reader.operand()? as usize),
+            39 => OP::CallNative(reader.operand()? as usize),
+            40 => OP::PushFrame(reader.operand()? as usize),
+            41 => OP::Return(reader.operand()? as usize),
+            42 => OP::PushAdd(f64::from_bits(reader.operand()?)),
+            43 => OP::PushMul(f64::from_bits(reader.operand()?)),
+            44 => OP::BringAdd(reader.operand()? as usize),
+            45 => OP::BringMul(reader.operand()? as usize),
+            c => return Err(format!("unknown opcode {} at byte {}", c, reader.position - 1)),
+        });
+    }
+    if reader.position != bytes.len() {
+        return Err("unexpected bytes after the end of the program".to_string());
+    }
+    check(&program)?;
+    Ok(program)
+}
+
+/// Checks the operands that would make the vm jump outside of the program
+/// or allocate objects whose type does not match their size.
+fn check(program: &[OP]) -> Result<(), String> {
+    for (i, op) in program.iter().enumerate() {
+        match op {
+            &OP::Goto(target) | &OP::PushFrame(target) if target >= program.len() => {
+                return Err(format!("instruction {} goes to {}, outside of the program", i, target));
+            }
+            &OP::AllocObj(descriptor) => {
+                let size = match i.checked_sub(1).map(|previous| &program[previous]) {
+                    Some(&OP::PushNum(size)) => size,
+                    _ => return Err(format!("instruction {} allocates an unknown size", i)),
+                };
+                match ObjectType::parse(descriptor) {
+                    Some(ref object) if object.get_size() as f64 == size => (),
+                    _ => {
+                        return Err(format!(
+                            "instruction {} allocates {} values with the descriptor {}",
+                            i, size, descriptor
+                        ))
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// A cursor over the encoded bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Takes the next n bytes.
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < n {
+            return Err("unexpected end of compiled file".to_string());
+        }
+        self.position += n;
+        Ok(&self.bytes[self.position - n..self.position])
+    }
+
+    /// Reads an eight bytes operand.
+    fn operand(&mut self) -> Result<u64, String> {
+        let mut operand = [0; 8];
+        operand.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(operand))
+    }
+}
+
+#[cfg(test)]
+mod tests_bytecode {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let program = vec![
+            OP::PushNum(-2.5),
+            OP::PushNum(3.0),
+            OP::Add,
+            OP::PushNum(2.0),
+            OP::AllocObj(6),
+            OP::Bring(2),
+            OP::Set(1),
+            OP::Goto(10),
+            OP::PrintChar,
+            OP::PopN(3),
+            OP::CheckIndex,
+            OP::CallIndirect(4),
+            OP::CallNative(1),
+            OP::PushFrame(15),
+            OP::PushAdd(0.5),
+            OP::BringMul(3),
+            OP::Return(2),
+            OP::End,
+        ];
+        assert_eq!(decode(&encode(&program)), Ok(program));
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let mut bytes = encode(&[OP::End]);
+        bytes[MAGIC.len()] += 1;
+        assert_eq!(
+            decode(&bytes),
+            Err("unsupported bytecode version : 6, expected : 5".to_string())
+        );
+        assert_eq!(
+            decode(b"#!/bin/sh"),
+            Err("not a compiled lisaa file".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_programs() {
+        let outside = encode(&[OP::Goto(2), OP::End]);
+        assert_eq!(
+            decode(&outside),
+            Err("instruction 0 goes to 2, outside of the program".to_string())
+        );
+        assert!(decode(&encode(&[OP::PushFrame(usize::max_value())])).is_err());
+        for alloc in [
+            vec![OP::PushNum(1.0), OP::AllocObj(6)],
+            vec![OP::PushNum(0.0), OP::AllocObj(0)],
+            vec![OP::Add, OP::AllocObj(4)],
+            vec![OP::AllocObj(1)],
+        ]
+        .iter()
+        {
+            assert!(decode(&encode(alloc)).is_err());
+        }
+        assert!(decode(&encode(&[OP::PushNum(0.0), OP::AllocObj(1)])).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = encode(&[OP::PushNum(1.0), OP::End]);
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 3]),
+            Err("unexpected end of compiled file".to_string())
+        );
+    }
+}
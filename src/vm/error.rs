@@ -0,0 +1,112 @@
+//! The errors that can happen while running a program.
+use std::fmt;
+
+/// What went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+    /// An instruction needed more values than the stack contains.
+    StackUnderflow,
+    /// An access to an adress outside of the heap.
+    HeapOutOfRange(usize),
+    /// A value printed as a char that is not a valid unicode char.
+    InvalidChar(f64),
+    /// An index outside of a slice, with the index and the length of the slice.
+    IndexOutOfBounds(f64, usize),
+    /// A slice operation on an adress that does not hold a slice.
+    NotASlice(usize),
+    /// An allocation whose size is negative, not an integer or too big.
+    InvalidSize(f64),
+    /// An allocation whose descriptor is not a type or does not have the size allocated.
+    InvalidDescriptor(u64, usize),
+    /// A frame offset outside of the stack.
+    InvalidOffset(f64),
+    /// A call of a value that is not a function.
+    NotAFunction(usize),
+    /// A native function failed, with its name and its error.
+    Native(String, String),
+    /// A call of a native function that was not registered, with its id.
+    UnknownNative(usize),
+    /// A modulo by zero.
+    DivisionByZero,
+    /// The output stream could not be written to.
+    Output(String),
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RuntimeErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            &RuntimeErrorKind::HeapOutOfRange(adress) => {
+                write!(f, "heap access out of range at adress {}", adress)
+            }
+            &RuntimeErrorKind::InvalidChar(value) => write!(f, "invalid char : {}", value),
+            &RuntimeErrorKind::IndexOutOfBounds(index, len) => write!(
+                f,
+                "index out of bounds : the len is {} but the index is {}",
+                len, index
+            ),
+            &RuntimeErrorKind::NotASlice(adress) => write!(f, "no slice at adress {}", adress),
+            &RuntimeErrorKind::InvalidSize(size) => write!(f, "invalid allocation size : {}", size),
+            &RuntimeErrorKind::InvalidDescriptor(descriptor, size) => write!(
+                f,
+                "invalid object descriptor {} for the size {}",
+                descriptor, size
+            ),
+            &RuntimeErrorKind::InvalidOffset(offset) => write!(f, "invalid stack offset : {}", offset),
+            &RuntimeErrorKind::NotAFunction(adress) => {
+                write!(f, "no function value at adress {}", adress)
+            }
+            &RuntimeErrorKind::Native(ref name, ref e) => {
+                write!(f, "native function {} failed : {}", name, e)
+            }
+            &RuntimeErrorKind::UnknownNative(id) => write!(f, "unknown native function {}", id),
+            &RuntimeErrorKind::DivisionByZero => write!(f, "division by zero"),
+            &RuntimeErrorKind::Output(ref e) => write!(f, "could not write the output : {}", e),
+        }
+    }
+}
+
+/// An error stopping the execution, with the place where it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    /// What went wrong.
+    pub kind: RuntimeErrorKind,
+    /// The position of the failing instruction.
+    pub instruction: usize,
+    /// The signature of the function containing the instruction, if the vm has the symbols.
+    pub function: Option<String>,
+    /// The functions being executed, the failing one first.
+    pub backtrace: Vec<Frame>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at instruction {}", self.kind, self.instruction)?;
+        match self.function {
+            Some(ref function) => write!(f, " in {}", function),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A function being executed when the error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The signature of the function.
+    pub function: String,
+    /// The file of the line being executed in this function, if known.
+    pub file: Option<String>,
+    /// The line being executed in this function, if known.
+    pub line: Option<usize>,
+}
+
+/// Shows the frame with the name of the function : at add (<stdlib>/string.lisaa:27)
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.function.split('(').next().unwrap_or("");
+        match (&self.file, self.line) {
+            (&Some(ref file), Some(line)) => write!(f, "at {} ({}:{})", name, file, line),
+            _ => write!(f, "at {}", name),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! The functions of the host program callable from lisaa, see Lisaa::register.
+//!
+//! The vm converts the arguments from the stack and the heap to native values,
+//! calls the function and converts its result back, allocating the strings and slices.
+use super::allocator::Allocator;
+use super::error::RuntimeErrorKind;
+use super::{IS_PTR_SLICE_BIT, IS_SLICE_BIT, STRING_TYPE};
+use std::char;
+use std::fmt;
+
+/// The types of the values given to and returned by native functions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NativeType {
+    /// A number.
+    Num,
+    /// A char.
+    Char,
+    /// A bool.
+    Bool,
+    /// A String object, defined in lib/string.lisaa.
+    String,
+    /// A slice of values of the given type.
+    Slice(Box<NativeType>),
+    /// No value, only as a return type.
+    Void,
+}
+
+/// A value given to or returned by a native function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NativeValue {
+    /// A number.
+    Num(f64),
+    /// A char.
+    Char(char),
+    /// A bool.
+    Bool(bool),
+    /// The content of a String.
+    String(String),
+    /// The elements of a slice.
+    Slice(Vec<NativeValue>),
+    /// No value.
+    Void,
+}
+
+/// A Rust function with its lisaa signature.
+pub struct NativeFunction {
+    /// The name used to call the function in the code.
+    pub name: String,
+    /// The types of the arguments.
+    pub args: Vec<NativeType>,
+    /// The return type.
+    pub ret: NativeType,
+    function: Box<FnMut(&[NativeValue]) -> Result<NativeValue, String>>,
+}
+
+impl NativeFunction {
+    /// Creates a native function from its signature and its code.
+    pub fn new(
+        name: String,
+        args: Vec<NativeType>,
+        ret: NativeType,
+        function: Box<FnMut(&[NativeValue]) -> Result<NativeValue, String>>,
+    ) -> Self {
+        NativeFunction {
+            name: name,
+            args: args,
+            ret: ret,
+            function: function,
+        }
+    }
+
+    /// Calls the function, checking that it returns a value of its return type.
+    pub fn call(&mut self, args: &[NativeValue]) -> Result<NativeValue, RuntimeErrorKind> {
+        let result = (self.function)(args)
+            .map_err(|e| RuntimeErrorKind::Native(self.name.clone(), e))?;
+        match result.is_of_type(&self.ret) {
+            true => Ok(result),
+            false => Err(RuntimeErrorKind::Native(
+                self.name.clone(),
+                format!("returned {:?} instead of a value of type {}", result, self.ret),
+            )),
+        }
+    }
+}
+
+impl NativeType {
+    /// Checks if the values of this type are adresses in the heap.
+    pub fn is_pointer(&self) -> bool {
+        match self {
+            &NativeType::String | &NativeType::Slice(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Reads the value of this type from its value on the stack.
+    pub fn read(&self, value: f64, allocator: &Allocator) -> Result<NativeValue, RuntimeErrorKind> {
+        Ok(match self {
+            &NativeType::Num => NativeValue::Num(value),
+            &NativeType::Char => NativeValue::Char(
+                char::from_u32(value as u32)
+                    .filter(|_| value >= 0.0)
+                    .ok_or(RuntimeErrorKind::InvalidChar(value))?,
+            ),
+            &NativeType::Bool => NativeValue::Bool(value != 0.0),
+            &NativeType::String => {
+                let adress = value as usize;
+                let len = allocator.get_heap(adress)? as usize;
+                let chars = allocator.get_heap(adress + 1)?;
+                let mut string = String::new();
+                for i in 0..len {
+                    let ch = allocator.get_heap(chars as usize + i)?;
+                    match NativeType::Char.read(ch, allocator)? {
+                        NativeValue::Char(ch) => string.push(ch),
+                        _ => unreachable!(),
+                    }
+                }
+                NativeValue::String(string)
+            }
+            &NativeType::Slice(ref inner) => {
+                let adress = value as usize;
+                let mut elements = vec![];
+                for i in 0..allocator.slice_len(adress)? {
+                    elements.push(inner.read(allocator.get_heap(adress + i)?, allocator)?);
+                }
+                NativeValue::Slice(elements)
+            }
+            &NativeType::Void => NativeValue::Void,
+        })
+    }
+}
+
+/// Shows the type like in the lisaa code : slice<String>
+impl fmt::Display for NativeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &NativeType::Num => write!(f, "num"),
+            &NativeType::Char => write!(f, "char"),
+            &NativeType::Bool => write!(f, "bool"),
+            &NativeType::String => write!(f, "String"),
+            &NativeType::Slice(ref inner) => write!(f, "slice<{}>", inner),
+            &NativeType::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl NativeValue {
+    /// Checks if the value can be returned as the given type.
+    pub fn is_of_type(&self, native_type: &NativeType) -> bool {
+        match (self, native_type) {
+            (&NativeValue::Num(_), &NativeType::Num)
+            | (&NativeValue::Char(_), &NativeType::Char)
+            | (&NativeValue::Bool(_), &NativeType::Bool)
+            | (&NativeValue::String(_), &NativeType::String)
+            | (&NativeValue::Void, &NativeType::Void) => true,
+            (&NativeValue::Slice(ref elements), &NativeType::Slice(ref inner)) => {
+                elements.iter().all(|e| e.is_of_type(inner))
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes the value, allocating the strings and slices in the heap.
+    /// Returns the value to push on the stack, void is 0 like the other functions.
+    pub fn write(&self, allocator: &mut Allocator) -> Result<f64, RuntimeErrorKind> {
+        Ok(match self {
+            &NativeValue::Num(n) => n,
+            &NativeValue::Char(ch) => ch as u32 as f64,
+            &NativeValue::Bool(b) => b as i32 as f64,
+            &NativeValue::String(ref s) => {
+                let chars = s
+                    .chars()
+                    .map(NativeValue::Char)
+                    .collect::<Vec<NativeValue>>();
+                let slice = NativeValue::Slice(chars).write(allocator)?;
+                let adress = allocator.alloc(2, STRING_TYPE);
+                allocator.set_ptr(adress, s.chars().count() as f64)?;
+                allocator.set_ptr(adress + 1, slice)?;
+                adress as f64
+            }
+            &NativeValue::Slice(ref elements) => {
+                let pointers = match elements.first() {
+                    Some(&NativeValue::String(_)) | Some(&NativeValue::Slice(_)) => IS_PTR_SLICE_BIT,
+                    _ => 0,
+                };
+                let values = elements
+                    .iter()
+                    .map(|e| e.write(allocator))
+                    .collect::<Result<Vec<f64>, RuntimeErrorKind>>()?;
+                let adress = allocator.alloc(
+                    values.len(),
+                    IS_SLICE_BIT + pointers + values.len() as u64,
+                );
+                for (i, value) in values.into_iter().enumerate() {
+                    allocator.set_ptr(adress + i, value)?;
+                }
+                adress as f64
+            }
+            &NativeValue::Void => 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_ffi {
+    use super::*;
+
+    #[test]
+    fn test_write_then_read() {
+        let mut allocator = Allocator::new();
+        let words = NativeValue::Slice(vec![
+            NativeValue::String("héllo".to_string()),
+            NativeValue::String(String::new()),
+        ]);
+        let words_type = NativeType::Slice(Box::new(NativeType::String));
+        let adress = words.write(&mut allocator).unwrap();
+        assert_eq!(words_type.read(adress, &allocator), Ok(words));
+        let empty = NativeValue::Slice(vec![]);
+        let adress = empty.write(&mut allocator).unwrap();
+        assert_eq!(adress, 0.0);
+        assert_eq!(words_type.read(adress, &allocator), Ok(empty));
+        assert_eq!(NativeType::Char.read(-1.0, &allocator), Err(RuntimeErrorKind::InvalidChar(-1.0)));
+    }
+
+    #[test]
+    fn test_checks_the_result() {
+        let mut half = NativeFunction::new(
+            "half".to_string(),
+            vec![NativeType::Num],
+            NativeType::Num,
+            Box::new(|args: &[NativeValue]| match args[0] {
+                NativeValue::Num(n) if n >= 0.0 => Ok(NativeValue::Num(n / 2.0)),
+                _ => Ok(NativeValue::Bool(false)),
+            }),
+        );
+        assert_eq!(half.call(&[NativeValue::Num(3.0)]), Ok(NativeValue::Num(1.5)));
+        assert_eq!(
+            half.call(&[NativeValue::Num(-3.0)]).unwrap_err().to_string(),
+            "native function half failed : returned Bool(false) instead of a value of type num"
+        );
+    }
+}
@@ -4,12 +4,20 @@ use std::char;
 use std::io::{self, Write};
 //mod gc;
 mod allocator;
-use self::allocator::Allocator;
+pub mod bytecode;
+mod error;
+pub mod ffi;
+pub mod peephole;
+pub mod trace;
+use self::allocator::{Allocator, ObjectType};
 pub use self::allocator::{IS_PTR_SLICE_BIT, IS_SLICE_BIT};
+pub use self::error::{Frame, RuntimeError, RuntimeErrorKind};
+use self::ffi::{NativeFunction, NativeType, NativeValue};
+use self::trace::Trace;
 use std::collections::BTreeSet;
 pub const STRING_TYPE: u64 = 6; // size 2 -> 4 + pointer in position 1 -> 2 = 6
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum OP {
     /// This is the end of the program.
     End,
@@ -24,6 +32,13 @@ pub enum OP {
     PushOffset,
     /// Jumps to the instruction with the same value as the top of the stack
     GotoTop,
+    /// Calls the function value at the top of the stack, it replaces the value by its captures.
+    /// The stack offset is set like OffsetToTop, counting the captures too :
+    /// Ret | Ins | Off | Args ... | Captures ...
+    CallIndirect(usize),
+    /// Calls the registered native function with the given id.
+    /// Its arguments at the top of the stack are replaced by its result.
+    CallNative(usize),
     Inv,
     Mul,
     Mod,
@@ -54,6 +69,11 @@ pub enum OP {
     AllocObj(u64),
     /// Allocates a slice, temporary... TODO -> it should use two arguments.
     AllocSlice,
+    /// Checks that the index at the top of the stack is inside the slice just below it.
+    /// Leaves both on the stack.
+    CheckIndex,
+    /// Replaces the slice at the top of the stack by its length.
+    SliceLen,
     /// Access the value in the heap and push it to the stack.
     GetHeap,
     /// Take the top of the stack as an adress to the heap and set its value to the second value in
@@ -66,10 +86,54 @@ pub enum OP {
     PrintChar,
     ToStr,
     RandNum,
+    /// Pushes the frame of a call : the return value, the given return adress and the offset.
+    /// Does what PushOffset PushNum(0) Swap2 PushNum(adress) Swap2 do.
+    PushFrame(usize),
+    /// Pops the given number of values then returns from the function, see PopN SetOffset GotoTop.
+    Return(usize),
+    /// Adds the number to the top of the stack, like PushNum(n) Add.
+    PushAdd(f64),
+    /// Multiplies the top of the stack by the number, like PushNum(n) Mul.
+    PushMul(f64),
+    /// Adds the local variable to the top of the stack, like Bring(n) Add.
+    BringAdd(usize),
+    /// Multiplies the top of the stack by the local variable, like Bring(n) Mul.
+    BringMul(usize),
+}
+
+/// A compiled function and the instructions it occupies, from start (included) to end (excluded).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    /// The monomorphised signature of the function, eg : String::add(String) -> String
+    pub name: String,
+    /// The index of the first instruction of the function.
+    pub start: usize,
+    /// The index after the last instruction of the function.
+    pub end: usize,
+}
+
+/// Gives the source line of the instructions from start until the start of the next line info.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineInfo {
+    /// The index of the first instruction of the line.
+    pub start: usize,
+    /// The source file.
+    pub file: String,
+    /// The line in the file.
+    pub line: usize,
+}
+
+/// What the compiler knows about the code, used to explain the errors.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugInfo {
+    /// The functions of the program, sorted by instruction.
+    pub symbols: Vec<Symbol>,
+    /// The source lines of the program, sorted by instruction.
+    pub lines: Vec<LineInfo>,
 }
 
-/// no memory safety, everything leaks.
 /// Stack contains only f64. everything else in the heap.
+/// The heap is garbage collected, starting from the root references.
 pub struct Vm<'a> {
     /// The root references into the heap. from the stack/global
     /// Contains a list of bools, the size of the stack.
@@ -81,6 +145,12 @@ pub struct Vm<'a> {
     stack_offset: usize,
     allocator: Allocator,
     output_stream: &'a mut Write,
+    /// Traces the execution if set.
+    trace: Option<Trace>,
+    /// The functions and lines of the program, to tell where errors happen.
+    debug_info: DebugInfo,
+    /// The native functions, called by their position.
+    natives: Vec<NativeFunction>,
 }
 
 impl<'a> Vm<'a> {
@@ -91,216 +161,556 @@ impl<'a> Vm<'a> {
             stack_offset: 0,
             allocator: Allocator::new(),
             output_stream: output_stream,
+            trace: None,
+            debug_info: DebugInfo::default(),
+            natives: vec![],
         }
     }
 
+    /// Gives the native functions that the program can call.
+    pub fn set_natives(&mut self, natives: Vec<NativeFunction>) {
+        self.natives = natives;
+    }
+
+    /// Returns the stream the program prints to, to write around the program's output.
+    pub fn output(&mut self) -> &mut Write {
+        &mut *self.output_stream
+    }
+
+    /// Gives back the native functions, to be used by another vm.
+    pub fn take_natives(&mut self) -> Vec<NativeFunction> {
+        self.natives.drain(..).collect()
+    }
+
+    /// Gives the symbols and lines of the program, used to locate the errors.
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = debug_info;
+    }
+
+    /// Enables the tracing of the execution.
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
     pub fn heap(&self) -> Vec<u64> {
         self.allocator.heap()
     }
 
-    pub fn run(&mut self, program: Vec<OP>) {
-        let mut instruction_pointer = 0;
+    /// Returns the values of the stack that are root references into the heap.
+    fn roots(&self) -> Vec<f64> {
+        self.root_references
+            .iter()
+            .filter(|&&i| i < self.stack.len())
+            .map(|&i| self.stack[i])
+            .collect()
+    }
+
+    /// Runs the program until its end or until an error happens.
+    pub fn run(&mut self, program: Vec<OP>) -> Result<(), RuntimeError> {
+        self.run_from(&program, 0)
+    }
+
+    /// Calls the function starting at the given instruction with the arguments, and returns its result.
+    /// The frame is built like a call from the code, with the end of the program as return adress,
+    /// the heap is kept between the calls but the stack is emptied after an error.
+    pub fn call(
+        &mut self,
+        program: &[OP],
+        start: usize,
+        args: &[(NativeValue, NativeType)],
+        ret: &NativeType,
+    ) -> Result<NativeValue, RuntimeError> {
+        let result = self.do_call(program, start, args, ret);
+        if result.is_err() {
+            self.stack.clear();
+            self.root_references.clear();
+            self.stack_offset = 0;
+        }
+        result
+    }
+
+    fn do_call(
+        &mut self,
+        program: &[OP],
+        start: usize,
+        args: &[(NativeValue, NativeType)],
+        ret: &NativeType,
+    ) -> Result<NativeValue, RuntimeError> {
+        let offset = self.stack.len();
+        let caller_offset = self.stack_offset as f64;
+        self.stack
+            .extend_from_slice(&[0.0, program.len() as f64, caller_offset]);
+        for &(ref value, ref value_type) in args.iter() {
+            let value = value
+                .write(&mut self.allocator)
+                .map_err(|kind| self.error(kind, start))?;
+            if value_type.is_pointer() {
+                self.root_references.insert(self.stack.len());
+            }
+            self.stack.push(value);
+        }
+        self.stack_offset = offset;
+        self.run_from(program, start)?;
+        let value = self
+            .pop()
+            .and_then(|value| ret.read(value, &self.allocator))
+            .map_err(|kind| self.error(kind, start))?;
+        self.root_references.remove(&self.stack.len());
+        Ok(value)
+    }
+
+    /// Runs a line of the repl, starting at the given instruction.
+    /// The line uses the frame left by the previous ones : Ret | Ins | Off | Variables ...
+    /// If it fails, the stack is put back as it was before the line.
+    pub fn run_line(&mut self, program: &[OP], start: usize) -> Result<(), RuntimeError> {
+        if self.stack.is_empty() {
+            self.stack.extend_from_slice(&[0.0, 0.0, 0.0]);
+        }
+        let size = self.stack.len();
+        self.stack_offset = 0;
+        let result = self.run_from(program, start);
+        if result.is_err() {
+            self.stack.truncate(size);
+            self.root_references = self.root_references.iter().cloned().filter(|&i| i < size).collect();
+            self.stack_offset = 0;
+        }
+        result
+    }
+
+    /// Runs the program from the given instruction until its end or until an error happens.
+    fn run_from(&mut self, program: &[OP], start: usize) -> Result<(), RuntimeError> {
+        let mut instruction_pointer = start;
         while instruction_pointer < program.len() {
+            let position = instruction_pointer;
             let op = &program[instruction_pointer];
             instruction_pointer += 1;
-            println!("executing {:?}", op);
-            match op {
-                &OP::End => {
-                    //println!("program execution terminated");
-                    return;
-                },
-                &OP::Goto(u) => instruction_pointer = u,
-                &OP::GotoTop => instruction_pointer = self.stack.pop().unwrap() as usize,
-                &OP::OffsetToTop(u) => {
-                    self.stack_offset = self.stack.len()-u;
-                }
-                &OP::PushOffset => {
-                    let val = self.stack_offset as f64;
-                    self.stack.push(val);
-                }
-                &OP::SetOffset => {
-                    let val = self.stack.pop().unwrap();
-                    self.stack_offset = val as usize;
-                }
-                &OP::PopN(u) => {
-                    let next_size = self.stack.len()-u;
-                    for i in 0..u{
-                        self.root_references.remove(&(self.stack.len()-1-i));
-                    }
-                    self.stack.truncate(next_size);
-                }
-                &OP::Pop => {
-                    self.stack.pop();
-                    self.root_references.remove(&self.stack.len());
-                }
-                &OP::Not => {
-                    let val = match self.stack.pop().unwrap() == 0.0 {
-                        false => 0.0,
-                        _ => 1.0,
-                    };
-                    self.stack.push(val)
-                }
-                &OP::GreaterThan => {
-                    let val = match self.stack.pop().unwrap() > self.stack.pop().unwrap() {
-                        false => 0.0,
-                        _ => 1.0,
-                    };
-                    self.stack.push(val)
-                }
-                &OP::GreaterEq => {
-                    let val = (self.stack.pop().unwrap() >= self.stack.pop().unwrap()) as i32 as f64;
-                    self.stack.push(val)
-                }
-                &OP::LowerEq => {
-                    let val = (self.stack.pop().unwrap() <= self.stack.pop().unwrap()) as i32 as f64;
-                    self.stack.push(val)
-                }
-                &OP::LowerThan => {
-                    let val = (self.stack.pop().unwrap() < self.stack.pop().unwrap()) as i32 as f64;
-                    self.stack.push(val)
-                }
-                &OP::And => {
-                    let val1 = self.stack.pop().unwrap() as i64;
-                    let val2 = self.stack.pop().unwrap() as i64;
-                    let res = val1 & val2;
-                    self.stack.push(res as f64)
-                }
-                &OP::AndAnd => {
-                    let val1 = self.stack.pop().unwrap() != 0.0;
-                    let val2 = self.stack.pop().unwrap() != 0.0;
-                    let res = (val1 && val2) as i32 as f64;
-                    self.stack.push(res)
-                }
-                &OP::Or => {
-                    let val1 = self.stack.pop().unwrap() as i64;
-                    let val2 = self.stack.pop().unwrap() as i64;
-                    let res = val1 | val2;
-                    self.stack.push(res as f64)
-                }
-                &OP::OrOr => {
-                    let val1 = self.stack.pop().unwrap() != 0.0;
-                    let val2 = self.stack.pop().unwrap() != 0.0;
-                    let res = (val1 || val2) as i32 as f64;
-                    self.stack.push(res)
-                }
-                &OP::Eq => {
-                    let val = match self.stack.pop().unwrap() == self.stack.pop().unwrap() {
-                        false => 0.0,
-                        _ => 1.0,
-                    };
-                    self.stack.push(val)
-                }
-                &OP::PushNum(n) => self.stack.push(n),
-                &OP::PushCopy => {
-                    let top = self.stack.last().unwrap().clone();
-                    self.stack.push(top);
-                },
+            let traced = self.trace.as_ref().map_or(false, |t| t.traces(position));
+            if traced {
+                self.trace.as_mut().unwrap().instruction(position, op);
+            }
+            let running = self
+                .execute(op, &mut instruction_pointer)
+                .map_err(|kind| self.error(kind, position))?;
+            if traced {
+                let heap = self.allocator.heap();
+                self.trace.as_mut().unwrap().state(&self.stack, &heap);
+            }
+            if !running {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-                &OP::PrintChar => {
-                    write!(self.output_stream, "{}", char::from_u32(self.stack.pop().unwrap() as u32).unwrap()).unwrap();
-                }
-                &OP::ToStr => {
-                    let top = self.stack.pop().unwrap().to_string();
-                    let len = top.len();
-                    let str_index = self.allocator.alloc(2, STRING_TYPE);
-                    self.allocator.set_ptr(str_index, len as f64);
-                    let slice_index = self.allocator.alloc(len, IS_SLICE_BIT+len as u64);
-                    self.allocator.set_ptr(str_index+1, slice_index as f64);
-                    for (i, ch )in top.chars().enumerate(){
-                        self.allocator.set_ptr(slice_index+i, ch as u32 as f64);
+    /// Creates an error for the instruction at the given position.
+    fn error(&self, kind: RuntimeErrorKind, position: usize) -> RuntimeError {
+        RuntimeError {
+            kind: kind,
+            instruction: position,
+            function: self.symbol_at(position).map(|s| s.name.clone()),
+            backtrace: self.backtrace(position),
+        }
+    }
+
+    /// Returns the function containing the instruction.
+    fn symbol_at(&self, position: usize) -> Option<&Symbol> {
+        self.debug_info
+            .symbols
+            .iter()
+            .find(|s| s.start <= position && position < s.end)
+    }
+
+    /// Walks the frames of the called functions, from the current one to main.
+    /// Each function called has the following frame, starting at the stack offset :
+    /// Ret | Ins | Off | Args ...
+    /// Where Ins is the instruction after the call in the caller and Off the offset of the caller.
+    /// The walk stops outside of the known functions or if the frames do not make sense.
+    fn backtrace(&self, position: usize) -> Vec<Frame> {
+        let (mut position, mut offset) = (position, self.stack_offset);
+        let mut frames = vec![];
+        while let Some(symbol) = self.symbol_at(position) {
+            let line = self
+                .debug_info
+                .lines
+                .iter()
+                .rev()
+                .find(|l| l.start <= position && l.start >= symbol.start);
+            frames.push(Frame {
+                function: symbol.name.clone(),
+                file: line.map(|l| l.file.clone()),
+                line: line.map(|l| l.line),
+            });
+            if offset + 2 >= self.stack.len() {
+                break;
+            }
+            let (ret, caller_offset) = (self.stack[offset + 1], self.stack[offset + 2]);
+            if ret < 1.0 || caller_offset < 0.0 || caller_offset as usize >= offset {
+                break;
+            }
+            // the call is the instruction just before the return adress.
+            position = ret as usize - 1;
+            offset = caller_offset as usize;
+        }
+        frames
+    }
+
+    /// Pops the top of the stack.
+    fn pop(&mut self) -> Result<f64, RuntimeErrorKind> {
+        self.stack.pop().ok_or(RuntimeErrorKind::StackUnderflow)
+    }
+
+    /// Returns the position in the stack of the nth value from the top.
+    fn from_top(&self, n: usize) -> Result<usize, RuntimeErrorKind> {
+        self.stack
+            .len()
+            .checked_sub(n)
+            .ok_or(RuntimeErrorKind::StackUnderflow)
+    }
+
+    /// Returns the position in the stack of the nth value of the current function.
+    fn local(&self, n: usize) -> Result<usize, RuntimeErrorKind> {
+        match self.stack_offset.checked_add(n) {
+            Some(local) if local < self.stack.len() => Ok(local),
+            _ => Err(RuntimeErrorKind::StackUnderflow),
+        }
+    }
+
+    /// Pops the offset of a frame, it must be inside the stack.
+    fn pop_offset(&mut self) -> Result<usize, RuntimeErrorKind> {
+        let offset = self.pop()?;
+        if !(offset >= 0.0 && offset <= self.stack.len() as f64) {
+            return Err(RuntimeErrorKind::InvalidOffset(offset));
+        }
+        Ok(offset as usize)
+    }
+
+    /// Pops the size of an allocation.
+    /// It must be a positive integer that fits in the type of a slice, below its IS_PTR_SLICE_BIT.
+    fn pop_size(&mut self) -> Result<usize, RuntimeErrorKind> {
+        let size = self.pop()?;
+        if !(size >= 0.0 && size.fract() == 0.0 && size < IS_PTR_SLICE_BIT as f64) {
+            return Err(RuntimeErrorKind::InvalidSize(size));
+        }
+        Ok(size as usize)
+    }
+
+    /// Allocates an object set to zero and pushes its adress, running the gc first.
+    /// The memory given by the allocator still contains what was there before.
+    fn alloc(&mut self, size: usize, descriptor: u64) -> Result<(), RuntimeErrorKind> {
+        let roots = self.roots();
+        self.allocator.run_gc(&roots);
+        let adress = self.allocator.alloc(size, descriptor);
+        for i in 0..size {
+            self.allocator.set_ptr(adress + i, 0.0)?;
+        }
+        self.root_references.insert(self.stack.len());
+        self.stack.push(adress as f64);
+        Ok(())
+    }
+
+    /// Executes one instruction, moving the instruction pointer if it jumps.
+    /// Returns false at the end of the program.
+    fn execute(
+        &mut self,
+        op: &OP,
+        instruction_pointer: &mut usize,
+    ) -> Result<bool, RuntimeErrorKind> {
+        match op {
+            &OP::End => {
+                //println!("program execution terminated");
+                return Ok(false);
+            },
+            &OP::Goto(u) => *instruction_pointer = u,
+            &OP::GotoTop => *instruction_pointer = self.pop()? as usize,
+            &OP::CallIndirect(u) => {
+                let value = self.pop()?;
+                self.root_references.remove(&self.stack.len());
+                let adress = value as usize;
+                let object = match adress {
+                    0 => None,
+                    _ => ObjectType::parse(self.allocator.get_heap(adress - 1)?.to_bits()),
+                };
+                let (pointers, size) = match object {
+                    Some(ObjectType::Object(pointers, size)) if size > 0 => (pointers, size),
+                    _ => return Err(RuntimeErrorKind::NotAFunction(adress)),
+                };
+                for i in 1..size {
+                    if pointers.contains(&i) {
+                        self.root_references.insert(self.stack.len());
                     }
-                    self.stack.push(str_index as f64);
-                    //println!("stack {:?}", self.stack);
-                    //println!("heap {:?}", self.allocator.heap);
+                    let capture = self.allocator.get_heap(adress + i)?;
+                    self.stack.push(capture);
                 }
-                &OP::RandNum => {
-                    self.stack.push(random::<f64>());
+                self.stack_offset = self.from_top(u.saturating_add(size - 1))?;
+                *instruction_pointer = self.allocator.get_heap(adress)? as usize;
+            }
+            &OP::CallNative(id) => {
+                let arity = match self.natives.get(id) {
+                    Some(native) => native.args.len(),
+                    None => return Err(RuntimeErrorKind::UnknownNative(id)),
+                };
+                let start = self.from_top(arity)?;
+                let native = &mut self.natives[id];
+                let mut args = vec![];
+                for (i, arg) in native.args.iter().enumerate() {
+                    args.push(arg.read(self.stack[start + i], &self.allocator)?);
                 }
-                &OP::JMPIf => {
-                    if self.stack.pop().unwrap() != 0.0 {
-                        instruction_pointer += 1;
-                    }
+                let result = native.call(&args)?.write(&mut self.allocator)?;
+                for i in start..self.stack.len() {
+                    self.root_references.remove(&i);
                 }
-                &OP::Inv => {
-                    let val = self.stack.pop().unwrap();
-                    self.stack.push(1.0 / val)
+                self.stack.truncate(start);
+                if native.ret.is_pointer() {
+                    self.root_references.insert(self.stack.len());
                 }
-                &OP::Mul => {
-                    let val = self.stack.pop().unwrap() * self.stack.pop().unwrap();
-                    self.stack.push(val)
+                self.stack.push(result);
+            }
+            &OP::OffsetToTop(u) => {
+                self.stack_offset = self.from_top(u)?;
+            }
+            &OP::PushOffset => {
+                let val = self.stack_offset as f64;
+                self.stack.push(val);
+            }
+            &OP::SetOffset => {
+                self.stack_offset = self.pop_offset()?;
+            }
+            &OP::PopN(u) => {
+                let next_size = self.from_top(u)?;
+                for i in 0..u{
+                    self.root_references.remove(&(self.stack.len()-1-i));
                 }
-                &OP::Neg => {
-                    let val = self.stack.pop().unwrap();
-                    self.stack.push(-val)
+                self.stack.truncate(next_size);
+            }
+            &OP::Pop => {
+                self.stack.pop();
+                self.root_references.remove(&self.stack.len());
+            }
+            &OP::Not => {
+                let val = match self.pop()? == 0.0 {
+                    false => 0.0,
+                    _ => 1.0,
+                };
+                self.stack.push(val)
+            }
+            &OP::GreaterThan => {
+                let val = match self.pop()? > self.pop()? {
+                    false => 0.0,
+                    _ => 1.0,
+                };
+                self.stack.push(val)
+            }
+            &OP::GreaterEq => {
+                let val = (self.pop()? >= self.pop()?) as i32 as f64;
+                self.stack.push(val)
+            }
+            &OP::LowerEq => {
+                let val = (self.pop()? <= self.pop()?) as i32 as f64;
+                self.stack.push(val)
+            }
+            &OP::LowerThan => {
+                let val = (self.pop()? < self.pop()?) as i32 as f64;
+                self.stack.push(val)
+            }
+            &OP::And => {
+                let val1 = self.pop()? as i64;
+                let val2 = self.pop()? as i64;
+                let res = val1 & val2;
+                self.stack.push(res as f64)
+            }
+            &OP::AndAnd => {
+                let val1 = self.pop()? != 0.0;
+                let val2 = self.pop()? != 0.0;
+                let res = (val1 && val2) as i32 as f64;
+                self.stack.push(res)
+            }
+            &OP::Or => {
+                let val1 = self.pop()? as i64;
+                let val2 = self.pop()? as i64;
+                let res = val1 | val2;
+                self.stack.push(res as f64)
+            }
+            &OP::OrOr => {
+                let val1 = self.pop()? != 0.0;
+                let val2 = self.pop()? != 0.0;
+                let res = (val1 || val2) as i32 as f64;
+                self.stack.push(res)
+            }
+            &OP::Eq => {
+                let val = match self.pop()? == self.pop()? {
+                    false => 0.0,
+                    _ => 1.0,
+                };
+                self.stack.push(val)
+            }
+            &OP::PushNum(n) => self.stack.push(n),
+            &OP::PushCopy => {
+                let top = self.stack[self.from_top(1)?];
+                if self.root_references.contains(&(self.stack.len() - 1)) {
+                    self.root_references.insert(self.stack.len());
                 }
-                &OP::Add => {
-                    let val = self.stack.pop().unwrap() + self.stack.pop().unwrap();
-                    self.stack.push(val)
+                self.stack.push(top);
+            },
+
+            &OP::PrintChar => {
+                let val = self.pop()?;
+                let ch = char::from_u32(val as u32)
+                    .filter(|_| val >= 0.0)
+                    .ok_or(RuntimeErrorKind::InvalidChar(val))?;
+                write!(self.output_stream, "{}", ch)
+                    .map_err(|e| RuntimeErrorKind::Output(e.to_string()))?;
+            }
+            &OP::ToStr => {
+                let top = self.pop()?.to_string();
+                let len = top.len();
+                let str_index = self.allocator.alloc(2, STRING_TYPE);
+                self.allocator.set_ptr(str_index, len as f64)?;
+                let slice_index = self.allocator.alloc(len, IS_SLICE_BIT+len as u64);
+                self.allocator.set_ptr(str_index+1, slice_index as f64)?;
+                for (i, ch )in top.chars().enumerate(){
+                    self.allocator.set_ptr(slice_index+i, ch as u32 as f64)?;
                 }
-                &OP::Mod => {
-                    let val = (self.stack.pop().unwrap() as i32) % (self.stack.pop().unwrap() as i32);
-                    self.stack.push(val as f64)
+                self.root_references.insert(self.stack.len());
+                self.stack.push(str_index as f64);
+                //println!("stack {:?}", self.stack);
+                //println!("heap {:?}", self.allocator.heap);
+            }
+            &OP::RandNum => {
+                self.stack.push(random::<f64>());
+            }
+            &OP::JMPIf => {
+                if self.pop()? != 0.0 {
+                    *instruction_pointer += 1;
                 }
-                &OP::Swap2 => {
-                    let (high, low) = (self.stack.pop().unwrap(), self.stack.pop().unwrap());
-                    self.stack.push(high);
-                    self.stack.push(low);
+            }
+            &OP::Inv => {
+                let val = self.pop()?;
+                self.stack.push(1.0 / val)
+            }
+            &OP::Mul => {
+                let val = self.pop()? * self.pop()?;
+                self.stack.push(val)
+            }
+            &OP::Neg => {
+                let val = self.pop()?;
+                self.stack.push(-val)
+            }
+            &OP::Add => {
+                let val = self.pop()? + self.pop()?;
+                self.stack.push(val)
+            }
+            &OP::Mod => {
+                let (val, modulo) = (self.pop()? as i32, self.pop()? as i32);
+                if modulo == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero);
                 }
-                &OP::Bring(id) => {
-                    if self.root_references.contains(&(self.stack_offset+id)){
-                        self.root_references.insert(self.stack.len());
-                    }
-                    let val = self.stack[self.stack_offset + id];
-                    self.stack.push(val);
+                self.stack.push(val.wrapping_rem(modulo) as f64)
+            }
+            &OP::Swap2 => {
+                let (high, low) = (self.pop()?, self.pop()?);
+                let (high_root, low_root) = (
+                    self.root_references.remove(&(self.stack.len() + 1)),
+                    self.root_references.remove(&self.stack.len()),
+                );
+                if high_root {
+                    self.root_references.insert(self.stack.len());
                 }
-                &OP::Set(id) => {
-                    let to_set = self.stack.pop().unwrap();
-                    if self.root_references.contains(&(self.stack.len())){
-                        self.root_references.remove(&self.stack.len());
-                        self.root_references.insert(self.stack_offset+id);
-                    }
-                    self.stack[self.stack_offset + id] = to_set;
+                if low_root {
+                    self.root_references.insert(self.stack.len() + 1);
                 }
-                &OP::AllocObj(descr) => {
-                    self.allocator.run_gc();
-                    let size = self.stack.pop().unwrap() as i32 as usize;
-                    let val = self.allocator.alloc(size, descr);
+                self.stack.push(high);
+                self.stack.push(low);
+            }
+            &OP::Bring(id) => {
+                let local = self.local(id)?;
+                if self.root_references.contains(&local){
                     self.root_references.insert(self.stack.len());
-                    self.stack.push(val as f64);
                 }
-                &OP::AllocSlice => {
-                    self.allocator.run_gc();
-                    let is_ptr = if self.stack.pop().unwrap() == 1.0 {IS_PTR_SLICE_BIT} else {0};
-                    let size = self.stack.pop().unwrap() as i32 as usize;
-                    let val = self.allocator.alloc(size, size as u64+IS_SLICE_BIT+is_ptr);
-                    self.root_references.insert(self.stack.len());
-                    self.stack.push(val as f64);
+                let val = self.stack[local];
+                self.stack.push(val);
+            }
+            &OP::Set(id) => {
+                let to_set = self.pop()?;
+                let local = self.local(id)?;
+                if self.root_references.contains(&(self.stack.len())){
+                    self.root_references.remove(&self.stack.len());
+                    self.root_references.insert(local);
                 }
-                &OP::GetHeap => {
-                    let adress: usize = self.stack.pop().unwrap() as usize;
-                    let val: f64 = self.allocator.get_heap(adress) as f64;
-                    self.root_references.insert(self.stack.len());
-                    self.stack.push(val);
+                self.stack[local] = to_set;
+            }
+            &OP::AllocObj(descr) => {
+                let size = self.pop_size()?;
+                match ObjectType::parse(descr) {
+                    Some(ref object) if object.get_size() == size => self.alloc(size, descr)?,
+                    _ => return Err(RuntimeErrorKind::InvalidDescriptor(descr, size)),
+                }
+            }
+            &OP::AllocSlice => {
+                let is_ptr = if self.pop()? == 1.0 {IS_PTR_SLICE_BIT} else {0};
+                let size = self.pop_size()?;
+                self.alloc(size, size as u64+IS_SLICE_BIT+is_ptr)?;
+            }
+            &OP::CheckIndex => {
+                let (index, slice) = (self.stack[self.from_top(1)?], self.stack[self.from_top(2)?]);
+                let len = self.allocator.slice_len(slice as usize)?;
+                if !(index >= 0.0 && index < len as f64 && index.fract() == 0.0) {
+                    return Err(RuntimeErrorKind::IndexOutOfBounds(index, len));
                 }
-                &OP::SetHeap => {
-                    let (adress, value) = (
-                        self.stack.pop().unwrap() as usize,
-                        self.stack.pop().unwrap(),
-                    );
-                    self.root_references.remove(&(self.stack.len()+1));
-                    //println!("val at : {} to {}", adress, value);
-                    self.allocator.set_ptr(adress, value);
+            }
+            &OP::SliceLen => {
+                let slice = self.pop()?;
+                self.root_references.remove(&self.stack.len());
+                let len = self.allocator.slice_len(slice as usize)?;
+                self.stack.push(len as f64);
+            }
+            &OP::GetHeap => {
+                let adress: usize = self.pop()? as usize;
+                let val: f64 = self.allocator.get_heap(adress)?;
+                self.root_references.insert(self.stack.len());
+                self.stack.push(val);
+            }
+            &OP::SetHeap => {
+                let (adress, value) = (
+                    self.pop()? as usize,
+                    self.pop()?,
+                );
+                self.root_references.remove(&(self.stack.len()+1));
+                //println!("val at : {} to {}", adress, value);
+                self.allocator.set_ptr(adress, value)?;
+            }
+            &OP::PushFrame(adress) => {
+                let offset = self.stack_offset as f64;
+                self.stack.extend_from_slice(&[0.0, adress as f64, offset]);
+            }
+            &OP::Return(u) => {
+                let next_size = self.from_top(u)?;
+                for i in next_size..self.stack.len() {
+                    self.root_references.remove(&i);
                 }
-                //_ => panic!("unsupported operand"),
+                self.stack.truncate(next_size);
+                self.stack_offset = self.pop_offset()?;
+                *instruction_pointer = self.pop()? as usize;
+            }
+            &OP::PushAdd(n) => {
+                let top = self.from_top(1)?;
+                self.stack[top] += n;
+            }
+            &OP::PushMul(n) => {
+                let top = self.from_top(1)?;
+                self.stack[top] *= n;
+            }
+            &OP::BringAdd(id) => {
+                let (local, top) = (self.local(id)?, self.from_top(1)?);
+                self.stack[top] += self.stack[local];
+            }
+            &OP::BringMul(id) => {
+                let (local, top) = (self.local(id)?, self.from_top(1)?);
+                self.stack[top] *= self.stack[local];
             }
 
-            println!("stack : {:?}", self.stack);
-            /*println!(
-                "root refs : {:?}",
-                self.root_references.iter().collect::<Vec<&usize>>()
-            );*/
-            println!("heap : {:?}", self.allocator.heap());
+            //_ => panic!("unsupported operand"),
         }
+        Ok(true)
     }
 }
 
@@ -312,10 +722,10 @@ mod tests_vm {
         let source = vec![OP::PushNum(1.0), OP::PrintChar];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(0, vm.stack.len());
         let source = vec![OP::PushNum(1.0), OP::PushNum(0.1)];
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(2, vm.stack.len());
     }
     #[test]
@@ -323,14 +733,14 @@ mod tests_vm {
         let source = vec![OP::PushNum(1.0), OP::PushNum(2.0), OP::Add, OP::Neg];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(1, vm.stack.len());
         assert_eq!(-3.0, vm.stack[0]);
         let source = vec![OP::PushNum(1.0), OP::Mul];
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(-3.0, vm.stack[0]);
         let source = vec![OP::PushNum(1.0)];
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(-3.0, vm.stack[0]);
     }
     #[test]
@@ -343,15 +753,15 @@ mod tests_vm {
         ];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(3, vm.stack.len());
         assert_eq!(1.0, vm.stack[0]);
         assert_eq!(0.5, vm.stack[1]);
         let source = vec![OP::Swap2];
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(0.5, vm.stack[2]);
         let source = vec![OP::Swap2, OP::PushNum(1.0), OP::Swap2];
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(2.0, vm.stack[3]);
     }
     #[test]
@@ -369,7 +779,7 @@ mod tests_vm {
         ];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         assert_eq!(vec![1.0, -1.0, -1.0], vm.stack);
     }
     // executes the following :
@@ -404,7 +814,7 @@ mod tests_vm {
         ];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         println!("heap : {:?}", vm.allocator.heap());
         println!("stack  : {:?}", vm.stack);
         assert_eq!(
@@ -441,11 +851,222 @@ mod tests_vm {
         ];
         let mut stdout = io::stdout();
         let mut vm = Vm::new(&mut stdout);
-        vm.run(source);
+        vm.run(source).unwrap();
         println!("heap : {:?}", vm.allocator.heap());
         assert_eq!(
             vm.allocator.heap(),
             vec![3, 3, 4, 7, 0, 0, allocator::MAX_HEAP_SIZE]
         );
     }
+    // allocates a lot of objects that are immediately dropped.
+    #[test]
+    fn test_gc_reuses_memory() {
+        let mut source = vec![OP::PushNum(1.0), OP::AllocObj(2)]; // kept alive.
+        for _ in 0..10 * allocator::GC_INTERVAL {
+            source.extend(vec![OP::PushNum(1.0), OP::AllocObj(2), OP::Pop]);
+        }
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        vm.run(source).unwrap();
+        assert!(vm.allocator.get_objects().len() <= allocator::GC_INTERVAL + 1);
+        assert!(vm.allocator.get_objects().contains(&0));
+    }
+    #[test]
+    fn test_runtime_errors() {
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        vm.set_debug_info(DebugInfo {
+            symbols: vec![Symbol {
+                name: "main()".to_string(),
+                start: 1,
+                end: 4,
+            }],
+            lines: vec![],
+        });
+        let err = vm.run(vec![OP::PushNum(1.0), OP::Add]).unwrap_err();
+        assert_eq!(
+            err,
+            RuntimeError {
+                kind: RuntimeErrorKind::StackUnderflow,
+                instruction: 1,
+                function: Some("main()".to_string()),
+                backtrace: vec![Frame {
+                    function: "main()".to_string(),
+                    file: None,
+                    line: None,
+                }],
+            }
+        );
+        let err = vm.run(vec![OP::PushNum(0.0), OP::PushNum(7.0), OP::Mod]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::DivisionByZero);
+        let err = vm.run(vec![OP::PushNum(-1.0), OP::PrintChar]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidChar(-1.0));
+        let ended = vm.run(vec![OP::End, OP::PushNum(3.0), OP::PushNum(50.0), OP::SetHeap]);
+        assert_eq!(ended, Ok(()));
+        let err = vm.run(vec![OP::PushNum(3.0), OP::PushNum(50.0), OP::SetHeap]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::HeapOutOfRange(50));
+        assert_eq!(err.function, Some("main()".to_string()));
+    }
+    #[test]
+    fn test_alloc_zeroed() {
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        let slice = vec![OP::PushNum(2.0), OP::AllocObj(IS_SLICE_BIT + 2)];
+        let mut written = slice.clone();
+        written.extend(vec![OP::PushNum(7.0), OP::Bring(0), OP::SetHeap, OP::Pop]);
+        vm.run(written).unwrap();
+        vm.allocator.collect(&[]);
+        let mut read = slice.clone();
+        read.push(OP::GetHeap);
+        vm.run(read).unwrap();
+        assert_eq!(vm.stack, vec![0.0]);
+    }
+    #[test]
+    fn test_invalid_alloc_size() {
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        for &size in [-1.0, 2.5, std::f64::NAN, IS_PTR_SLICE_BIT as f64].iter() {
+            let err = vm.run(vec![OP::PushNum(size), OP::PushNum(0.0), OP::AllocSlice]).unwrap_err();
+            match err.kind {
+                RuntimeErrorKind::InvalidSize(s) => assert!(s == size || s.is_nan() && size.is_nan()),
+                kind => panic!("unexpected error : {:?}", kind),
+            }
+        }
+        let err = vm.run(vec![OP::PushNum(-2.0), OP::AllocObj(2)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidSize(-2.0));
+    }
+    #[test]
+    fn test_malformed_programs() {
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        let err = vm.run(vec![OP::PushNum(1.0), OP::AllocObj(6)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidDescriptor(6, 1));
+        let err = vm.run(vec![OP::PushNum(0.0), OP::AllocObj(0)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidDescriptor(0, 0));
+        let err = vm.run(vec![OP::PushNum(1e30), OP::SetOffset, OP::Bring(3)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidOffset(1e30));
+        let err = vm.run(vec![OP::PushNum(-1.0), OP::PushNum(2.0), OP::Return(1)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidOffset(-1.0));
+        // the first cell of the object is zero, not a function type.
+        let call = vec![
+            OP::PushNum(1.0),
+            OP::AllocObj(2),
+            OP::PushNum(1.0),
+            OP::Add,
+            OP::CallIndirect(usize::max_value()),
+        ];
+        assert!(vm.run(call).is_err());
+    }
+    #[test]
+    fn test_slice_bounds() {
+        let mut output = vec![];
+        let mut vm = Vm::new(&mut output);
+        let slice = vec![OP::PushNum(3.0), OP::PushNum(0.0), OP::AllocSlice];
+        let mut in_bounds = slice.clone();
+        in_bounds.extend(vec![OP::PushNum(2.0), OP::CheckIndex, OP::Add, OP::GetHeap]);
+        assert_eq!(vm.run(in_bounds), Ok(()));
+        let mut len = slice.clone();
+        len.extend(vec![OP::SliceLen, OP::PrintChar]);
+        vm.run(len).unwrap();
+        for &index in [3.0, -1.0, 0.5].iter() {
+            let mut out_of_bounds = slice.clone();
+            out_of_bounds.extend(vec![OP::PushNum(index), OP::CheckIndex]);
+            let err = vm.run(out_of_bounds).unwrap_err();
+            assert_eq!(err.kind, RuntimeErrorKind::IndexOutOfBounds(index, 3));
+        }
+        let empty = vec![OP::PushNum(0.0), OP::PushNum(0.0), OP::AllocSlice];
+        let mut empty_index = empty.clone();
+        empty_index.extend(vec![OP::PushNum(0.0), OP::CheckIndex]);
+        let err = vm.run(empty_index).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::IndexOutOfBounds(0.0, 0));
+        drop(vm);
+        assert_eq!(output, vec![3]);
+    }
+    #[test]
+    fn test_call_indirect() {
+        // a function value capturing 40, called with 2 : returns the sum.
+        let source = vec![
+            OP::PushNum(2.0),
+            OP::AllocObj(4),
+            OP::PushCopy,
+            OP::PushNum(19.0),
+            OP::Swap2,
+            OP::SetHeap,
+            OP::PushCopy,
+            OP::PushNum(40.0),
+            OP::Swap2,
+            OP::PushNum(1.0),
+            OP::Add,
+            OP::SetHeap,
+            OP::PushNum(0.0),
+            OP::PushNum(18.0),
+            OP::PushNum(0.0),
+            OP::PushNum(2.0),
+            OP::Bring(0),
+            OP::CallIndirect(4),
+            OP::End,
+            OP::Bring(3),
+            OP::Bring(4),
+            OP::Add,
+            OP::Set(0),
+            OP::PopN(2),
+            OP::SetOffset,
+            OP::GotoTop,
+        ];
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        vm.run(source).unwrap();
+        assert_eq!(vm.stack[1], 42.0);
+        let err = vm.run(vec![OP::PushNum(0.0), OP::CallIndirect(3)]).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::NotAFunction(0));
+    }
+    #[test]
+    fn test_backtrace() {
+        let call = |ret: f64, function: usize| {
+            vec![
+                OP::PushOffset,
+                OP::PushNum(0.0),
+                OP::Swap2,
+                OP::PushNum(ret),
+                OP::Swap2,
+                OP::OffsetToTop(3),
+                OP::Goto(function),
+            ]
+        };
+        let mut source = call(7.0, 8);
+        source.push(OP::End);
+        source.append(&mut call(15.0, 16));
+        source.push(OP::GotoTop);
+        source.append(&mut vec![OP::PushNum(0.0), OP::PushNum(1.0), OP::Mod]);
+        let line = |start: usize, line: usize| LineInfo {
+            start: start,
+            file: "test.lisaa".to_string(),
+            line: line,
+        };
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        vm.set_debug_info(DebugInfo {
+            symbols: vec![
+                Symbol {
+                    name: "main()".to_string(),
+                    start: 8,
+                    end: 16,
+                },
+                Symbol {
+                    name: "f() -> num".to_string(),
+                    start: 16,
+                    end: 19,
+                },
+            ],
+            lines: vec![line(8, 1), line(14, 2), line(16, 5)],
+        });
+        let err = vm.run(source).unwrap_err();
+        assert_eq!(
+            err.backtrace
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<String>>(),
+            vec!["at f (test.lisaa:5)", "at main (test.lisaa:2)"]
+        );
+    }
 }
@@ -0,0 +1,325 @@
+//! The peephole optimizer : rewrites short sequences of the linked code into cheaper ones.
+//!
+//! A sequence is only replaced if no instruction after its first one is jumped to
+//! or starts a source line, so the jumps, the symbols and the lines still find their code.
+//! Once linked, the code adresses pushed (the return adresses and the closures) are numbers
+//! like the others : the compiler gives their positions so they are moved with the code.
+//! JMPIf skips the instruction after it, that instruction is never removed nor fused.
+use super::{DebugInfo, OP};
+use std::collections::HashSet;
+
+/// The longest sequence replaced.
+const WINDOW: usize = 5;
+
+/// An instruction with its position in the linked code,
+/// or the position of the first instruction of the sequence it replaces.
+/// Until the end, the jumps target positions in the linked code.
+#[derive(Debug, Clone)]
+struct Instruction {
+    origin: usize,
+    op: OP,
+    /// Pushes a code adress.
+    adress: bool,
+}
+
+/// Optimizes the linked code, the adresses are the positions of the PushNum pushing code adresses.
+/// The symbols and lines of the debug info are moved with their code.
+pub fn optimize(code: &[OP], adresses: &[usize], debug_info: &mut DebugInfo) -> Vec<OP> {
+    let adresses = adresses.iter().cloned().collect::<HashSet<usize>>();
+    let mut instructions = code
+        .iter()
+        .enumerate()
+        .map(|(i, op)| Instruction {
+            origin: i,
+            op: op.clone(),
+            adress: adresses.contains(&i),
+        })
+        .collect::<Vec<Instruction>>();
+    loop {
+        thread_jumps(&mut instructions);
+        let (rewritten, changed) = rewrite(&instructions, debug_info);
+        instructions = rewritten;
+        if !changed {
+            break;
+        }
+    }
+    relocate(&instructions, debug_info)
+}
+
+/// Returns where the code at the position of the linked code is now :
+/// the instruction with this origin, or the next one if it was removed.
+fn find(instructions: &[Instruction], origin: usize) -> usize {
+    instructions.partition_point(|i| i.origin < origin)
+}
+
+/// Makes the gotos going to a goto jump directly to its target.
+fn thread_jumps(instructions: &mut Vec<Instruction>) {
+    for k in 0..instructions.len() {
+        let mut target = match instructions[k].op {
+            OP::Goto(target) => target,
+            _ => continue,
+        };
+        // a loop of gotos never ends, it is left as it is.
+        for _ in 0..instructions.len() {
+            match instructions.get(find(instructions, target)).map(|i| &i.op) {
+                Some(&OP::Goto(next)) if next != target => target = next,
+                _ => break,
+            }
+        }
+        instructions[k].op = OP::Goto(target);
+    }
+}
+
+/// Returns the instructions that can only start a sequence : the ones jumped to,
+/// the starts of the functions and of the lines, and the two after each JMPIf.
+fn barriers(instructions: &[Instruction], debug_info: &DebugInfo) -> HashSet<usize> {
+    let mut origins = vec![];
+    for i in instructions {
+        match i.op {
+            OP::Goto(target) | OP::PushFrame(target) => origins.push(target),
+            OP::PushNum(target) if i.adress => origins.push(target as usize),
+            _ => (),
+        }
+    }
+    origins.extend(debug_info.symbols.iter().map(|s| s.start));
+    origins.extend(debug_info.lines.iter().map(|l| l.start));
+    let mut barriers = origins
+        .into_iter()
+        .map(|origin| find(instructions, origin))
+        .collect::<HashSet<usize>>();
+    for (k, i) in instructions.iter().enumerate() {
+        if i.op == OP::JMPIf {
+            barriers.insert(k + 1);
+            barriers.insert(k + 2);
+        }
+    }
+    barriers
+}
+
+/// Replaces the known sequences once and removes the code that can not be reached.
+/// Returns the new instructions and if they changed.
+fn rewrite(instructions: &[Instruction], debug_info: &DebugInfo) -> (Vec<Instruction>, bool) {
+    let barriers = barriers(instructions, debug_info);
+    let mut rewritten: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    let mut changed = false;
+    // the code jumped to is still jumped to when its first instructions are removed.
+    let mut jumped_to = false;
+    let mut k = 0;
+    while k < instructions.len() {
+        let skipped = k > 0 && instructions[k - 1].op == OP::JMPIf;
+        let mut end = k + 1;
+        while end < instructions.len() && end - k < WINDOW && !barriers.contains(&end) {
+            end += 1;
+        }
+        jumped_to = jumped_to || barriers.contains(&k);
+        let unreachable = !jumped_to && rewritten.last().map_or(false, |i| ends_flow(&i.op));
+        let replaced = match instructions[k].op {
+            _ if skipped => None,
+            _ if unreachable => Some((1, None)),
+            OP::Goto(target) if find(instructions, target) == k + 1 => Some((1, None)),
+            _ => replacement(&instructions[k..end]),
+        };
+        match replaced {
+            Some((size, op)) => {
+                jumped_to = jumped_to && op.is_none();
+                rewritten.extend(op.map(|op| Instruction {
+                    origin: instructions[k].origin,
+                    op: op,
+                    adress: false,
+                }));
+                k += size;
+                changed = true;
+            }
+            None => {
+                jumped_to = false;
+                rewritten.push(instructions[k].clone());
+                k += 1;
+            }
+        }
+    }
+    (rewritten, changed)
+}
+
+/// Checks if the instruction never goes to the next one.
+fn ends_flow(op: &OP) -> bool {
+    match op {
+        &OP::Goto(_) | &OP::GotoTop | &OP::Return(_) | &OP::End => true,
+        _ => false,
+    }
+}
+
+/// Returns the number of instructions replaced at the start of the sequence
+/// and the instruction replacing them, None if they are removed.
+fn replacement(sequence: &[Instruction]) -> Option<(usize, Option<OP>)> {
+    let ops = sequence.iter().map(|i| &i.op).collect::<Vec<&OP>>();
+    let number = |k: usize| match sequence.get(k) {
+        Some(&Instruction {
+            op: OP::PushNum(n),
+            adress: false,
+            ..
+        }) => Some(n),
+        _ => None,
+    };
+    Some(match ops.as_slice() {
+        [&OP::PushOffset, &OP::PushNum(ret), &OP::Swap2, &OP::PushNum(adress), &OP::Swap2, ..]
+            if ret.to_bits() == 0 && !sequence[1].adress && sequence[3].adress =>
+        {
+            (5, Some(OP::PushFrame(adress as usize)))
+        }
+        [&OP::PopN(n), &OP::SetOffset, &OP::GotoTop, ..] => (3, Some(OP::Return(n))),
+        [&OP::PushNum(_), &OP::Inv, ..] => (2, Some(OP::PushNum(1.0 / number(0)?))),
+        [&OP::PushNum(_), &OP::Neg, ..] => (2, Some(OP::PushNum(-number(0)?))),
+        [&OP::PushNum(_), &OP::Add, ..] => (2, Some(OP::PushAdd(number(0)?))),
+        [&OP::PushNum(_), &OP::Mul, ..] => (2, Some(OP::PushMul(number(0)?))),
+        [&OP::PushNum(_), &OP::Pop, ..] if number(0).is_some() => (2, None),
+        [&OP::Bring(id), &OP::Add, ..] => (2, Some(OP::BringAdd(id))),
+        [&OP::Bring(id), &OP::Mul, ..] => (2, Some(OP::BringMul(id))),
+        [&OP::Swap2, &OP::Swap2, ..] => (2, None),
+        [&OP::PopN(0), ..] => (1, None),
+        _ => return None,
+    })
+}
+
+/// Gives the instructions their final positions and points the jumps,
+/// the adresses pushed and the debug info to them.
+fn relocate(instructions: &[Instruction], debug_info: &mut DebugInfo) -> Vec<OP> {
+    for symbol in debug_info.symbols.iter_mut() {
+        symbol.start = find(instructions, symbol.start);
+        symbol.end = find(instructions, symbol.end);
+    }
+    for line in debug_info.lines.iter_mut() {
+        line.start = find(instructions, line.start);
+    }
+    instructions
+        .iter()
+        .map(|i| match i.op {
+            OP::Goto(target) => OP::Goto(find(instructions, target)),
+            OP::PushFrame(target) => OP::PushFrame(find(instructions, target)),
+            OP::PushNum(target) if i.adress => {
+                OP::PushNum(find(instructions, target as usize) as f64)
+            }
+            ref op => op.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests_peephole {
+    use super::*;
+    use std::io;
+    use vm::{LineInfo, Symbol, Vm};
+
+    #[test]
+    fn test_fuses_and_keeps_targets() {
+        // main calls a function counting to 3 in a loop and returning the count.
+        let code = vec![
+            OP::PushOffset,
+            OP::PushNum(0.0),
+            OP::Swap2,
+            OP::PushNum(7.0),
+            OP::Swap2,
+            OP::OffsetToTop(3),
+            OP::Goto(8),
+            OP::End,
+            OP::PushNum(0.0),
+            OP::Bring(3),
+            OP::PushNum(3.0),
+            OP::GreaterThan,
+            OP::JMPIf,
+            OP::Goto(19),
+            OP::Bring(3),
+            OP::PushNum(1.0),
+            OP::Add,
+            OP::Set(3),
+            OP::Goto(9),
+            OP::PopN(0),
+            OP::Bring(3),
+            OP::Set(0),
+            OP::PopN(1),
+            OP::SetOffset,
+            OP::GotoTop,
+        ];
+        let mut debug_info = DebugInfo {
+            symbols: vec![Symbol {
+                name: "count() -> num".to_string(),
+                start: 8,
+                end: 25,
+            }],
+            lines: vec![LineInfo {
+                start: 14,
+                file: "count.lisaa".to_string(),
+                line: 3,
+            }],
+        };
+        let optimized = optimize(&code, &[3], &mut debug_info);
+        assert_eq!(
+            optimized,
+            vec![
+                OP::PushFrame(3),
+                OP::OffsetToTop(3),
+                OP::Goto(4),
+                OP::End,
+                OP::PushNum(0.0),
+                OP::Bring(3),
+                OP::PushNum(3.0),
+                OP::GreaterThan,
+                OP::JMPIf,
+                OP::Goto(14),
+                OP::Bring(3),
+                OP::PushAdd(1.0),
+                OP::Set(3),
+                OP::Goto(5),
+                OP::Bring(3),
+                OP::Set(0),
+                OP::Return(1),
+            ]
+        );
+        assert_eq!((debug_info.symbols[0].start, debug_info.symbols[0].end), (4, 17));
+        assert_eq!(debug_info.lines[0].start, 10);
+        let mut stdout = io::stdout();
+        let mut vm = Vm::new(&mut stdout);
+        vm.run(code).unwrap();
+        assert_eq!(vm.stack, vec![3.0]);
+        let mut vm = Vm::new(&mut stdout);
+        vm.run(optimized).unwrap();
+        assert_eq!(vm.stack, vec![3.0]);
+    }
+
+    #[test]
+    fn test_keeps_the_skipped_instruction() {
+        let code = vec![
+            OP::PushNum(1.0),
+            OP::JMPIf,
+            OP::PopN(0),
+            OP::PushNum(2.0),
+            OP::Add,
+            OP::End,
+        ];
+        assert_eq!(
+            optimize(&code, &[], &mut DebugInfo::default()),
+            vec![
+                OP::PushNum(1.0),
+                OP::JMPIf,
+                OP::PopN(0),
+                OP::PushAdd(2.0),
+                OP::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_removes_unreachable_code() {
+        let code = vec![
+            OP::Goto(3),
+            OP::PushNum(65.0),
+            OP::PrintChar,
+            OP::PushNum(2.0),
+            OP::Inv,
+            OP::End,
+        ];
+        assert_eq!(
+            optimize(&code, &[], &mut DebugInfo::default()),
+            vec![OP::PushNum(0.5), OP::End]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Execution tracing : shows each executed instruction with the state of the stack and the heap.
+//! Off by default, the vm only traces when it is given a Trace.
+use super::OP;
+use std::io::Write;
+
+/// Where and what to trace.
+pub struct Trace {
+    sink: Box<Write>,
+    /// The instruction ranges to trace, start included end excluded. Empty to trace everything.
+    ranges: Vec<(usize, usize)>,
+    /// The code of the functions to trace, like the ranges. When both are given, an instruction must be in both.
+    functions: Vec<(usize, usize)>,
+}
+
+impl Trace {
+    /// Traces every instruction to the given sink.
+    pub fn new(sink: Box<Write>) -> Self {
+        Trace {
+            sink: sink,
+            ranges: vec![],
+            functions: vec![],
+        }
+    }
+
+    /// Only traces the instructions in the given range, can be called several times.
+    pub fn only(mut self, start: usize, end: usize) -> Self {
+        self.ranges.push((start, end));
+        self
+    }
+
+    /// Only traces the instructions of the function whose code is in the given range, can be called several times.
+    /// When ranges are given too, only the instructions of these functions in the ranges are traced.
+    pub fn only_function(mut self, start: usize, end: usize) -> Self {
+        self.functions.push((start, end));
+        self
+    }
+
+    /// Checks if the instruction at the given position must be traced.
+    pub fn traces(&self, position: usize) -> bool {
+        let contains = |ranges: &Vec<(usize, usize)>| {
+            ranges.is_empty()
+                || ranges
+                    .iter()
+                    .any(|&(start, end)| start <= position && position < end)
+        };
+        contains(&self.ranges) && contains(&self.functions)
+    }
+
+    /// Shows the instruction about to be executed.
+    /// A failing sink does not stop the program, the trace is just lost.
+    pub fn instruction(&mut self, position: usize, op: &OP) {
+        let _ = writeln!(self.sink, "{:>6}  {:?}", position, op);
+    }
+
+    /// Shows the state of the vm after the instruction.
+    pub fn state(&mut self, stack: &[f64], heap: &[u64]) {
+        let _ = writeln!(self.sink, "        stack : {:?}", stack);
+        let _ = writeln!(self.sink, "        heap : {:?}", heap);
+    }
+}
+
+#[cfg(test)]
+mod tests_trace {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_ranges() {
+        let all = Trace::new(Box::new(io::sink()));
+        assert!(all.traces(0) && all.traces(1000));
+        let some = Trace::new(Box::new(io::sink())).only(2, 4).only(10, 11);
+        let traced = (0..12).filter(|&i| some.traces(i)).collect::<Vec<usize>>();
+        assert_eq!(traced, vec![2, 3, 10]);
+    }
+
+    #[test]
+    fn test_functions_and_ranges() {
+        let functions = Trace::new(Box::new(io::sink())).only_function(2, 6).only_function(8, 9);
+        let traced = (0..12).filter(|&i| functions.traces(i)).collect::<Vec<usize>>();
+        assert_eq!(traced, vec![2, 3, 4, 5, 8]);
+        let both = functions.only(4, 10);
+        let traced = (0..12).filter(|&i| both.traces(i)).collect::<Vec<usize>>();
+        assert_eq!(traced, vec![4, 5, 8]);
+    }
+}
@@ -1,78 +1,467 @@
 extern crate lisaa_lang;
-use lisaa_lang::script::Script;
-use std::fs::File;
-use std::io::Read;
+use lisaa_lang::script::Golden;
+use std::env;
 
-fn test_for(script: &str) {
-    let (mut name, mut expect) = (
-        "tests/scripts/test_".to_string(),
-        "tests/scripts/expect_".to_string(),
-    );
-    name.push_str(script);
-    name.push_str(".lisaa");
-    expect.push_str(script);
-    let mut expected_file = File::open(expect).expect(&format!(
-        "could not open file tests/scripts/expect_{}",
-        script
-    ));
-    let mut expected_result = String::new();
-    expected_file
-        .read_to_string(&mut expected_result)
-        .expect("could not read file");
-    expected_result = expected_result.replace("\r", ""); // ignore stupid windows crlf
-    let result = Script::new(&name).run_program(expected_result.len());
-    assert_eq!(result.expect("not an error"), expected_result);
-}
-
-/// Create a directory tests.
-/// containing test_hunter2.rs and expect_hunter2
-/// Add a test in tests.rs with the expec
+/// Runs the golden tests : every tests/scripts/test_NAME.lisaa is compared with expect_NAME
+/// (what it prints) and expect_NAME.stderr (its errors, only for the scripts that must fail).
+/// Adding a test is adding the script, then running `LISAA_BLESS=1 cargo test` writes the
+/// expectation files from what the script does, check them before commiting.
 #[test]
-fn test_hello_world() {
-    test_for("hello_world");
+fn test_scripts() {
+    let bless = env::var("LISAA_BLESS").map(|b| b != "0").unwrap_or(false);
+    let tests = Golden::discover("tests/scripts").expect("could not find the tests");
+    assert!(!tests.is_empty(), "no test found in tests/scripts");
+    let failures = tests
+        .iter()
+        .filter_map(|test| test.check(bless).err())
+        .collect::<Vec<String>>();
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} scripts failed :\n\n{}",
+            failures.len(),
+            tests.len(),
+            failures.join("\n\n")
+        );
+    }
 }
 
+/// The optimized golden scripts must print the same thing and end the same way.
+/// The instructions move but the runtime errors still show the same functions and lines.
 #[test]
-fn test_char() {
-    test_for("char");
-}
-#[test]
-fn test_slice() {
-    test_for("slice");
-}
-#[test]
-fn test_num() {
-    test_for("num");
+fn test_optimized_scripts() {
+    use lisaa_lang::script::Script;
+    let frames = |stderr: &str| {
+        stderr
+            .lines()
+            .filter(|l| l.starts_with("    at "))
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>()
+    };
+    let tests = Golden::discover("tests/scripts").expect("could not find the tests");
+    for test in tests {
+        let script = test.script().to_string_lossy().into_owned();
+        let plain = Script::new(&script).run();
+        for level in 1..3 {
+            let optimized = Script::new(&script).optimize(level).run();
+            assert_eq!(plain.status, optimized.status, "{} ended differently", script);
+            if let Some(diff) = optimized.diff_stdout(&plain.stdout) {
+                panic!("{} prints differently at level {} :\n{}", script, level, diff);
+            }
+            assert_eq!(frames(&plain.stderr), frames(&optimized.stderr), "{}", script);
+        }
+    }
 }
+
 #[test]
-fn test_pi() {
-    test_for("pi");
+fn test_ffi() {
+    use lisaa_lang::lisaa::{Lisaa, NativeType, NativeValue};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let logs = Rc::new(RefCell::new(vec![]));
+    let mut output = vec![];
+    {
+        let logged = logs.clone();
+        let mut lisaa = Lisaa::new("tests/scripts/host/ffi.lisaa".to_string(), &mut output, false);
+        lisaa
+            .register(
+                "hypot",
+                vec![NativeType::Num, NativeType::Num],
+                NativeType::Num,
+                |args| match (&args[0], &args[1]) {
+                    (&NativeValue::Num(a), &NativeValue::Num(b)) => Ok(NativeValue::Num(a.hypot(b))),
+                    _ => Err("expected two numbers".to_string()),
+                },
+            )
+            .register("shout", vec![NativeType::String], NativeType::String, |args| {
+                match args[0] {
+                    NativeValue::String(ref s) => Ok(NativeValue::String(s.to_uppercase() + "!")),
+                    _ => Err("expected a string".to_string()),
+                }
+            })
+            .register(
+                "split",
+                vec![NativeType::String],
+                NativeType::Slice(Box::new(NativeType::String)),
+                |args| match args[0] {
+                    NativeValue::String(ref s) => Ok(NativeValue::Slice(
+                        s.split(' ').map(|w| NativeValue::String(w.to_string())).collect(),
+                    )),
+                    _ => Err("expected a string".to_string()),
+                },
+            )
+            .register(
+                "total",
+                vec![NativeType::Slice(Box::new(NativeType::Num))],
+                NativeType::Num,
+                |args| match args[0] {
+                    NativeValue::Slice(ref values) => Ok(NativeValue::Num(
+                        values.iter().fold(0.0, |sum, v| match v {
+                            &NativeValue::Num(n) => sum + n,
+                            _ => sum,
+                        }),
+                    )),
+                    _ => Err("expected a slice".to_string()),
+                },
+            )
+            .register("log", vec![NativeType::String], NativeType::Void, move |args| {
+                logged.borrow_mut().push(args[0].clone());
+                Ok(NativeValue::Void)
+            });
+        lisaa.run().expect("not an error");
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), "5\nHELLO!\n3\nthree\n0\n");
+    assert_eq!(*logs.borrow(), vec![NativeValue::String("done".to_string())]);
 }
 #[test]
-fn test_fac() {
-    test_for("fac");
+fn test_embed() {
+    use lisaa_lang::lisaa::{Lisaa, NativeValue};
+    let mut output = vec![];
+    {
+        let mut lisaa = Lisaa::new("main".to_string(), &mut output, false);
+        lisaa
+            .module(
+                "main",
+                "import string\nimport geometry\n\
+                 fn greet(String name) -> String {\n\
+                 \t\"called\".println();\n\
+                 \treturn \"hello \" + name;\n\
+                 }\n\
+                 fn down(num n) -> num {\n\
+                 \treturn 1 % n;\n\
+                 }\n\
+                 fn area(num side) -> num {\n\
+                 \treturn geometry::square(side);\n\
+                 }\n",
+            )
+            .module(
+                "geometry",
+                "import stdlib\n\
+                 pub fn square(num a) -> num {\n\treturn a * a;\n}\n\
+                 fn square(slice<num> values) -> slice<num> {\n\
+                 \tslice<num> result = newslice::<num>(values.len());\n\
+                 \tfor (num i = 0; i < values.len(); i = i + 1;) {\n\
+                 \t\tresult[i] = square(values[i]);\n\
+                 \t}\n\
+                 \treturn result;\n\
+                 }\n",
+            );
+        let mut program = lisaa.load().expect("not an error");
+        assert!(program.functions().contains(&"square(num) -> num".to_string()));
+        assert_eq!(
+            program.call("square", &[NativeValue::Num(3.0)]),
+            Ok(NativeValue::Num(9.0))
+        );
+        assert_eq!(
+            program.call(
+                "square",
+                &[NativeValue::Slice(vec![NativeValue::Num(2.0), NativeValue::Num(-4.0)])]
+            ),
+            Ok(NativeValue::Slice(vec![NativeValue::Num(4.0), NativeValue::Num(16.0)]))
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                program.call("greet", &[NativeValue::String("lisaa".to_string())]),
+                Ok(NativeValue::String("hello lisaa".to_string()))
+            );
+        }
+        let error = program.call("down", &[NativeValue::Num(0.0)]).unwrap_err();
+        assert!(error.starts_with("division by zero at instruction"));
+        assert!(error.ends_with("in down(num) -> num\n    at down (main:8)"));
+        assert_eq!(
+            program.call("square", &[NativeValue::Bool(true)]),
+            Err("no function square taking the arguments [Bool(true)]".to_string())
+        );
+        assert_eq!(
+            program.call("cube", &[]),
+            Err("unknown function : cube".to_string())
+        );
+        assert_eq!(program.call("square", &[NativeValue::Num(5.0)]), Ok(NativeValue::Num(25.0)));
+        assert_eq!(program.call("area", &[NativeValue::Num(4.0)]), Ok(NativeValue::Num(16.0)));
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), "called\ncalled\n");
 }
 #[test]
-fn test_factors() {
-    test_for("factors");
+fn test_search_path() {
+    use lisaa_lang::lisaa::Lisaa;
+    let mut output = vec![];
+    {
+        let mut lisaa = Lisaa::new("main".to_string(), &mut output, false);
+        lisaa
+            .module(
+                "main",
+                "import string
+import shapes
+                 fn main() {
+                 	Square s = Square();
+                 	s.side = 5;
+                 	println(area(s));
+                 }
+",
+            )
+            .search_path("tests/scripts/host")
+            .search_path("tests/scripts/imp");
+        lisaa.run().expect("not an error");
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), "25\n");
 }
 #[test]
-fn test_overloading() {
-    test_for("overloading");
+fn test_repl() {
+    use lisaa_lang::lisaa::Lisaa;
+    use std::io::Cursor;
+    let input = "import string\n\
+                 num a = 40;\n\
+                 fn double(num x) -> num {\n\
+                 \treturn x * 2;\n\
+                 }\n\
+                 double(a + 1)\n\
+                 fn(num) -> num f = fn(num x) -> num { return x + a; };\n\
+                 slice<num> values = newslice::<num>(2);\n\
+                 values[3]\n\
+                 values[1] = f(2);\n\
+                 values\n\
+                 num b = \"two\";\n\
+                 println(\"done\")\n";
+    let (mut output, mut errors) = (vec![], vec![]);
+    {
+        let mut lisaa = Lisaa::new("repl".to_string(), &mut output, false);
+        lisaa.error_output(&mut errors);
+        lisaa
+            .repl(&mut Cursor::new(input.as_bytes()))
+            .expect("not an error");
+    }
+    // the prompts are written before each line, the one of the lines going on with a dot
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "> > > . . > 82\n> > > > > 042\n> > done\n> "
+    );
+    let errors = String::from_utf8(errors).unwrap();
+    assert!(errors.starts_with("error: index out of bounds : the len is 2 but the index is 3"));
+    assert!(errors.contains("    at repl_line (repl:1)\n"), "{}", errors);
+    // the errors point to the line entered, not to the function holding it
+    assert!(errors.contains(" --> repl:1:9\n  |\n1 | num b = \"two\";\n"), "{}", errors);
 }
+
+/// Talks to the language server like an editor : the messages are written to its input
+/// and the responses are read from its output, with the notifications sent before them.
 #[test]
-fn test_import() {
-    test_for("import");
+fn test_lsp() {
+    use lisaa_lang::lsp::json::Json;
+    use lisaa_lang::lsp::{read_message, write_message};
+    use std::fs;
+    use std::io::BufReader;
+    use std::process::{Command, Stdio};
+    let mut server = Command::new(env!("CARGO_BIN_EXE_lisaa-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("could not start the language server");
+    let mut input = server.stdin.take().unwrap();
+    let mut output = BufReader::new(server.stdout.take().unwrap());
+    let mut send = |id: Option<f64>, method: &str, params: Json| {
+        let mut fields = vec![("jsonrpc", Json::string("2.0")), ("method", Json::string(method))];
+        if let Some(id) = id {
+            fields.push(("id", Json::Number(id)));
+        }
+        fields.push(("params", params));
+        write_message(&mut input, &Json::object(fields)).unwrap();
+    };
+    let mut receive = |id: f64| {
+        let mut notifications = vec![];
+        loop {
+            let message = read_message(&mut output).unwrap().expect("no response");
+            if message.get("id").as_f64() == Some(id) {
+                return (message.get("result").clone(), notifications);
+            }
+            notifications.push(message);
+        }
+    };
+    let dir = env::current_dir().unwrap();
+    let uri = |file: &str| format!("file://{}", dir.join(file).to_string_lossy());
+    let document = uri("tests/scripts/test_modules.lisaa");
+    let at = |line: f64, character: f64| {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::string(&document))])),
+            (
+                "position",
+                Json::object(vec![("line", Json::Number(line)), ("character", Json::Number(character))]),
+            ),
+        ])
+    };
+
+    send(Some(1.0), "initialize", Json::object(vec![]));
+    let (result, _) = receive(1.0);
+    assert_eq!(
+        result.get("capabilities").get("definitionProvider"),
+        &Json::Bool(true)
+    );
+    send(None, "initialized", Json::object(vec![]));
+
+    let text = fs::read_to_string("tests/scripts/test_modules.lisaa").unwrap();
+    let incomplete = text.replace("\ts.side = 3;", "\ts.side = 3;\n\ts.si");
+    let open = |uri: &str, text: &str| {
+        Json::object(vec![(
+            "textDocument",
+            Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("languageId", Json::string("lisaa")),
+                ("version", Json::Number(1.0)),
+                ("text", Json::string(text)),
+            ]),
+        )])
+    };
+    let broken = uri("tests/scripts/test_type_error.lisaa");
+    let broken_text = fs::read_to_string("tests/scripts/test_type_error.lisaa").unwrap();
+    send(None, "textDocument/didOpen", open(&broken, &broken_text));
+    send(None, "textDocument/didOpen", open(&document, &incomplete));
+
+    // the completion of s.si, on the line that does not parse
+    send(Some(2.0), "textDocument/completion", at(6.0, 5.0));
+    let (result, notifications) = receive(2.0);
+    assert_eq!(
+        result,
+        Json::Array(vec![Json::object(vec![
+            ("label", Json::string("side")),
+            ("kind", Json::Number(5.0)),
+            ("detail", Json::string("num")),
+        ])])
+    );
+    let diagnostics = notifications
+        .iter()
+        .filter(|n| n.get("params").get("uri").as_str() == Some(&broken))
+        .map(|n| n.get("params").get("diagnostics").clone())
+        .next()
+        .expect("no diagnostics published");
+    let diagnostic = &diagnostics.as_array().unwrap()[0];
+    assert_eq!(
+        diagnostic.get("message").as_str(),
+        Some("Expected : num, got : class String<>")
+    );
+    assert_eq!(diagnostic.get("range").get("start").get("line").as_f64(), Some(3.0));
+    assert_eq!(diagnostic.get("range").get("start").get("character").as_f64(), Some(9.0));
+
+    // the document parses again, area(s) is declared in the imported module
+    send(
+        None,
+        "textDocument/didChange",
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::string(&document))])),
+            (
+                "contentChanges",
+                Json::Array(vec![Json::object(vec![("text", Json::string(&text))])]),
+            ),
+        ]),
+    );
+    send(Some(3.0), "textDocument/definition", at(6.0, 2.0));
+    let (result, _) = receive(3.0);
+    assert_eq!(
+        result.get("uri").as_str(),
+        Some(uri("tests/scripts/imp/shapes.lisaa").as_str())
+    );
+    assert_eq!(result.get("range").get("start").get("line").as_f64(), Some(10.0));
+
+    send(Some(4.0), "textDocument/hover", at(7.0, 19.0));
+    let (result, _) = receive(4.0);
+    assert_eq!(
+        result.get("contents").get("value").as_str(),
+        Some("```lisaa\nSquare::toString() -> String\n```")
+    );
+
+    send(Some(5.0), "shutdown", Json::Null);
+    assert_eq!(receive(5.0).0, Json::Null);
+    send(None, "exit", Json::Null);
+    assert!(server.wait().unwrap().success());
 }
+
 #[test]
-fn test_generic_class() {
-    test_for("generic_class");
+fn test_fmt() {
+    use std::fs;
+    use std::process::Command;
+    let lisaa = env!("CARGO_BIN_EXE_lisaa_lang");
+    // each tests/fmt/test_NAME.lisaa is formatted into tests/fmt/expect_NAME.lisaa
+    for entry in fs::read_dir("tests/fmt").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if !name.starts_with("test_") {
+            continue;
+        }
+        let expected = fs::read_to_string(path.with_file_name(name.replace("test_", "expect_"))).unwrap();
+        let file = env::temp_dir().join(format!("lisaa_fmt_{}_{}", std::process::id(), name));
+        fs::copy(&path, &file).unwrap();
+        let check = Command::new(lisaa).args(&["fmt", "--check"]).arg(&file).output().unwrap();
+        assert!(!check.status.success(), "{} is not formatted", name);
+        assert!(String::from_utf8_lossy(&check.stderr).contains("is not formatted"));
+        assert!(Command::new(lisaa).arg("fmt").arg(&file).status().unwrap().success());
+        let formatted = fs::read_to_string(&file).unwrap();
+        let check = Command::new(lisaa).args(&["fmt", "--check"]).arg(&file).status();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(formatted, expected, "{}", name);
+        assert!(check.unwrap().success());
+    }
 }
+
 #[test]
-fn test_generic_func() {
-    test_for("generic_func");
+fn test_lints() {
+    use lisaa_lang::lisaa::{Level, Lint, Lisaa};
+    let main = "import geometry::{double, half}\n\
+                import string\n\
+                import colors\n\
+                fn main() {\n    num a = 1;\n    num b = 2;\n    println(double(a));\n}\n";
+    let geometry = "pub fn double(num x) -> num {\n    return x * 2;\n}\n\
+                    pub fn half(num x) -> num {\n    return x / 2;\n}\n";
+    let colors = "pub fn red() -> num {\n    return 1;\n}\n";
+    let run = |level: Level| {
+        let (mut output, mut errors) = (vec![], vec![]);
+        let result = {
+            let mut lisaa = Lisaa::new("main".to_string(), &mut output, false);
+            lisaa
+                .error_output(&mut errors)
+                .module("main", main)
+                .module("geometry", geometry)
+                .module("colors", colors)
+                .lint(Lint::UnusedImport, level);
+            lisaa.run().map(|_| ())
+        };
+        (result, String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+    };
+    let (result, output, errors) = run(Level::Warn);
+    assert!(result.is_ok());
+    assert_eq!(output, "2\n");
+    let warnings = errors
+        .lines()
+        .filter(|l| l.starts_with("warning: "))
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        warnings,
+        vec![
+            "warning: unused import : half from geometry [unused-import]",
+            "warning: unused import : colors [unused-import]",
+            "warning: unused variable : b [unused-variable]",
+        ]
+    );
+    let (result, output, errors) = run(Level::Deny);
+    assert!(result.is_err());
+    assert_eq!(output, "");
+    assert!(errors.contains("error: unused import : colors [unused-import]"));
+    assert!(errors.contains("warning: unused variable : b [unused-variable]"));
 }
+
 #[test]
-fn test_nested_generics() {
-    test_for("nested_generics");
+fn test_emit_is_reproducible() {
+    use lisaa_lang::lisaa::Lisaa;
+    use std::fs;
+    let emit = |n: usize| {
+        let file = env::temp_dir().join(format!("lisaa_emit_{}_{}.lisaac", std::process::id(), n));
+        let file = file.to_string_lossy().into_owned();
+        let mut output = vec![];
+        Lisaa::new("tests/scripts/test_overloading.lisaa".to_string(), &mut output, false)
+            .emit(&file)
+            .expect("not an error");
+        let bytes = fs::read(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        bytes
+    };
+    let first = emit(0);
+    for n in 1..5 {
+        assert!(emit(n) == first, "the same program compiled differently");
+    }
 }